@@ -43,8 +43,7 @@ impl Refreshable for GoogleTokens {
 
         let claims: Claims = claims_from_response(&response).await.unwrap();
 
-        self.access_token =
-            create_jwt(claims.sub, claims.email, claims.exp, config.key.as_str()).unwrap();
+        self.access_token = create_jwt(&claims, config.key.as_str()).unwrap();
         self.refresh_token = String::from(new_refresh_token);
     }
 }
@@ -107,8 +106,7 @@ pub async fn google_callback(
     let config: Config = Config::init_from_env().unwrap();
     let claims: Claims = claims_from_response(&response).await.unwrap();
     let tokens: Tokens = Tokens {
-        access_token: create_jwt(claims.sub, claims.email, claims.exp, config.key.as_str())
-            .unwrap(),
+        access_token: create_jwt(&claims, config.key.as_str()).unwrap(),
         refresh_token: response.refresh_token().unwrap().to_string(),
     };
     println!("access_token={:?}", tokens);
@@ -127,9 +125,10 @@ pub async fn google_callback(
             .same_site(SameSite::Lax)
             .build(),
     );
+    // Provisions the User on their first sign-in, and otherwise just fetches it
     let client = reqwest::Client::new();
     let response = client
-        .get(config.user_api_endpoint)
+        .post(config.user_api_endpoint)
         .header(AUTHORIZATION, format!("Bearer {}", tokens.access_token))
         .header(CONTENT_TYPE, "application/json")
         .header(ACCEPT, "application/json")
@@ -137,7 +136,7 @@ pub async fn google_callback(
         .await
         .unwrap();
     match response.status() {
-        reqwest::StatusCode::OK => {
+        reqwest::StatusCode::OK | reqwest::StatusCode::CREATED => {
             // on success, parse our JSON to an APIResponse
             match response.json::<SingleApiResponse>().await {
                 Ok(parsed) => {
@@ -0,0 +1,62 @@
+// Guests are visitors without a Google account. They pick a name and get a
+// token for the one room they were asked into, which the room service only
+// honours while the room's owner allows guests.
+
+use chrono::Utc;
+use envconfig::Envconfig;
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::response::status::BadRequest;
+use rocket::serde::json::{json, Json, Value};
+use rocket::serde::uuid::Uuid;
+use serde::Deserialize;
+
+use super::{create_jwt, Claims, Config};
+
+/// Guest tokens can't be refreshed, so a guest still there after this long
+/// has to ask for another
+const GUEST_TOKEN_LIFETIME: i64 = 4 * 60 * 60; // 4 hours
+const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+
+#[derive(Deserialize, Debug)]
+pub struct GuestRequest {
+    display_name: String,
+}
+
+#[post("/auth/guest/<room_id>", format = "json", data = "<guest>")]
+pub fn guest_token(
+    room_id: Uuid,
+    guest: Json<GuestRequest>,
+) -> Result<Json<Value>, BadRequest<&'static str>> {
+    let display_name = guest.display_name.trim();
+    if display_name.is_empty()
+        || display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH
+        || display_name.chars().any(char::is_control)
+    {
+        return Err(BadRequest(
+            "display_name must be a name of at most 64 characters",
+        ));
+    }
+    let config: Config = Config::init_from_env().unwrap();
+    let exp = Utc::now().timestamp() + GUEST_TOKEN_LIFETIME;
+    // Every guest token is a new guest, as far as the room service can tell
+    let claims = Claims {
+        sub: Uuid::new_v4().to_string(),
+        email: String::new(),
+        exp,
+        given_name: Some(display_name.to_string()),
+        family_name: None,
+        room_id: Some(room_id.to_string()),
+    };
+    let access_token = create_jwt(&claims, config.key.as_str()).unwrap();
+    Ok(Json(json!({
+        "access_token": access_token,
+        "room_id": room_id,
+        "expires_at": exp,
+    })))
+}
+
+pub fn fairing() -> impl Fairing {
+    AdHoc::on_ignite("Guest access", |rocket| async {
+        rocket.mount("/", rocket::routes![guest_token])
+    })
+}
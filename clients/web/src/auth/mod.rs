@@ -13,6 +13,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 pub mod google;
+pub mod guest;
 
 #[derive(Envconfig)]
 pub struct Config {
@@ -39,6 +40,13 @@ pub struct Claims {
     pub sub: String,
     pub email: String,
     pub exp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+    /// Only set for guests, whose tokens are good for this one room
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_id: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -144,16 +152,10 @@ impl Claims {
     }
 }
 
-pub fn create_jwt(uid: String, email: String, exp: i64, secret: &str) -> Result<String, Error> {
-    let claims = Claims {
-        sub: uid.to_string(),
-        email: email.to_string(),
-        exp,
-    };
-
+pub fn create_jwt(claims: &Claims, secret: &str) -> Result<String, Error> {
     encode(
         &Header::default(),
-        &claims,
+        claims,
         &EncodingKey::from_secret(secret.as_ref()),
     )
 }
@@ -109,12 +109,13 @@ impl<'r> request::FromRequest<'r> for Account {
 #[get("/")]
 fn index(account: Account) -> String {
     format!(
-        "Hi, {} {} ({})! Your state is: {}, Email is: {}",
+        "Hi, {} {} ({})! Your state is: {}, Email is: {}. Rooms mention you as {}",
         account.user.given_name,
         account.user.family_name,
         account.user.id,
         account.state,
-        account.id
+        account.id,
+        account.user.mention()
     )
 }
 
@@ -128,4 +129,5 @@ fn rocket() -> _ {
     rocket::build()
         .mount("/", routes![index, index_anonymous])
         .attach(auth::google::fairing())
+        .attach(auth::guest::fairing())
 }
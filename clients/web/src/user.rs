@@ -17,3 +17,11 @@ pub struct User {
     pub family_name: String,
     pub groups: Vec<Uuid>,
 }
+
+impl User {
+    /// How messages in rooms mention this user. Names aren't unique, so rooms
+    /// only know mentions by id.
+    pub fn mention(&self) -> String {
+        format!("<@{}>", self.id)
+    }
+}
@@ -0,0 +1,316 @@
+//! Authentication shared by the Yonder services.
+//!
+//! Services identify callers by the bearer token in the `Authorization`
+//! header, a JWT signed with `YONDER_JWT_SECRET` (see the web client's
+//! `create_jwt`). Add `Claims` to a handler's arguments to require one.
+//!
+//! Guests without an account get a token for a single room instead, marked
+//! by its `room_id`. The `Claims` guard turns these away; a service that lets
+//! guests in reads the header with `Claims::from_authorization` itself.
+//!
+//! A service asking another for something no user may see sends a short-lived
+//! `service_token` of its own. Add `ServiceClaims` to a handler's arguments to
+//! only answer other services.
+
+use envconfig::Envconfig;
+use jsonwebtoken::{
+    decode, encode, errors::Error, errors::ErrorKind, DecodingKey, EncodingKey, Header, Validation,
+};
+use rocket::http::Status;
+use rocket::request;
+use rocket::request::Outcome;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const BEARER: &str = "Bearer ";
+pub const AUTHORIZATION: &str = "Authorization";
+/// Service tokens are made for each request, so they needn't last
+const SERVICE_TOKEN_LIFETIME: i64 = 60;
+
+#[derive(Deserialize, Debug)]
+pub struct Claims {
+    pub sub: String,
+    pub email: String,
+    pub exp: i64,
+    #[serde(default)]
+    pub given_name: Option<String>,
+    #[serde(default)]
+    pub family_name: Option<String>,
+    /// The one room a guest token is good for
+    #[serde(default)]
+    pub room_id: Option<String>,
+}
+
+/// The claims of a token one service sends another. Users' tokens have no
+/// `service`, so they never pass for one.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ServiceClaims {
+    /// The name of the service asking
+    pub service: String,
+    pub exp: i64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthenticationError {
+    Missing,
+    Decoding(String),
+    Expired,
+    /// A guest token where only those of users with an account will do
+    Guest,
+}
+
+#[derive(Envconfig)]
+struct Config {
+    #[envconfig(from = "YONDER_JWT_SECRET")]
+    key: String,
+}
+
+/// Sign a token with `YONDER_JWT_SECRET`. Besides access tokens, services
+/// use this for their own short-lived tokens, such as room invites.
+pub fn encode_token<T: Serialize>(claims: &T) -> Result<String, Error> {
+    let config = Config::init_from_env().unwrap();
+    encode(
+        &Header::default(),
+        claims,
+        &EncodingKey::from_secret(config.key.as_ref()),
+    )
+}
+
+/// Verify a token signed with `YONDER_JWT_SECRET` and decode its claims. The
+/// claims must include an `exp`.
+pub fn decode_token<T: DeserializeOwned>(token: &str) -> Result<T, AuthenticationError> {
+    let config = Config::init_from_env().unwrap();
+    let decoded_token = decode::<T>(
+        token,
+        &DecodingKey::from_secret(config.key.as_ref()),
+        &Validation::default(),
+    )
+    .map_err(|e| match e.kind() {
+        ErrorKind::ExpiredSignature => AuthenticationError::Expired,
+        _ => AuthenticationError::Decoding(e.to_string()),
+    })?;
+
+    Ok(decoded_token.claims)
+}
+
+/// Make a token for `service` to send another service, as a `Bearer` value
+pub fn service_token(service: &str) -> Result<String, Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64);
+    let claims = ServiceClaims {
+        service: service.to_string(),
+        exp: now + SERVICE_TOKEN_LIFETIME,
+    };
+    Ok(format!("{}{}", BEARER, encode_token(&claims)?))
+}
+
+impl Claims {
+    /// Create a `Claims` from a 'Bearer <token>' value
+    pub fn from_authorization(value: &str) -> Result<Self, AuthenticationError> {
+        match value.strip_prefix(BEARER).map(str::trim) {
+            Some(access_token) => decode_token(access_token),
+            None => Err(AuthenticationError::Missing),
+        }
+    }
+
+    pub fn is_guest(&self) -> bool {
+        self.room_id.is_some()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> request::FromRequest<'r> for Claims {
+    type Error = AuthenticationError;
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one(AUTHORIZATION) {
+            None => Outcome::Error((Status::Forbidden, AuthenticationError::Missing)),
+            Some(value) => match Claims::from_authorization(value) {
+                Err(e) => Outcome::Error((Status::Forbidden, e)),
+                Ok(claims) if claims.is_guest() => {
+                    Outcome::Error((Status::Forbidden, AuthenticationError::Guest))
+                }
+                Ok(claims) => Outcome::Success(claims),
+            },
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> request::FromRequest<'r> for ServiceClaims {
+    type Error = AuthenticationError;
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> Outcome<Self, Self::Error> {
+        let value = request.headers().get_one(AUTHORIZATION);
+        match value.and_then(|value| value.strip_prefix(BEARER)) {
+            None => Outcome::Error((Status::Forbidden, AuthenticationError::Missing)),
+            Some(token) => match decode_token(token.trim()) {
+                Ok(claims) => Outcome::Success(claims),
+                Err(e) => Outcome::Error((Status::Forbidden, e)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Header as HttpHeader;
+    use rocket::local::blocking::Client;
+    use std::env;
+
+    const SECRET: &str = "test secret";
+
+    fn now() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+    }
+
+    const USER_ID: &str = "2d2cd7a3-5a5e-4a67-9d07-3b4c1f0c0b8e";
+
+    /// What the web client puts in a token
+    #[derive(Serialize)]
+    struct TokenClaims {
+        sub: &'static str,
+        email: &'static str,
+        exp: i64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        given_name: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        room_id: Option<&'static str>,
+    }
+
+    /// A user's claims, expiring `expires_in` seconds from now
+    fn user_claims(expires_in: i64) -> TokenClaims {
+        TokenClaims {
+            sub: USER_ID,
+            email: "ada@example.com",
+            exp: now() + expires_in,
+            given_name: None,
+            room_id: None,
+        }
+    }
+
+    fn bearer(claims: &impl Serialize) -> String {
+        env::set_var("YONDER_JWT_SECRET", SECRET);
+        format!("{}{}", BEARER, encode_token(claims).unwrap())
+    }
+
+    #[rocket::get("/user")]
+    fn user(claims: Claims) -> String {
+        claims.sub
+    }
+
+    #[rocket::get("/service")]
+    fn service(claims: ServiceClaims) -> String {
+        claims.service
+    }
+
+    fn client() -> Client {
+        env::set_var("YONDER_JWT_SECRET", SECRET);
+        let rocket = rocket::build().mount("/", rocket::routes![user, service]);
+        Client::tracked(rocket).unwrap()
+    }
+
+    /// The status of a request for `uri` with `authorization`, and its body
+    fn call(client: &Client, uri: &str, authorization: String) -> (Status, Option<String>) {
+        let response = client
+            .get(uri)
+            .header(HttpHeader::new(AUTHORIZATION, authorization))
+            .dispatch();
+        (response.status(), response.into_string())
+    }
+
+    #[test]
+    fn tokens_round_trip() {
+        let claims = Claims::from_authorization(&bearer(&user_claims(60))).unwrap();
+        assert_eq!(claims.sub, USER_ID);
+        assert_eq!(claims.email, "ada@example.com");
+        assert!(!claims.is_guest());
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        // Well past the leeway allowed for clocks being out
+        let expired = bearer(&user_claims(-60 * 60));
+        assert_eq!(
+            Claims::from_authorization(&expired).unwrap_err(),
+            AuthenticationError::Expired
+        );
+    }
+
+    #[test]
+    fn tokens_signed_with_another_secret_are_rejected() {
+        env::set_var("YONDER_JWT_SECRET", SECRET);
+        let forged = encode(
+            &Header::default(),
+            &user_claims(60),
+            &EncodingKey::from_secret(b"another secret"),
+        )
+        .unwrap();
+        let forged = format!("{}{}", BEARER, forged);
+        assert!(matches!(
+            Claims::from_authorization(&forged),
+            Err(AuthenticationError::Decoding(_))
+        ));
+
+        // The claims changed after signing
+        let signed = bearer(&user_claims(60));
+        let mut parts: Vec<&str> = signed.split('.').collect();
+        let tampered = bearer(&user_claims(120));
+        parts[1] = tampered.split('.').nth(1).unwrap();
+        assert!(matches!(
+            Claims::from_authorization(&parts.join(".")),
+            Err(AuthenticationError::Decoding(_))
+        ));
+    }
+
+    #[test]
+    fn authorization_must_be_a_bearer_token() {
+        let token = bearer(&user_claims(60));
+        let basic = token.replacen(BEARER, "Basic ", 1);
+        assert_eq!(
+            Claims::from_authorization(&basic).unwrap_err(),
+            AuthenticationError::Missing
+        );
+    }
+
+    #[test]
+    fn guests_are_turned_away_where_an_account_is_needed() {
+        let client = client();
+        let guest = bearer(&TokenClaims {
+            given_name: Some("Visitor"),
+            room_id: Some("8f6b2a90-3c1e-4f7a-b0d2-6e5c4a3b2c1d"),
+            ..user_claims(60)
+        });
+        assert!(Claims::from_authorization(&guest).unwrap().is_guest());
+        assert_eq!(call(&client, "/user", guest).0, Status::Forbidden);
+
+        let (status, sub) = call(&client, "/user", bearer(&user_claims(60)));
+        assert_eq!(status, Status::Ok);
+        assert_eq!(sub.as_deref(), Some(USER_ID));
+    }
+
+    #[test]
+    fn only_services_pass_as_services() {
+        let client = client();
+        let (status, _) = call(&client, "/service", bearer(&user_claims(60)));
+        assert_eq!(status, Status::Forbidden);
+        let (status, _) = call(&client, "/user", service_token("room").unwrap());
+        assert_eq!(status, Status::Forbidden);
+
+        let (status, service) = call(&client, "/service", service_token("room").unwrap());
+        assert_eq!(status, Status::Ok);
+        assert_eq!(service.as_deref(), Some("room"));
+        let expired = bearer(&ServiceClaims {
+            service: String::from("room"),
+            exp: now() - 60 * 60,
+        });
+        assert_eq!(call(&client, "/service", expired).0, Status::Forbidden);
+    }
+}
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS "Room";
+DROP TYPE IF EXISTS room_visibility;
@@ -0,0 +1,27 @@
+BEGIN;
+
+
+CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
+
+CREATE TYPE public.room_visibility AS ENUM ('public', 'private');
+
+CREATE TABLE IF NOT EXISTS public."Room"
+(
+    id uuid NOT NULL DEFAULT uuid_generate_v4(),
+    name text COLLATE pg_catalog."default" NOT NULL,
+    description text COLLATE pg_catalog."default" NOT NULL DEFAULT '',
+    owner_id uuid NOT NULL,
+    visibility public.room_visibility NOT NULL DEFAULT 'public',
+    archived_at timestamp without time zone,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    updated_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "Room_pkey" PRIMARY KEY (id)
+);
+
+COMMENT ON TABLE public."Room"
+    IS 'A place where Yonder users gather. owner_id refers to a User in the user service.';
+
+CREATE INDEX IF NOT EXISTS "Room_owner_id_idx"
+    ON public."Room" (owner_id);
+
+END;
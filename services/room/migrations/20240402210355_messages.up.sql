@@ -0,0 +1,27 @@
+BEGIN;
+
+
+CREATE TABLE IF NOT EXISTS public."Message"
+(
+    id uuid NOT NULL DEFAULT uuid_generate_v4(),
+    room_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    body text COLLATE pg_catalog."default" NOT NULL,
+    created_at timestamp without time zone NOT NULL DEFAULT clock_timestamp(),
+    CONSTRAINT "Message_pkey" PRIMARY KEY (id)
+);
+
+COMMENT ON TABLE public."Message"
+    IS 'Messages sent in a Room. user_id refers to a User in the user service.';
+
+ALTER TABLE IF EXISTS public."Message"
+    ADD CONSTRAINT "Message_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+-- History is read newest first, a page at a time
+CREATE INDEX IF NOT EXISTS "Message_room_id_created_at_idx"
+    ON public."Message" (room_id, created_at DESC, id DESC);
+
+END;
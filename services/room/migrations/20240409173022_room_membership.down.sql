@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS "RoomGroupGrant";
+DROP TABLE IF EXISTS "RoomMember";
+DROP TYPE IF EXISTS room_role;
@@ -0,0 +1,55 @@
+BEGIN;
+
+
+CREATE TYPE public.room_role AS ENUM ('guest', 'member', 'moderator', 'owner');
+
+CREATE TABLE IF NOT EXISTS public."RoomMember"
+(
+    room_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    role public.room_role NOT NULL DEFAULT 'member',
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    updated_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "RoomMember_pkey" PRIMARY KEY (room_id, user_id)
+);
+
+COMMENT ON TABLE public."RoomMember"
+    IS 'The Role a User has in a Room. user_id refers to a User in the user service.';
+
+CREATE TABLE IF NOT EXISTS public."RoomGroupGrant"
+(
+    room_id uuid NOT NULL,
+    group_id uuid NOT NULL,
+    role public.room_role NOT NULL DEFAULT 'member',
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    updated_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "RoomGroupGrant_pkey" PRIMARY KEY (room_id, group_id),
+    CONSTRAINT "RoomGroupGrant_role_check" CHECK (role <> 'owner')
+);
+
+COMMENT ON TABLE public."RoomGroupGrant"
+    IS 'Gives every User in a Group a Role in a Room. group_id refers to a UserGroup in the user service.';
+
+ALTER TABLE IF EXISTS public."RoomMember"
+    ADD CONSTRAINT "RoomMember_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+ALTER TABLE IF EXISTS public."RoomGroupGrant"
+    ADD CONSTRAINT "RoomGroupGrant_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS "RoomMember_user_id_idx"
+    ON public."RoomMember" (user_id);
+
+CREATE INDEX IF NOT EXISTS "RoomGroupGrant_group_id_idx"
+    ON public."RoomGroupGrant" (group_id);
+
+INSERT INTO public."RoomMember" (room_id, user_id, role)
+    SELECT id, owner_id, 'owner' FROM public."Room"
+    ON CONFLICT DO NOTHING;
+
+END;
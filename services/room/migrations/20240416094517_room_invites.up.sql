@@ -0,0 +1,32 @@
+BEGIN;
+
+
+CREATE TABLE IF NOT EXISTS public."RoomInvite"
+(
+    id uuid NOT NULL DEFAULT uuid_generate_v4(),
+    room_id uuid NOT NULL,
+    created_by uuid NOT NULL,
+    role public.room_role NOT NULL DEFAULT 'member',
+    max_uses integer NOT NULL DEFAULT 1,
+    uses integer NOT NULL DEFAULT 0,
+    expires_at timestamp without time zone NOT NULL,
+    revoked_at timestamp without time zone,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "RoomInvite_pkey" PRIMARY KEY (id),
+    CONSTRAINT "RoomInvite_role_check" CHECK (role <> 'owner'),
+    CONSTRAINT "RoomInvite_uses_check" CHECK (uses >= 0 AND uses <= max_uses)
+);
+
+COMMENT ON TABLE public."RoomInvite"
+    IS 'An invitation to join a Room, redeemed with a signed token. created_by refers to a User in the user service.';
+
+ALTER TABLE IF EXISTS public."RoomInvite"
+    ADD CONSTRAINT "RoomInvite_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS "RoomInvite_room_id_idx"
+    ON public."RoomInvite" (room_id);
+
+END;
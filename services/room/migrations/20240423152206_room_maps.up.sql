@@ -0,0 +1,28 @@
+BEGIN;
+
+
+CREATE TABLE IF NOT EXISTS public."RoomMap"
+(
+    room_id uuid NOT NULL,
+    width double precision NOT NULL,
+    height double precision NOT NULL,
+    earshot double precision NOT NULL DEFAULT 150,
+    walls jsonb NOT NULL DEFAULT '[]',
+    zones jsonb NOT NULL DEFAULT '[]',
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    updated_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "RoomMap_pkey" PRIMARY KEY (room_id),
+    CONSTRAINT "RoomMap_size_check" CHECK (width > 0 AND height > 0),
+    CONSTRAINT "RoomMap_earshot_check" CHECK (earshot > 0)
+);
+
+COMMENT ON TABLE public."RoomMap"
+    IS 'The floor plan of a Room that is laid out as a 2D space. walls is a list of line segments that block sound, zones a list of named rectangles whose occupants only hear each other.';
+
+ALTER TABLE IF EXISTS public."RoomMap"
+    ADD CONSTRAINT "RoomMap_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+END;
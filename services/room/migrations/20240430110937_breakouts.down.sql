@@ -0,0 +1,12 @@
+BEGIN;
+
+
+DROP TABLE IF EXISTS "BreakoutAssignment";
+
+DELETE FROM public."Room" WHERE parent_id IS NOT NULL;
+
+ALTER TABLE IF EXISTS public."Room"
+    DROP COLUMN IF EXISTS parent_id,
+    DROP COLUMN IF EXISTS closes_at;
+
+END;
@@ -0,0 +1,38 @@
+BEGIN;
+
+
+ALTER TABLE IF EXISTS public."Room"
+    ADD COLUMN parent_id uuid,
+    ADD COLUMN closes_at timestamp without time zone,
+    ADD CONSTRAINT "Room_breakout_check" CHECK ((parent_id IS NULL) = (closes_at IS NULL));
+
+COMMENT ON COLUMN public."Room".parent_id
+    IS 'Set on breakout Rooms, which take their Roles from the parent and close at closes_at.';
+
+ALTER TABLE IF EXISTS public."Room"
+    ADD CONSTRAINT "Room_parent_fkey" FOREIGN KEY (parent_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS "Room_parent_id_idx"
+    ON public."Room" (parent_id);
+
+CREATE TABLE IF NOT EXISTS public."BreakoutAssignment"
+(
+    room_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "BreakoutAssignment_pkey" PRIMARY KEY (room_id, user_id)
+);
+
+COMMENT ON TABLE public."BreakoutAssignment"
+    IS 'Who has been sent to a breakout Room. user_id refers to a User in the user service.';
+
+ALTER TABLE IF EXISTS public."BreakoutAssignment"
+    ADD CONSTRAINT "BreakoutAssignment_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+END;
@@ -0,0 +1,18 @@
+BEGIN;
+
+
+CREATE TABLE IF NOT EXISTS public."RoomUpdate"
+(
+    id bigserial NOT NULL,
+    payload text COLLATE pg_catalog."default" NOT NULL,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "RoomUpdate_pkey" PRIMARY KEY (id)
+);
+
+COMMENT ON TABLE public."RoomUpdate"
+    IS 'Realtime updates too large to send between instances of the room service with NOTIFY. Only kept briefly.';
+
+CREATE INDEX IF NOT EXISTS "RoomUpdate_created_at_idx"
+    ON public."RoomUpdate" (created_at);
+
+END;
@@ -0,0 +1,18 @@
+BEGIN;
+
+
+DROP TABLE IF EXISTS "ModerationAction";
+DROP TYPE IF EXISTS moderation_action;
+DROP TABLE IF EXISTS "RoomMute";
+DROP TABLE IF EXISTS "RoomBan";
+
+DROP INDEX IF EXISTS "Message_room_id_user_id_created_at_idx";
+
+ALTER TABLE IF EXISTS public."Message"
+    DROP COLUMN IF EXISTS deleted_at,
+    DROP COLUMN IF EXISTS deleted_by;
+
+ALTER TABLE IF EXISTS public."Room"
+    DROP COLUMN IF EXISTS slow_mode;
+
+END;
@@ -0,0 +1,89 @@
+BEGIN;
+
+
+ALTER TABLE IF EXISTS public."Room"
+    ADD COLUMN slow_mode integer NOT NULL DEFAULT 0,
+    ADD CONSTRAINT "Room_slow_mode_check" CHECK (slow_mode >= 0);
+
+COMMENT ON COLUMN public."Room".slow_mode
+    IS 'How many seconds members must wait between messages, or 0 to let them post freely.';
+
+ALTER TABLE IF EXISTS public."Message"
+    ADD COLUMN deleted_at timestamp without time zone,
+    ADD COLUMN deleted_by uuid;
+
+-- Slow mode looks up each user's latest message
+CREATE INDEX IF NOT EXISTS "Message_room_id_user_id_created_at_idx"
+    ON public."Message" (room_id, user_id, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS public."RoomBan"
+(
+    room_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    created_by uuid NOT NULL,
+    reason text COLLATE pg_catalog."default",
+    expires_at timestamp without time zone,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "RoomBan_pkey" PRIMARY KEY (room_id, user_id)
+);
+
+COMMENT ON TABLE public."RoomBan"
+    IS 'Keeps a User out of a Room until expires_at, or for good if it is null. user_id and created_by refer to Users in the user service.';
+
+ALTER TABLE IF EXISTS public."RoomBan"
+    ADD CONSTRAINT "RoomBan_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+CREATE TABLE IF NOT EXISTS public."RoomMute"
+(
+    room_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    created_by uuid NOT NULL,
+    reason text COLLATE pg_catalog."default",
+    expires_at timestamp without time zone,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "RoomMute_pkey" PRIMARY KEY (room_id, user_id)
+);
+
+COMMENT ON TABLE public."RoomMute"
+    IS 'Stops a User posting or publishing media in a Room until expires_at, or for good if it is null. user_id and created_by refer to Users in the user service.';
+
+ALTER TABLE IF EXISTS public."RoomMute"
+    ADD CONSTRAINT "RoomMute_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+CREATE TYPE public.moderation_action AS ENUM
+    ('mute', 'unmute', 'kick', 'ban', 'unban', 'delete_message', 'slow_mode');
+
+CREATE TABLE IF NOT EXISTS public."ModerationAction"
+(
+    id uuid NOT NULL DEFAULT uuid_generate_v4(),
+    room_id uuid NOT NULL,
+    moderator_id uuid NOT NULL,
+    action public.moderation_action NOT NULL,
+    user_id uuid,
+    message_id uuid,
+    reason text COLLATE pg_catalog."default",
+    expires_at timestamp without time zone,
+    slow_mode integer,
+    created_at timestamp without time zone NOT NULL DEFAULT clock_timestamp(),
+    CONSTRAINT "ModerationAction_pkey" PRIMARY KEY (id)
+);
+
+COMMENT ON TABLE public."ModerationAction"
+    IS 'A log of what moderators have done in a Room. moderator_id and user_id refer to Users in the user service.';
+
+ALTER TABLE IF EXISTS public."ModerationAction"
+    ADD CONSTRAINT "ModerationAction_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS "ModerationAction_room_id_created_at_idx"
+    ON public."ModerationAction" (room_id, created_at DESC, id DESC);
+
+END;
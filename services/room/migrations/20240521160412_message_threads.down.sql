@@ -0,0 +1,14 @@
+BEGIN;
+
+
+DROP VIEW IF EXISTS "MessageView";
+DROP TABLE IF EXISTS "MessageReaction";
+DROP TABLE IF EXISTS "MessageEdit";
+
+DELETE FROM public."Message" WHERE thread_id IS NOT NULL;
+
+ALTER TABLE IF EXISTS public."Message"
+    DROP COLUMN IF EXISTS thread_id,
+    DROP COLUMN IF EXISTS edited_at;
+
+END;
@@ -0,0 +1,89 @@
+BEGIN;
+
+
+ALTER TABLE IF EXISTS public."Message"
+    ADD COLUMN thread_id uuid,
+    ADD COLUMN edited_at timestamp without time zone;
+
+COMMENT ON COLUMN public."Message".thread_id
+    IS 'The first Message of the thread this is a reply in.';
+
+ALTER TABLE IF EXISTS public."Message"
+    ADD CONSTRAINT "Message_thread_fkey" FOREIGN KEY (thread_id)
+    REFERENCES public."Message" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+-- Threads are read a page at a time like the rest of the history
+CREATE INDEX IF NOT EXISTS "Message_thread_id_created_at_idx"
+    ON public."Message" (thread_id, created_at DESC, id DESC);
+
+CREATE TABLE IF NOT EXISTS public."MessageEdit"
+(
+    id uuid NOT NULL DEFAULT uuid_generate_v4(),
+    message_id uuid NOT NULL,
+    body text COLLATE pg_catalog."default" NOT NULL,
+    created_at timestamp without time zone NOT NULL DEFAULT clock_timestamp(),
+    CONSTRAINT "MessageEdit_pkey" PRIMARY KEY (id)
+);
+
+COMMENT ON TABLE public."MessageEdit"
+    IS 'What a Message said before each time it was edited. created_at is when it was replaced.';
+
+ALTER TABLE IF EXISTS public."MessageEdit"
+    ADD CONSTRAINT "MessageEdit_Message_fkey" FOREIGN KEY (message_id)
+    REFERENCES public."Message" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS "MessageEdit_message_id_idx"
+    ON public."MessageEdit" (message_id, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS public."MessageReaction"
+(
+    message_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    emoji text COLLATE pg_catalog."default" NOT NULL,
+    created_at timestamp without time zone NOT NULL DEFAULT clock_timestamp(),
+    CONSTRAINT "MessageReaction_pkey" PRIMARY KEY (message_id, user_id, emoji)
+);
+
+COMMENT ON TABLE public."MessageReaction"
+    IS 'A User reacting to a Message with an emoji. user_id refers to a User in the user service.';
+
+ALTER TABLE IF EXISTS public."MessageReaction"
+    ADD CONSTRAINT "MessageReaction_Message_fkey" FOREIGN KEY (message_id)
+    REFERENCES public."Message" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+-- Messages as they are shown: deleted ones keep their place in the history
+-- but not what they said, and each comes with its reply count and reactions
+CREATE OR REPLACE VIEW public."MessageView" AS
+SELECT
+    m.id,
+    m.room_id,
+    m.user_id,
+    CASE WHEN m.deleted_at IS NULL THEN m.body ELSE '' END AS body,
+    m.thread_id,
+    (
+        SELECT COUNT(*) FROM public."Message" r
+        WHERE r.thread_id = m.id AND r.deleted_at IS NULL
+    ) AS reply_count,
+    (
+        SELECT COALESCE(jsonb_agg(jsonb_build_object(
+            'emoji', r.emoji, 'count', r.count, 'user_ids', r.user_ids
+        ) ORDER BY r.first_at, r.emoji), '[]'::jsonb)
+        FROM (
+            SELECT emoji, COUNT(*) AS count, array_agg(user_id ORDER BY created_at) AS user_ids,
+                MIN(created_at) AS first_at
+            FROM public."MessageReaction" WHERE message_id = m.id
+            GROUP BY emoji
+        ) r
+    ) AS reactions,
+    m.edited_at,
+    m.deleted_at,
+    m.created_at
+FROM public."Message" m;
+
+END;
@@ -0,0 +1,40 @@
+BEGIN;
+
+
+DROP VIEW IF EXISTS public."MessageView";
+
+-- Messages as they are shown: deleted ones keep their place in the history
+-- but not what they said, and each comes with its reply count and reactions
+CREATE OR REPLACE VIEW public."MessageView" AS
+SELECT
+    m.id,
+    m.room_id,
+    m.user_id,
+    CASE WHEN m.deleted_at IS NULL THEN m.body ELSE '' END AS body,
+    m.thread_id,
+    (
+        SELECT COUNT(*) FROM public."Message" r
+        WHERE r.thread_id = m.id AND r.deleted_at IS NULL
+    ) AS reply_count,
+    (
+        SELECT COALESCE(jsonb_agg(jsonb_build_object(
+            'emoji', r.emoji, 'count', r.count, 'user_ids', r.user_ids
+        ) ORDER BY r.first_at, r.emoji), '[]'::jsonb)
+        FROM (
+            SELECT emoji, COUNT(*) AS count, array_agg(user_id ORDER BY created_at) AS user_ids,
+                MIN(created_at) AS first_at
+            FROM public."MessageReaction" WHERE message_id = m.id
+            GROUP BY emoji
+        ) r
+    ) AS reactions,
+    m.edited_at,
+    m.deleted_at,
+    m.created_at
+FROM public."Message" m;
+
+DROP TABLE IF EXISTS public."MessageMention";
+
+ALTER TABLE IF EXISTS public."Message"
+    DROP COLUMN IF EXISTS mentions;
+
+END;
@@ -0,0 +1,80 @@
+BEGIN;
+
+
+CREATE TABLE IF NOT EXISTS public."Attachment"
+(
+    id uuid NOT NULL DEFAULT uuid_generate_v4(),
+    room_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    message_id uuid,
+    name text COLLATE pg_catalog."default" NOT NULL,
+    content_type text COLLATE pg_catalog."default" NOT NULL,
+    size bigint NOT NULL,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "Attachment_pkey" PRIMARY KEY (id)
+);
+
+COMMENT ON TABLE public."Attachment"
+    IS 'A file uploaded to a Room, which is kept by the storage backend under its id. user_id refers to the User in the user service who uploaded it.';
+
+COMMENT ON COLUMN public."Attachment".message_id
+    IS 'The Message the file was attached to, if it has been sent yet.';
+
+ALTER TABLE IF EXISTS public."Attachment"
+    ADD CONSTRAINT "Attachment_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+ALTER TABLE IF EXISTS public."Attachment"
+    ADD CONSTRAINT "Attachment_Message_fkey" FOREIGN KEY (message_id)
+    REFERENCES public."Message" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS "Attachment_room_id_created_at_idx"
+    ON public."Attachment" (room_id, created_at DESC, id DESC);
+
+CREATE INDEX IF NOT EXISTS "Attachment_message_id_idx"
+    ON public."Attachment" (message_id);
+
+DROP VIEW IF EXISTS public."MessageView";
+
+-- Messages as they are shown: deleted ones keep their place in the history
+-- but not what they said, who they mentioned or what was attached, and each comes with its reply count, reactions and attachments
+CREATE OR REPLACE VIEW public."MessageView" AS
+SELECT
+    m.id,
+    m.room_id,
+    m.user_id,
+    CASE WHEN m.deleted_at IS NULL THEN m.body ELSE '' END AS body,
+    m.thread_id,
+    (
+        SELECT COUNT(*) FROM public."Message" r
+        WHERE r.thread_id = m.id AND r.deleted_at IS NULL
+    ) AS reply_count,
+    (
+        SELECT COALESCE(jsonb_agg(jsonb_build_object(
+            'emoji', r.emoji, 'count', r.count, 'user_ids', r.user_ids
+        ) ORDER BY r.first_at, r.emoji), '[]'::jsonb)
+        FROM (
+            SELECT emoji, COUNT(*) AS count, array_agg(user_id ORDER BY created_at) AS user_ids,
+                MIN(created_at) AS first_at
+            FROM public."MessageReaction" WHERE message_id = m.id
+            GROUP BY emoji
+        ) r
+    ) AS reactions,
+    CASE WHEN m.deleted_at IS NULL THEN m.mentions ELSE '{}' END AS mentions,
+    (
+        SELECT COALESCE(jsonb_agg(jsonb_build_object(
+            'id', a.id, 'name', a.name, 'content_type', a.content_type, 'size', a.size
+        ) ORDER BY a.created_at, a.id), '[]'::jsonb)
+        FROM public."Attachment" a
+        WHERE a.message_id = m.id AND m.deleted_at IS NULL
+    ) AS attachments,
+    m.edited_at,
+    m.deleted_at,
+    m.created_at
+FROM public."Message" m;
+
+END;
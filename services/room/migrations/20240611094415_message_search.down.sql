@@ -0,0 +1,9 @@
+BEGIN;
+
+
+DROP INDEX IF EXISTS "Message_search_idx";
+
+ALTER TABLE IF EXISTS public."Message"
+    DROP COLUMN IF EXISTS search;
+
+END;
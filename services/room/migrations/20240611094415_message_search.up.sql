@@ -0,0 +1,13 @@
+BEGIN;
+
+
+ALTER TABLE IF EXISTS public."Message"
+    ADD COLUMN search tsvector GENERATED ALWAYS AS (to_tsvector('english', body)) STORED;
+
+COMMENT ON COLUMN public."Message".search
+    IS 'The words in the Message body, for full-text search.';
+
+CREATE INDEX IF NOT EXISTS "Message_search_idx"
+    ON public."Message" USING GIN (search);
+
+END;
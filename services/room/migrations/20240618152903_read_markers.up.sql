@@ -0,0 +1,31 @@
+BEGIN;
+
+
+CREATE TABLE IF NOT EXISTS public."RoomReadMarker"
+(
+    room_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    message_id uuid NOT NULL,
+    read_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "RoomReadMarker_pkey" PRIMARY KEY (room_id, user_id)
+);
+
+COMMENT ON TABLE public."RoomReadMarker"
+    IS 'The last Message a User has read in a Room. Everything sent after it is unread. user_id refers to a User in the user service.';
+
+ALTER TABLE IF EXISTS public."RoomReadMarker"
+    ADD CONSTRAINT "RoomReadMarker_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+ALTER TABLE IF EXISTS public."RoomReadMarker"
+    ADD CONSTRAINT "RoomReadMarker_Message_fkey" FOREIGN KEY (message_id)
+    REFERENCES public."Message" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS "RoomReadMarker_user_id_idx"
+    ON public."RoomReadMarker" (user_id);
+
+END;
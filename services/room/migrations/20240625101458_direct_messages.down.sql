@@ -0,0 +1,10 @@
+BEGIN;
+
+
+DELETE FROM public."Room" WHERE direct_key IS NOT NULL;
+
+ALTER TABLE IF EXISTS public."Room"
+    DROP CONSTRAINT IF EXISTS "Room_direct_key_unique",
+    DROP COLUMN IF EXISTS direct_key;
+
+END;
@@ -0,0 +1,13 @@
+BEGIN;
+
+
+ALTER TABLE IF EXISTS public."Room"
+    ADD COLUMN direct_key text COLLATE pg_catalog."default";
+
+COMMENT ON COLUMN public."Room".direct_key
+    IS 'For a direct conversation, the ids of the Users in it, sorted and joined by commas. Null for every other Room.';
+
+ALTER TABLE IF EXISTS public."Room"
+    ADD CONSTRAINT "Room_direct_key_unique" UNIQUE (direct_key);
+
+END;
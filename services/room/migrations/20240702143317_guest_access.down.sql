@@ -0,0 +1,7 @@
+BEGIN;
+
+
+ALTER TABLE IF EXISTS public."Room"
+    DROP COLUMN IF EXISTS guest_access;
+
+END;
@@ -0,0 +1,10 @@
+BEGIN;
+
+
+ALTER TABLE IF EXISTS public."Room"
+    ADD COLUMN guest_access boolean NOT NULL DEFAULT false;
+
+COMMENT ON COLUMN public."Room".guest_access
+    IS 'Whether visitors without an account may view and join the Room with a guest token.';
+
+END;
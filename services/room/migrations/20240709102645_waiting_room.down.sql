@@ -0,0 +1,12 @@
+BEGIN;
+
+
+DROP TABLE IF EXISTS "RoomKnock";
+DROP TYPE IF EXISTS knock_state;
+
+ALTER TABLE IF EXISTS public."Room"
+    DROP CONSTRAINT IF EXISTS "Room_capacity_check",
+    DROP COLUMN IF EXISTS capacity,
+    DROP COLUMN IF EXISTS lobby;
+
+END;
@@ -0,0 +1,46 @@
+BEGIN;
+
+
+ALTER TABLE IF EXISTS public."Room"
+    ADD COLUMN capacity integer,
+    ADD COLUMN lobby boolean NOT NULL DEFAULT false,
+    ADD CONSTRAINT "Room_capacity_check" CHECK (capacity > 0);
+
+COMMENT ON COLUMN public."Room".capacity
+    IS 'How many Users may be connected to the Room at once before others have to wait in its lobby, or null for no limit.';
+
+COMMENT ON COLUMN public."Room".lobby
+    IS 'Whether everyone who can''t moderate the Room waits in its lobby until a moderator admits them.';
+
+CREATE TYPE public.knock_state AS ENUM ('waiting', 'admitted', 'denied', 'cancelled');
+
+CREATE TABLE IF NOT EXISTS public."RoomKnock"
+(
+    id uuid NOT NULL DEFAULT uuid_generate_v4(),
+    room_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    guest_name text COLLATE pg_catalog."default",
+    state public.knock_state NOT NULL DEFAULT 'waiting',
+    answered_by uuid,
+    answered_at timestamp without time zone,
+    created_at timestamp without time zone NOT NULL DEFAULT clock_timestamp(),
+    CONSTRAINT "RoomKnock_pkey" PRIMARY KEY (id)
+);
+
+COMMENT ON TABLE public."RoomKnock"
+    IS 'Someone asking to be let into a Room that is locked or full, and what a moderator said. user_id and answered_by refer to Users in the user service, except that user_id may be a guest.';
+
+ALTER TABLE IF EXISTS public."RoomKnock"
+    ADD CONSTRAINT "RoomKnock_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+-- Nobody waits in the same lobby twice at once
+CREATE UNIQUE INDEX IF NOT EXISTS "RoomKnock_room_id_user_id_waiting_idx"
+    ON public."RoomKnock" (room_id, user_id) WHERE state = 'waiting';
+
+CREATE INDEX IF NOT EXISTS "RoomKnock_room_id_user_id_answered_at_idx"
+    ON public."RoomKnock" (room_id, user_id, answered_at DESC);
+
+END;
@@ -0,0 +1,7 @@
+BEGIN;
+
+
+DROP TABLE IF EXISTS "CalendarFeed";
+DROP TABLE IF EXISTS "ScheduledSession";
+
+END;
@@ -0,0 +1,61 @@
+BEGIN;
+
+
+CREATE TABLE IF NOT EXISTS public."ScheduledSession"
+(
+    id uuid NOT NULL DEFAULT uuid_generate_v4(),
+    room_id uuid NOT NULL,
+    title text COLLATE pg_catalog."default" NOT NULL,
+    description text COLLATE pg_catalog."default" NOT NULL DEFAULT '',
+    starts_at timestamp without time zone NOT NULL,
+    duration integer NOT NULL,
+    recurrence text COLLATE pg_catalog."default",
+    invitee_ids uuid[] NOT NULL DEFAULT '{}',
+    user_ids uuid[] NOT NULL DEFAULT '{}',
+    created_by uuid NOT NULL,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    updated_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "ScheduledSession_pkey" PRIMARY KEY (id),
+    CONSTRAINT "ScheduledSession_duration_check" CHECK (duration > 0)
+);
+
+COMMENT ON TABLE public."ScheduledSession"
+    IS 'A time set aside to meet in a Room, once or over and over. created_by and the ids in invitee_ids and user_ids refer to the user service.';
+
+COMMENT ON COLUMN public."ScheduledSession".duration
+    IS 'How many seconds each occurrence lasts.';
+
+COMMENT ON COLUMN public."ScheduledSession".recurrence
+    IS 'An RFC 5545 RRULE value, such as FREQ=WEEKLY;BYDAY=MO, or null for a one-off. Occurrences repeat at the same UTC time.';
+
+COMMENT ON COLUMN public."ScheduledSession".invitee_ids
+    IS 'The Users and Groups invited, as given.';
+
+COMMENT ON COLUMN public."ScheduledSession".user_ids
+    IS 'The Users invited, directly or through a Group, as of when the session was last changed.';
+
+ALTER TABLE IF EXISTS public."ScheduledSession"
+    ADD CONSTRAINT "ScheduledSession_Room_fkey" FOREIGN KEY (room_id)
+    REFERENCES public."Room" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS "ScheduledSession_room_id_starts_at_idx"
+    ON public."ScheduledSession" (room_id, starts_at, id);
+
+CREATE INDEX IF NOT EXISTS "ScheduledSession_user_ids_idx"
+    ON public."ScheduledSession" USING gin (user_ids);
+
+CREATE TABLE IF NOT EXISTS public."CalendarFeed"
+(
+    user_id uuid NOT NULL,
+    token text COLLATE pg_catalog."default" NOT NULL,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "CalendarFeed_pkey" PRIMARY KEY (user_id),
+    CONSTRAINT "CalendarFeed_token_unique" UNIQUE (token)
+);
+
+COMMENT ON TABLE public."CalendarFeed"
+    IS 'The secret token in the address of a User''s calendar feed. user_id refers to a User in the user service.';
+
+END;
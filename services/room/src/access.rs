@@ -0,0 +1,282 @@
+//! Who may do what in a room.
+//!
+//! A user's Role in a room comes from their own membership and from any
+//! grants to the user-service Groups they're in, whichever is highest. Room
+//! owners and superusers can always do everything, except in direct
+//! conversations, which have no owner and are only for the people in them.
+//! Guests are only ever guests, in the one room their token is for.
+
+use crate::db::responses::{Role, Room, Visibility};
+use crate::users::Caller;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// See the room, its participants and its history
+    View,
+    /// Connect to the room's event stream
+    Join,
+    /// Send messages
+    Post,
+    /// Act on other participants and their messages
+    Moderate,
+    /// Change the room itself and who belongs to it
+    Manage,
+}
+
+impl Action {
+    /// The least Role that may take this action
+    fn required_role(self) -> Role {
+        match self {
+            Action::View | Action::Join => Role::Guest,
+            Action::Post => Role::Member,
+            Action::Moderate => Role::Moderator,
+            Action::Manage => Role::Owner,
+        }
+    }
+}
+
+impl Role {
+    pub fn allows(self, action: Action) -> bool {
+        self >= action.required_role()
+    }
+}
+
+/// Work out the caller's Role in a room from every Role they hold in it.
+/// Anyone may take part in a public room as a member unless they've been
+/// given a Role there explicitly. Returns `None` if the caller can't see the
+/// room at all.
+pub fn effective_role(caller: &Caller, room: &Room, roles: &[Role]) -> Option<Role> {
+    if let Some(guest) = &caller.guest {
+        let allowed = guest.room_id == room.id && room.guest_access && !room.direct;
+        return allowed.then_some(Role::Guest);
+    }
+    let granted = roles.iter().copied().max();
+    // Nobody runs a direct conversation, not even whoever started it or a
+    // superuser. The people in it can only take part.
+    if room.direct {
+        return granted.map(|role| role.min(Role::Member));
+    }
+    if caller.is_superuser {
+        return Some(Role::Owner);
+    }
+    if caller.id == room.owner_id {
+        return Some(Role::Owner);
+    }
+    match room.visibility {
+        Visibility::Public => Some(granted.unwrap_or(Role::Member)),
+        Visibility::Private => granted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::users::Guest;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    #[derive(Clone, Copy, Debug)]
+    enum Who {
+        Stranger,
+        Owner,
+        Superuser,
+        Guest,
+        /// A guest whose token is for another room
+        OtherGuest,
+    }
+
+    struct Case {
+        name: &'static str,
+        who: Who,
+        visibility: Visibility,
+        direct: bool,
+        guest_access: bool,
+        /// The caller's own Role and those of their groups' grants
+        roles: &'static [Role],
+        expected: Option<Role>,
+    }
+
+    const fn case(name: &'static str, who: Who, roles: &'static [Role]) -> Case {
+        Case {
+            name,
+            who,
+            visibility: Visibility::Private,
+            direct: false,
+            guest_access: false,
+            roles,
+            expected: None,
+        }
+    }
+
+    fn room(case: &Case, owner_id: Uuid) -> Room {
+        let now = Utc::now().naive_utc();
+        Room {
+            id: Uuid::new_v4(),
+            name: String::from("Room"),
+            description: String::new(),
+            owner_id,
+            visibility: case.visibility,
+            archived_at: None,
+            parent_id: None,
+            closes_at: None,
+            slow_mode: 0,
+            guest_access: case.guest_access,
+            capacity: None,
+            lobby: false,
+            direct: case.direct,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn caller(who: Who, room: &Room) -> Caller {
+        let guest = |room_id| Guest {
+            room_id,
+            name: String::from("Visitor"),
+        };
+        Caller {
+            id: match who {
+                Who::Owner => room.owner_id,
+                _ => Uuid::new_v4(),
+            },
+            is_superuser: matches!(who, Who::Superuser),
+            groups: Vec::new(),
+            guest: match who {
+                Who::Guest => Some(guest(room.id)),
+                Who::OtherGuest => Some(guest(Uuid::new_v4())),
+                _ => None,
+            },
+        }
+    }
+
+    #[test]
+    fn effective_role_of_each_caller() {
+        let cases = [
+            Case {
+                expected: Some(Role::Member),
+                visibility: Visibility::Public,
+                ..case("public room without a membership", Who::Stranger, &[])
+            },
+            Case {
+                expected: Some(Role::Guest),
+                visibility: Visibility::Public,
+                ..case(
+                    "public room with a lesser Role given",
+                    Who::Stranger,
+                    &[Role::Guest],
+                )
+            },
+            Case {
+                expected: Some(Role::Moderator),
+                visibility: Visibility::Public,
+                ..case(
+                    "public room with a greater Role given",
+                    Who::Stranger,
+                    &[Role::Moderator],
+                )
+            },
+            case("private room without a membership", Who::Stranger, &[]),
+            Case {
+                expected: Some(Role::Member),
+                ..case("private room member", Who::Stranger, &[Role::Member])
+            },
+            Case {
+                expected: Some(Role::Moderator),
+                ..case(
+                    "group grant above the member's own Role",
+                    Who::Stranger,
+                    &[Role::Member, Role::Moderator],
+                )
+            },
+            Case {
+                expected: Some(Role::Moderator),
+                ..case(
+                    "group grant below the member's own Role",
+                    Who::Stranger,
+                    &[Role::Moderator, Role::Guest],
+                )
+            },
+            Case {
+                expected: Some(Role::Owner),
+                ..case("owner without a membership", Who::Owner, &[])
+            },
+            Case {
+                expected: Some(Role::Owner),
+                ..case("owner given a lesser Role", Who::Owner, &[Role::Member])
+            },
+            Case {
+                expected: Some(Role::Owner),
+                ..case("superuser without a membership", Who::Superuser, &[])
+            },
+            Case {
+                expected: Some(Role::Member),
+                direct: true,
+                ..case(
+                    "direct conversation moderator",
+                    Who::Stranger,
+                    &[Role::Moderator],
+                )
+            },
+            Case {
+                direct: true,
+                ..case("direct conversation outsider", Who::Stranger, &[])
+            },
+            Case {
+                direct: true,
+                ..case("direct conversation starter out of it", Who::Owner, &[])
+            },
+            Case {
+                direct: true,
+                ..case("direct conversation superuser", Who::Superuser, &[])
+            },
+            Case {
+                expected: Some(Role::Member),
+                direct: true,
+                ..case(
+                    "direct conversation superuser in it",
+                    Who::Superuser,
+                    &[Role::Member],
+                )
+            },
+            Case {
+                expected: Some(Role::Guest),
+                guest_access: true,
+                ..case("guest allowed in", Who::Guest, &[])
+            },
+            Case {
+                expected: Some(Role::Guest),
+                guest_access: true,
+                ..case("guest somehow given a Role", Who::Guest, &[Role::Owner])
+            },
+            case("guest in a room without guest access", Who::Guest, &[]),
+            Case {
+                visibility: Visibility::Public,
+                ..case(
+                    "guest given a Role in a public room without guest access",
+                    Who::Guest,
+                    &[Role::Member],
+                )
+            },
+            Case {
+                guest_access: true,
+                visibility: Visibility::Public,
+                ..case("guest of another room", Who::OtherGuest, &[])
+            },
+            Case {
+                guest_access: true,
+                direct: true,
+                ..case("guest in a direct conversation", Who::Guest, &[])
+            },
+        ];
+        for case in cases {
+            let room = room(&case, Uuid::new_v4());
+            let caller = caller(case.who, &room);
+            assert_eq!(
+                effective_role(&caller, &room, case.roles),
+                case.expected,
+                "{}",
+                case.name
+            );
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use super::{check_not_guest, check_not_muted, own_session, room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::realtime::events::Event;
+use crate::realtime::Hub;
+use crate::users::Caller;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::State;
+use rocket_db_pools::Connection;
+
+/// Tell the room that one of the caller's sessions started or stopped
+/// typing. Clients send this again every few seconds while typing goes on.
+#[put(
+    "/rooms/<id>/sessions/<session_id>/typing",
+    format = "json",
+    data = "<typing>"
+)]
+pub async fn room_session_typing(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    session_id: Uuid,
+    typing: Json<db::requests::TypingRequest>,
+) -> ApiResponse {
+    let (room, role) = match room_for(&mut conn, &caller, id, Action::Post).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    if !own_session(hub, &caller, id, session_id) {
+        return ApiResponse::not_found();
+    }
+    if typing.typing {
+        if let Err(response) = check_not_muted(&mut conn, &caller, &room, role).await {
+            return response;
+        }
+    }
+    let event = Event::Typing {
+        session_id,
+        user_id: caller.id,
+        thread_id: typing.thread_id,
+        typing: typing.typing,
+    };
+    hub.broadcast(id, event.clone());
+    ApiResponse::ok(event)
+}
+
+/// Where everyone who has read anything in a room has read up to
+#[get("/rooms/<id>/reads")]
+pub async fn room_reads_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::View).await {
+        return response;
+    }
+    match db::get_read_markers(&mut conn, id).await {
+        Ok(markers) => ApiResponse::ok(markers),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Mark everything in a room up to a message as read by the caller. Markers
+/// only move forward, so reading an older message changes nothing.
+#[put("/rooms/<id>/reads/me", format = "json", data = "<read>")]
+pub async fn room_read_set(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    read: Json<db::requests::ReadRequest>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::View).await {
+        return response;
+    }
+    match db::set_read_marker(&mut conn, id, caller.id, read.message_id).await {
+        Ok(Some(marker)) => {
+            hub.broadcast(
+                id,
+                Event::Read {
+                    marker: marker.clone(),
+                },
+            );
+            return ApiResponse::ok(marker);
+        }
+        Ok(None) => {}
+        Err(_) => return ApiResponse::internal_error(),
+    }
+    // Either the message isn't in the room or the marker is already past it
+    match db::get_message(&mut conn, id, read.message_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return ApiResponse::not_found(),
+        Err(_) => return ApiResponse::internal_error(),
+    }
+    match db::get_read_marker(&mut conn, id, caller.id).await {
+        Ok(Some(marker)) => ApiResponse::ok(marker),
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// How many messages the caller hasn't read, and how many of those mention
+/// them, in each room they belong to or have read something in
+#[get("/unread")]
+pub async fn unread_list(caller: Caller, mut conn: Connection<db::RoomDb>) -> ApiResponse {
+    if let Err(response) = check_not_guest(&caller) {
+        return response;
+    }
+    match db::get_unread_counts(&mut conn, caller.id, &caller.groups).await {
+        Ok(counts) => ApiResponse::ok(counts),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
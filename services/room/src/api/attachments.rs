@@ -0,0 +1,284 @@
+use super::{check_not_muted, paginate, room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::db::responses::Attachment;
+use crate::realtime::events::Event;
+use crate::realtime::Hub;
+use crate::storage::{Reader, Storage};
+use crate::users::Caller;
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::uuid::Uuid;
+use rocket::{Request, State};
+use rocket_db_pools::Connection;
+use std::sync::Arc;
+
+const MAX_ATTACHMENT_SIZE: u64 = 25 * 1024 * 1024;
+const MAX_NAME_LENGTH: usize = 255;
+
+/// What can be uploaded. Anything a browser would run or render as a page,
+/// like HTML or SVG, is left out.
+const ALLOWED_TYPES: &[&str] = &[
+    "application/json",
+    "application/pdf",
+    "application/zip",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    "audio/mpeg",
+    "audio/ogg",
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+    "text/csv",
+    "text/markdown",
+    "text/plain",
+    "video/mp4",
+    "video/webm",
+];
+
+fn validate_name(name: &str) -> Result<&str, ApiResponse> {
+    let name = name.trim();
+    if name.is_empty()
+        || name.chars().count() > MAX_NAME_LENGTH
+        || name
+            .chars()
+            .any(|c| c.is_control() || c == '/' || c == '\\')
+    {
+        return Err(ApiResponse::bad_request(
+            "name must be a file name of at most 255 characters",
+        ));
+    }
+    Ok(name)
+}
+
+/// The `Content-Disposition` for downloading a file, with an ASCII name for
+/// old clients and the real one for everyone else
+fn disposition(name: &str) -> String {
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let mut encoded = String::new();
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+/// A file's content, sent as a download so browsers never render it in place
+pub struct Download {
+    attachment: Attachment,
+    content: Reader<'static>,
+}
+
+impl<'r> Responder<'r, 'static> for Download {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let content_type = ContentType::parse_flexible(&self.attachment.content_type)
+            .unwrap_or(ContentType::Binary);
+        Response::build()
+            .header(content_type)
+            .header(Header::new(
+                "Content-Disposition",
+                disposition(&self.attachment.name),
+            ))
+            .header(Header::new("X-Content-Type-Options", "nosniff"))
+            .streamed_body(self.content)
+            .ok()
+    }
+}
+
+/// Upload a file to a room. The body is the file itself, its type comes from
+/// the `Content-Type` header, and it can then be sent in a message.
+#[post("/rooms/<id>/attachments?<name>", data = "<file>")]
+pub async fn room_attachment_upload(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    storage: &State<Arc<dyn Storage>>,
+    id: Uuid,
+    name: &str,
+    content_type: Option<&ContentType>,
+    file: Data<'_>,
+) -> ApiResponse {
+    let name = match validate_name(name) {
+        Ok(name) => name,
+        Err(response) => return response,
+    };
+    let content_type = match content_type {
+        Some(content_type) => {
+            format!("{}/{}", content_type.top(), content_type.sub()).to_ascii_lowercase()
+        }
+        None => return ApiResponse::bad_request("the file's Content-Type is required"),
+    };
+    if !ALLOWED_TYPES.contains(&content_type.as_str()) {
+        return ApiResponse::error(
+            Status::UnsupportedMediaType,
+            "UnsupportedMediaType",
+            "files of this type can't be uploaded",
+        );
+    }
+    let (room, role) = match room_for(&mut conn, &caller, id, Action::Post).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_not_muted(&mut conn, &caller, &room, role).await {
+        return response;
+    }
+    // Read one byte past the limit to tell a file that fits from one that doesn't
+    let attachment_id = Uuid::new_v4();
+    let limit = MAX_ATTACHMENT_SIZE + 1;
+    let content = Box::pin(file.open(limit.bytes()));
+    let size = match storage.put(attachment_id, content, limit).await {
+        Ok(size) => size,
+        Err(e) => {
+            error!("Failed to store attachment {}: {}", attachment_id, e);
+            return ApiResponse::internal_error();
+        }
+    };
+    if size == 0 || size > MAX_ATTACHMENT_SIZE {
+        let _ = storage.delete(attachment_id).await;
+        if size == 0 {
+            return ApiResponse::bad_request("the file is empty");
+        }
+        return ApiResponse::error(
+            Status::PayloadTooLarge,
+            "PayloadTooLarge",
+            "files can be at most 25 MiB",
+        );
+    }
+    match db::create_attachment(
+        &mut conn,
+        attachment_id,
+        id,
+        caller.id,
+        name,
+        &content_type,
+        size as i64,
+    )
+    .await
+    {
+        Ok(attachment) => ApiResponse::created(attachment),
+        Err(_) => {
+            let _ = storage.delete(attachment_id).await;
+            ApiResponse::internal_error()
+        }
+    }
+}
+
+/// List the files sent in a room, and those the caller has uploaded but not
+/// sent yet, newest first
+#[get("/rooms/<id>/attachments?<page>&<per_page>")]
+pub async fn room_attachments_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    page: Option<i32>,
+    per_page: Option<i32>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::View).await {
+        return response;
+    }
+    let count = db::get_attachment_count(&mut conn, id, caller.id)
+        .await
+        .unwrap_or(0);
+    let (resolved_page, resolved_per_page, total_pages) = paginate(page, per_page, count);
+    match db::get_attachments(
+        &mut conn,
+        id,
+        caller.id,
+        resolved_page,
+        resolved_per_page,
+        total_pages,
+    )
+    .await
+    {
+        Ok(attachments) => ApiResponse {
+            json: attachments,
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[get("/rooms/<id>/attachments/<attachment_id>")]
+pub async fn room_attachment_download(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    storage: &State<Arc<dyn Storage>>,
+    id: Uuid,
+    attachment_id: Uuid,
+) -> Result<Download, ApiResponse> {
+    room_for(&mut conn, &caller, id, Action::View).await?;
+    let attachment = match db::get_attachment(&mut conn, id, attachment_id, caller.id).await {
+        Ok(Some(attachment)) => attachment,
+        Ok(None) => return Err(ApiResponse::not_found()),
+        Err(_) => return Err(ApiResponse::internal_error()),
+    };
+    match storage.get(attachment_id).await {
+        Ok(content) => Ok(Download {
+            attachment,
+            content,
+        }),
+        Err(e) => {
+            error!("Failed to read attachment {}: {}", attachment_id, e);
+            Err(ApiResponse::internal_error())
+        }
+    }
+}
+
+/// Delete a file. Whoever uploaded it can, and so can moderators. If it was
+/// sent in a message, the message is shown without it.
+#[delete("/rooms/<id>/attachments/<attachment_id>")]
+pub async fn room_attachment_remove(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    storage: &State<Arc<dyn Storage>>,
+    id: Uuid,
+    attachment_id: Uuid,
+) -> ApiResponse {
+    let role = match room_for(&mut conn, &caller, id, Action::View).await {
+        Ok((_, role)) => role,
+        Err(response) => return response,
+    };
+    let attachment = match db::get_attachment(&mut conn, id, attachment_id, caller.id).await {
+        Ok(Some(attachment)) => attachment,
+        Ok(None) => return ApiResponse::not_found(),
+        Err(_) => return ApiResponse::internal_error(),
+    };
+    let action = if attachment.user_id == caller.id && !role.allows(Action::Moderate) {
+        Action::Post
+    } else {
+        Action::Moderate
+    };
+    if let Err(response) = room_for(&mut conn, &caller, id, action).await {
+        return response;
+    }
+    let attachment = match db::delete_attachment(&mut conn, id, attachment_id).await {
+        Ok(Some(attachment)) => attachment,
+        Ok(None) => return ApiResponse::not_found(),
+        Err(_) => return ApiResponse::internal_error(),
+    };
+    if let Err(e) = storage.delete(attachment_id).await {
+        error!("Failed to delete attachment {}: {}", attachment_id, e);
+    }
+    if let Some(message_id) = attachment.message_id {
+        if let Ok(Some(message)) = db::get_message(&mut conn, id, message_id).await {
+            hub.broadcast(id, Event::MessageEdited { message });
+        }
+    }
+    ApiResponse::ok(attachment)
+}
@@ -0,0 +1,242 @@
+use super::{room_for, ApiResponse};
+use crate::access::Action;
+use crate::breakouts;
+use crate::db;
+use crate::db::requests::BreakoutRequest;
+use crate::db::responses::Room;
+use crate::realtime::events::Event;
+use crate::realtime::Hub;
+use crate::users::Caller;
+use chrono::{Duration, Utc};
+use rocket::http::Status;
+use rocket::serde::json::json;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::State;
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+
+const MAX_BREAKOUTS: usize = 50;
+const MIN_BREAKOUT_DURATION: i64 = 30;
+const MAX_BREAKOUT_DURATION: i64 = 4 * 60 * 60;
+
+/// Work out who goes to which breakout: everyone sent somewhere by hand, then
+/// if asked, everyone else connected to the parent room spread at random so
+/// that the breakouts are as even as possible
+fn assign(
+    request: BreakoutRequest,
+    connected: Vec<Uuid>,
+    facilitator: Uuid,
+) -> Result<Vec<(String, Vec<Uuid>)>, &'static str> {
+    let mut breakouts: Vec<(String, Vec<Uuid>)> = Vec::new();
+    for room in request.rooms {
+        let name = room.name.trim();
+        if name.is_empty() {
+            return Err("breakout room names must not be empty");
+        }
+        for user_id in &room.user_ids {
+            if breakouts.iter().any(|(_, users)| users.contains(user_id)) {
+                return Err("a user can only be sent to one breakout room");
+            }
+        }
+        let mut user_ids = Vec::new();
+        for user_id in room.user_ids {
+            if !user_ids.contains(&user_id) {
+                user_ids.push(user_id);
+            }
+        }
+        breakouts.push((name.to_string(), user_ids));
+    }
+    if request.shuffle {
+        let mut remaining: Vec<Uuid> = Vec::new();
+        for user_id in connected {
+            let assigned = breakouts.iter().any(|(_, users)| users.contains(&user_id));
+            if user_id != facilitator && !assigned && !remaining.contains(&user_id) {
+                remaining.push(user_id);
+            }
+        }
+        remaining.sort_by_cached_key(|_| Uuid::new_v4());
+        for user_id in remaining {
+            if let Some((_, users)) = breakouts.iter_mut().min_by_key(|(_, users)| users.len()) {
+                users.push(user_id);
+            }
+        }
+    }
+    Ok(breakouts)
+}
+
+/// Whether a user can be sent to one of a room's breakouts by hand: they have
+/// to be connected to the room, own it or hold a Role in it, and mustn't be
+/// banned from it
+async fn may_take_part(
+    conn: &mut PgConnection,
+    room: &Room,
+    connected: &[Uuid],
+    user_id: Uuid,
+) -> db::DBResult<bool> {
+    if db::is_banned(conn, &[room.id], user_id).await? {
+        return Ok(false);
+    }
+    if user_id == room.owner_id || connected.contains(&user_id) {
+        return Ok(true);
+    }
+    let roles = db::get_roles(conn, room.id, user_id, &[]).await?;
+    Ok(!roles.is_empty())
+}
+
+#[get("/rooms/<id>/breakouts")]
+pub async fn room_breakouts_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::View).await {
+        return response;
+    }
+    match db::get_breakouts(&mut conn, id).await {
+        Ok(breakouts) => ApiResponse::ok(breakouts),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Split a room into breakout rooms that close after `duration` seconds,
+/// when everyone is sent back. A room can only have one set of breakouts open
+/// at a time.
+#[post("/rooms/<id>/breakouts", format = "json", data = "<breakouts>")]
+pub async fn room_breakouts_open(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    breakouts: Json<BreakoutRequest>,
+) -> ApiResponse {
+    let room = match room_for(&mut conn, &caller, id, Action::Moderate).await {
+        Ok((room, _)) => room,
+        Err(response) => return response,
+    };
+    if room.parent_id.is_some() {
+        return ApiResponse::bad_request("breakout rooms can't have breakouts of their own");
+    }
+    if breakouts.rooms.is_empty() || breakouts.rooms.len() > MAX_BREAKOUTS {
+        return ApiResponse::bad_request("there must be between 1 and 50 breakout rooms");
+    }
+    if !(MIN_BREAKOUT_DURATION..=MAX_BREAKOUT_DURATION).contains(&breakouts.duration) {
+        return ApiResponse::bad_request("duration must be between 30 seconds and 4 hours");
+    }
+    match db::get_breakouts(&mut conn, id).await {
+        Ok(open) if !open.is_empty() => {
+            return ApiResponse::error(
+                Status::Conflict,
+                "BreakoutsOpen",
+                "the room already has breakout rooms open",
+            )
+        }
+        Ok(_) => {}
+        Err(_) => return ApiResponse::internal_error(),
+    }
+    let closes_at = (Utc::now() + Duration::seconds(breakouts.duration)).naive_utc();
+    let connected: Vec<Uuid> = hub
+        .participants(id)
+        .into_iter()
+        .map(|p| p.user_id)
+        .collect();
+    for user_id in breakouts.rooms.iter().flat_map(|room| &room.user_ids) {
+        match may_take_part(&mut conn, &room, &connected, *user_id).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return ApiResponse::bad_request(
+                    "only people who can take part in the room can be sent to its breakouts",
+                )
+            }
+            Err(_) => return ApiResponse::internal_error(),
+        }
+    }
+    let assignments = match assign(breakouts.into_inner(), connected, caller.id) {
+        Ok(assignments) => assignments,
+        Err(long) => return ApiResponse::bad_request(long),
+    };
+    match db::create_breakouts(&mut conn, &room, &assignments, closes_at).await {
+        Ok(breakouts) => {
+            hub.broadcast(
+                id,
+                Event::BreakoutsOpened {
+                    breakouts: breakouts.clone(),
+                },
+            );
+            ApiResponse::created(breakouts)
+        }
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Close a room's breakout rooms early, sending everyone back
+#[delete("/rooms/<id>/breakouts")]
+pub async fn room_breakouts_close(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Moderate).await {
+        return response;
+    }
+    match db::close_breakouts(&mut conn, id).await {
+        Ok(closed) if closed.is_empty() => ApiResponse::not_found(),
+        Ok(closed) => {
+            breakouts::announce_closed(hub, &closed);
+            ApiResponse {
+                json: json!({"result": closed}),
+                status: Status::Ok,
+            }
+        }
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, User};
+    use rocket::http::{ContentType, Status};
+    use rocket::serde::json::json;
+
+    #[rocket::async_test]
+    async fn only_people_in_the_room_are_sent_to_breakouts() {
+        let client = testing::client().await;
+        let (owner, member, stranger) = (User::new(), User::new(), User::new());
+        let room_id = testing::create_room(&client, &owner, "private").await;
+        testing::add_member(&client, &owner, room_id, &member, "member").await;
+        let uri = format!("/rooms/{}/breakouts", room_id);
+        let breakouts = |user_ids: Vec<_>| {
+            json!({
+                "rooms": [{"name": "Corner", "user_ids": user_ids}],
+                "duration": 60,
+            })
+        };
+
+        let request = breakouts(vec![member.id, stranger.id]);
+        let (status, _) = testing::post(&client, &owner, uri.clone(), request).await;
+        assert_eq!(status, Status::BadRequest);
+
+        let request = breakouts(vec![member.id]);
+        let (status, opened) = testing::post(&client, &owner, uri, request).await;
+        assert_eq!(status, Status::Created);
+        assert_eq!(opened["result"][0]["user_ids"], json!([member.id]));
+        let breakout_id = opened["result"][0]["room"]["id"].as_str().unwrap();
+
+        // Roles in a breakout room would never apply, so none can be given
+        let response = client
+            .put(format!("/rooms/{}/members/{}", breakout_id, stranger.id))
+            .header(ContentType::JSON)
+            .header(owner.authorization())
+            .body(json!({"role": "member"}).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client
+            .delete(format!("/rooms/{}/members/{}", breakout_id, member.id))
+            .header(owner.authorization())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+}
@@ -0,0 +1,97 @@
+use super::messages::{send, validate_message};
+use super::{check_not_guest, paginate, room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::realtime::Hub;
+use crate::users;
+use crate::users::Caller;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_db_pools::Connection;
+
+/// Including the sender
+const MAX_DIRECT_PARTICIPANTS: usize = 8;
+
+/// Send a message to one or a few other users directly. The conversation
+/// between exactly these users is started by the first message and reused
+/// after that; it is a private room without a name or an owner, left out of
+/// room listings, and works like any other room from then on.
+#[post("/direct/messages", format = "json", data = "<request>")]
+pub async fn direct_message_send(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    request: Json<db::requests::DirectMessageRequest>,
+) -> ApiResponse {
+    if let Err(response) = check_not_guest(&caller) {
+        return response;
+    }
+    let mut user_ids = request.user_ids.clone();
+    user_ids.retain(|user_id| *user_id != caller.id);
+    user_ids.sort();
+    user_ids.dedup();
+    if user_ids.is_empty() || user_ids.len() >= MAX_DIRECT_PARTICIPANTS {
+        return ApiResponse::bad_request("user_ids must name between 1 and 7 other users");
+    }
+    // Don't start a conversation for a message that can't be sent
+    if let Err(response) = validate_message(&request.message) {
+        return response;
+    }
+    // Groups can't be messaged directly, only users
+    match users::resolve_mentions(&user_ids).await {
+        Ok(found)
+            if found.len() == user_ids.len()
+                && found.iter().all(|user| user.user_ids == [user.id]) => {}
+        Ok(_) => return ApiResponse::bad_request("user_ids must all be users"),
+        Err(_) => {
+            return ApiResponse::error(
+                Status::ServiceUnavailable,
+                "Unavailable",
+                "the users could not be looked up, try again later",
+            )
+        }
+    }
+    user_ids.push(caller.id);
+    user_ids.sort();
+    let room = match db::open_direct_room(&mut conn, caller.id, &user_ids).await {
+        Ok(room) => room,
+        Err(_) => return ApiResponse::internal_error(),
+    };
+    match room_for(&mut conn, &caller, room.id, Action::Post).await {
+        Ok((room, role)) => send(&mut conn, hub, &caller, &room, role, &request.message).await,
+        Err(response) => response,
+    }
+}
+
+/// List the caller's direct conversations, most recently active first
+#[get("/direct?<page>&<per_page>")]
+pub async fn direct_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    page: Option<i32>,
+    per_page: Option<i32>,
+) -> ApiResponse {
+    if let Err(response) = check_not_guest(&caller) {
+        return response;
+    }
+    let count = db::get_direct_room_count(&mut conn, caller.id)
+        .await
+        .unwrap_or(0);
+    let (resolved_page, resolved_per_page, total_pages) = paginate(page, per_page, count);
+    match db::get_direct_rooms(
+        &mut conn,
+        caller.id,
+        resolved_page,
+        resolved_per_page,
+        total_pages,
+    )
+    .await
+    {
+        Ok(conversations) => ApiResponse {
+            json: conversations,
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
@@ -0,0 +1,252 @@
+use super::{check_not_guest, room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::db::responses::{Role, RoomInvite};
+use crate::users::Caller;
+use chrono::{Duration, Utc};
+use rocket::http::Status;
+use rocket::serde::json::json;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_db_pools::Connection;
+
+const DEFAULT_INVITE_LIFETIME: i64 = 7 * 24 * 60 * 60;
+const MAX_INVITE_LIFETIME: i64 = 30 * 24 * 60 * 60;
+const MAX_INVITE_USES: i32 = 1000;
+
+/// The claims of a signed invite token. The token only proves which invite
+/// it is for; whether it can still be redeemed is tracked in the database.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+struct InviteClaims {
+    invite_id: Uuid,
+    room_id: Uuid,
+    exp: i64,
+}
+
+fn invite_token(invite: &RoomInvite) -> Option<String> {
+    auth::encode_token(&InviteClaims {
+        invite_id: invite.id,
+        room_id: invite.room_id,
+        exp: invite.expires_at.and_utc().timestamp(),
+    })
+    .ok()
+}
+
+/// Create an invite to a room. Anyone with the returned token can use it to
+/// join until it expires or runs out of uses.
+#[post("/rooms/<id>/invites", format = "json", data = "<invite>")]
+pub async fn room_invites_add(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    invite: Json<db::requests::InviteRequest>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Manage).await {
+        return response;
+    }
+    let role = invite.role.unwrap_or(Role::Member);
+    if role == Role::Owner {
+        return ApiResponse::bad_request("invites can't make someone a room's owner");
+    }
+    let max_uses = invite.max_uses.unwrap_or(1);
+    if !(1..=MAX_INVITE_USES).contains(&max_uses) {
+        return ApiResponse::bad_request("max_uses must be between 1 and 1000");
+    }
+    let lifetime = invite.expires_in.unwrap_or(DEFAULT_INVITE_LIFETIME);
+    if !(1..=MAX_INVITE_LIFETIME).contains(&lifetime) {
+        return ApiResponse::bad_request("expires_in must be between 1 second and 30 days");
+    }
+    let expires_at = Utc::now().naive_utc() + Duration::seconds(lifetime);
+    match db::create_invite(&mut conn, id, caller.id, role, max_uses, expires_at).await {
+        Ok(invite) => match invite_token(&invite) {
+            Some(token) => ApiResponse::created(json!({"invite": invite, "token": token})),
+            None => ApiResponse::internal_error(),
+        },
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// List the invites to a room that can still be redeemed, along with their
+/// tokens so they can be shared again
+#[get("/rooms/<id>/invites")]
+pub async fn room_invites_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Manage).await {
+        return response;
+    }
+    match db::get_invites(&mut conn, id).await {
+        Ok(invites) => ApiResponse::ok(
+            invites
+                .into_iter()
+                .map(|invite| {
+                    let token = invite_token(&invite);
+                    json!({"invite": invite, "token": token})
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[delete("/rooms/<id>/invites/<invite_id>")]
+pub async fn room_invite_revoke(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    invite_id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Manage).await {
+        return response;
+    }
+    match db::revoke_invite(&mut conn, id, invite_id).await {
+        Ok(Some(invite)) => ApiResponse::ok(invite),
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Redeem an invite token, joining the room it is for
+#[post("/invites/redeem", format = "json", data = "<redeem>")]
+pub async fn invite_redeem(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    redeem: Json<db::requests::RedeemRequest>,
+) -> ApiResponse {
+    if let Err(response) = check_not_guest(&caller) {
+        return response;
+    }
+    let claims: InviteClaims = match auth::decode_token(redeem.token.trim()) {
+        Ok(claims) => claims,
+        Err(auth::AuthenticationError::Expired) => return invite_unavailable(),
+        Err(_) => return ApiResponse::bad_request("invalid invite token"),
+    };
+    match db::get_room(&mut conn, claims.room_id).await {
+        Ok(Some(room)) if room.archived_at.is_none() => {}
+        Ok(_) => return invite_unavailable(),
+        Err(_) => return ApiResponse::internal_error(),
+    }
+    match db::redeem_invite(&mut conn, claims.room_id, claims.invite_id, caller.id).await {
+        Ok(Some(invite)) => ApiResponse::ok(json!({
+            "room_id": invite.room_id,
+            "role": invite.role,
+        })),
+        Ok(None) => invite_unavailable(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+fn invite_unavailable() -> ApiResponse {
+    ApiResponse::error(
+        Status::Gone,
+        "InviteUnavailable",
+        "the invite has expired, been revoked or been used up",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, User};
+    use rocket::local::asynchronous::Client;
+    use rocket::serde::json::Value;
+
+    /// Invite people to a room, returning the invite's id and its token
+    async fn invite(client: &Client, owner: &User, room_id: Uuid, invite: Value) -> (Uuid, String) {
+        let uri = format!("/rooms/{}/invites", room_id);
+        let (status, created) = testing::post(client, owner, uri, invite).await;
+        assert_eq!(status, Status::Created);
+        let invite_id = created["result"]["invite"]["id"].as_str().unwrap();
+        let token = created["result"]["token"].as_str().unwrap();
+        (invite_id.parse().unwrap(), token.to_string())
+    }
+
+    async fn redeem(client: &Client, user: &User, token: &str) -> Status {
+        let uri = String::from("/invites/redeem");
+        let (status, _) = testing::post(client, user, uri, json!({ "token": token })).await;
+        status
+    }
+
+    #[rocket::async_test]
+    async fn invites_run_out_after_max_uses() {
+        let client = testing::client().await;
+        let owner = User::new();
+        let room_id = testing::create_room(&client, &owner, "private").await;
+        let (_, token) = invite(&client, &owner, room_id, json!({ "max_uses": 2 })).await;
+
+        let user = User::new();
+        assert_eq!(redeem(&client, &user, &token).await, Status::Ok);
+        let (status, _) = testing::get(&client, &user, format!("/rooms/{}", room_id)).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(redeem(&client, &User::new(), &token).await, Status::Ok);
+        assert_eq!(redeem(&client, &User::new(), &token).await, Status::Gone);
+    }
+
+    #[rocket::async_test]
+    async fn expired_invites_are_rejected() {
+        let client = testing::client().await;
+        let owner = User::new();
+        let room_id = testing::create_room(&client, &owner, "private").await;
+        let (invite_id, token) = invite(&client, &owner, room_id, json!({ "max_uses": 5 })).await;
+
+        // A token past its expiry, for an invite that hasn't expired
+        let expired = auth::encode_token(&InviteClaims {
+            invite_id,
+            room_id,
+            exp: Utc::now().timestamp() - 60 * 60,
+        })
+        .unwrap();
+        assert_eq!(redeem(&client, &User::new(), &expired).await, Status::Gone);
+
+        // An invite that has expired, with a token that hasn't
+        sqlx::query(r#"UPDATE public."RoomInvite" SET expires_at = '2000-01-01' WHERE id = $1"#)
+            .bind(invite_id)
+            .execute(testing::database(&client))
+            .await
+            .unwrap();
+        assert_eq!(redeem(&client, &User::new(), &token).await, Status::Gone);
+    }
+
+    #[rocket::async_test]
+    async fn revoked_invites_are_rejected() {
+        let client = testing::client().await;
+        let owner = User::new();
+        let room_id = testing::create_room(&client, &owner, "private").await;
+        let (invite_id, token) = invite(&client, &owner, room_id, json!({ "max_uses": 5 })).await;
+
+        let response = client
+            .delete(format!("/rooms/{}/invites/{}", room_id, invite_id))
+            .header(owner.authorization())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(redeem(&client, &User::new(), &token).await, Status::Gone);
+    }
+
+    #[rocket::async_test]
+    async fn invite_tokens_only_work_for_their_room() {
+        let client = testing::client().await;
+        let owner = User::new();
+        let room_id = testing::create_room(&client, &owner, "private").await;
+        let other_room_id = testing::create_room(&client, &owner, "private").await;
+        let (invite_id, _) = invite(&client, &owner, room_id, json!({})).await;
+
+        let token = auth::encode_token(&InviteClaims {
+            invite_id,
+            room_id: other_room_id,
+            exp: Utc::now().timestamp() + 60 * 60,
+        })
+        .unwrap();
+        let user = User::new();
+        assert_eq!(redeem(&client, &user, &token).await, Status::Gone);
+        let (status, _) = testing::get(&client, &user, format!("/rooms/{}", other_room_id)).await;
+        assert_eq!(status, Status::NotFound);
+
+        let tampered = format!("{}x", token);
+        assert_eq!(redeem(&client, &user, &tampered).await, Status::BadRequest);
+    }
+}
@@ -0,0 +1,223 @@
+use super::{room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::db::responses::{KnockState, Role, Room};
+use crate::realtime::Hub;
+use crate::users::Caller;
+use rocket::http::Status;
+use rocket::response::stream::{Event as StreamEvent, EventStream};
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::select;
+use rocket::{Shutdown, State};
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+
+/// Why someone would have to wait in a room's lobby
+enum Closed {
+    Locked,
+    Full,
+}
+
+/// Work out whether the caller would have to knock to join a room. Moderators
+/// never do, nor do people already in the room or let in lately. Breakout
+/// rooms and direct conversations have no lobby.
+async fn closed(
+    conn: &mut PgConnection,
+    hub: &Hub,
+    caller: &Caller,
+    room: &Room,
+    role: Role,
+) -> Result<Option<Closed>, ApiResponse> {
+    if role.allows(Action::Moderate)
+        || room.parent_id.is_some()
+        || room.direct
+        || hub.is_present(room.id, caller.id)
+    {
+        return Ok(None);
+    }
+    let closed = if room.lobby {
+        Closed::Locked
+    } else if room
+        .capacity
+        .is_some_and(|capacity| hub.user_count(room.id) >= capacity as usize)
+    {
+        Closed::Full
+    } else {
+        return Ok(None);
+    };
+    match db::is_admitted(conn, room.id, caller.id).await {
+        Ok(true) => Ok(None),
+        Ok(false) => Ok(Some(closed)),
+        Err(_) => Err(ApiResponse::internal_error()),
+    }
+}
+
+/// Check that the caller can join a room without knocking
+pub(super) async fn check_entry(
+    conn: &mut PgConnection,
+    hub: &Hub,
+    caller: &Caller,
+    room: &Room,
+    role: Role,
+) -> Result<(), ApiResponse> {
+    match closed(conn, hub, caller, room, role).await? {
+        None => Ok(()),
+        Some(Closed::Locked) => Err(ApiResponse::error(
+            Status::Forbidden,
+            "Locked",
+            "the room is locked, knock to be let in",
+        )),
+        Some(Closed::Full) => Err(ApiResponse::error(
+            Status::Forbidden,
+            "Full",
+            "the room is full, knock to be let in",
+        )),
+    }
+}
+
+/// Knock on a room's lobby to ask to be let in, when it is locked or full.
+/// Knocking again while waiting returns the same knock. The room hears about
+/// every knock and answer over its event stream.
+#[post("/rooms/<id>/knocks")]
+pub async fn room_knock(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+) -> ApiResponse {
+    let (room, role) = match room_for(&mut conn, &caller, id, Action::Join).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    match closed(&mut conn, hub, &caller, &room, role).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return ApiResponse::error(
+                Status::Conflict,
+                "Open",
+                "the room can be joined without knocking",
+            )
+        }
+        Err(response) => return response,
+    }
+    let guest_name = caller.guest.as_ref().map(|guest| guest.name.as_str());
+    match db::create_knock(&mut conn, id, caller.id, guest_name).await {
+        Ok(knock) => {
+            hub.knock(knock.clone());
+            ApiResponse::created(knock)
+        }
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// The knocks waiting in a room's lobby, in the order they came
+#[get("/rooms/<id>/knocks")]
+pub async fn room_knocks_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Moderate).await {
+        return response;
+    }
+    match db::get_waiting_knocks(&mut conn, id).await {
+        Ok(knocks) => ApiResponse::ok(knocks),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Admit or deny someone waiting in a room's lobby. Only knocks that are
+/// still waiting can be answered.
+#[put("/rooms/<id>/knocks/<knock_id>", format = "json", data = "<answer>")]
+pub async fn room_knock_answer(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    knock_id: Uuid,
+    answer: Json<db::requests::KnockAnswerRequest>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Moderate).await {
+        return response;
+    }
+    let state = if answer.admit {
+        KnockState::Admitted
+    } else {
+        KnockState::Denied
+    };
+    match db::answer_knock(&mut conn, id, knock_id, state, caller.id).await {
+        Ok(Some(knock)) => {
+            hub.knock(knock.clone());
+            ApiResponse::ok(knock)
+        }
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Stop waiting in a room's lobby
+#[delete("/rooms/<id>/knocks/<knock_id>")]
+pub async fn room_knock_cancel(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    knock_id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::View).await {
+        return response;
+    }
+    match db::get_knock(&mut conn, id, knock_id).await {
+        Ok(Some(knock)) if knock.user_id == caller.id => {}
+        Ok(_) => return ApiResponse::not_found(),
+        Err(_) => return ApiResponse::internal_error(),
+    }
+    match db::answer_knock(&mut conn, id, knock_id, KnockState::Cancelled, caller.id).await {
+        Ok(Some(knock)) => {
+            hub.knock(knock.clone());
+            ApiResponse::ok(knock)
+        }
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Follow a knock from the lobby. The knock is sent as it stands, then again
+/// whenever it changes, and the stream ends once it has been settled. A
+/// client whose stream ends while the knock is still waiting should
+/// reconnect.
+#[get("/rooms/<id>/knocks/<knock_id>/events")]
+pub async fn room_knock_events(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    mut end: Shutdown,
+    id: Uuid,
+    knock_id: Uuid,
+) -> Result<EventStream![], ApiResponse> {
+    room_for(&mut conn, &caller, id, Action::View).await?;
+    // Listen before looking, so an answer in between isn't missed
+    let mut waiting = hub.wait(knock_id);
+    let knock = match db::get_knock(&mut conn, id, knock_id).await {
+        Ok(Some(knock)) if knock.user_id == caller.id => knock,
+        Ok(_) => return Err(ApiResponse::not_found()),
+        Err(_) => return Err(ApiResponse::internal_error()),
+    };
+    Ok(EventStream! {
+        let mut knock = knock;
+        loop {
+            yield StreamEvent::json(&knock);
+            if knock.state != KnockState::Waiting {
+                break;
+            }
+            knock = select! {
+                knock = waiting.recv() => match knock {
+                    Some(knock) => knock,
+                    None => break,
+                },
+                _ = &mut end => break,
+            };
+        }
+    })
+}
@@ -0,0 +1,156 @@
+use super::{can_manage_member, paginate, room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::db::responses::Role;
+use crate::users::Caller;
+use rocket::http::Status;
+use rocket::serde::json::json;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket_db_pools::Connection;
+
+/// Breakout rooms take their Roles from the parent room, so any given in one
+/// would never apply
+const BREAKOUT_ROLES: &str = "breakout rooms take their roles from the parent room";
+
+#[get("/rooms/<id>/members?<page>&<per_page>")]
+pub async fn room_members_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    page: Option<i32>,
+    per_page: Option<i32>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::View).await {
+        return response;
+    }
+    let count = db::get_room_member_count(&mut conn, id).await.unwrap_or(0);
+    let (resolved_page, resolved_per_page, total_pages) = paginate(page, per_page, count);
+    match db::get_room_members(&mut conn, id, resolved_page, resolved_per_page, total_pages).await {
+        Ok(members) => ApiResponse {
+            json: members,
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Add a user to a room, or change their Role in it
+#[put("/rooms/<id>/members/<user_id>", format = "json", data = "<role>")]
+pub async fn room_member_set(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    user_id: Uuid,
+    role: Json<db::requests::RoleRequest>,
+) -> ApiResponse {
+    let (room, caller_role) = match room_for(&mut conn, &caller, id, Action::Moderate).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    if room.parent_id.is_some() {
+        return ApiResponse::bad_request(BREAKOUT_ROLES);
+    }
+    if role.role == Role::Owner || user_id == room.owner_id {
+        return ApiResponse::bad_request("a room's owner can't be changed");
+    }
+    if role.role >= Role::Moderator && !caller_role.allows(Action::Manage) {
+        return ApiResponse::forbidden();
+    }
+    match can_manage_member(&mut conn, caller_role, id, user_id).await {
+        Ok(true) => {}
+        Ok(false) => return ApiResponse::forbidden(),
+        Err(response) => return response,
+    }
+    match db::set_room_member(&mut conn, id, user_id, role.role).await {
+        Ok(member) => ApiResponse::ok(member),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[delete("/rooms/<id>/members/<user_id>")]
+pub async fn room_member_remove(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    user_id: Uuid,
+) -> ApiResponse {
+    let (room, caller_role) = match room_for(&mut conn, &caller, id, Action::Moderate).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    if room.parent_id.is_some() {
+        return ApiResponse::bad_request(BREAKOUT_ROLES);
+    }
+    match can_manage_member(&mut conn, caller_role, id, user_id).await {
+        Ok(true) => {}
+        Ok(false) => return ApiResponse::forbidden(),
+        Err(response) => return response,
+    }
+    match db::remove_room_member(&mut conn, id, user_id).await {
+        Ok(0) => ApiResponse::not_found(),
+        Ok(_) => ApiResponse::ok(json!({"user_id": user_id})),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[get("/rooms/<id>/groups")]
+pub async fn room_groups_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::View).await {
+        return response;
+    }
+    match db::get_room_group_grants(&mut conn, id).await {
+        Ok(grants) => ApiResponse::ok(grants),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Give everyone in a user-service Group a Role in a room
+#[put("/rooms/<id>/groups/<group_id>", format = "json", data = "<role>")]
+pub async fn room_group_set(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    group_id: Uuid,
+    role: Json<db::requests::RoleRequest>,
+) -> ApiResponse {
+    let room = match room_for(&mut conn, &caller, id, Action::Manage).await {
+        Ok((room, _)) => room,
+        Err(response) => return response,
+    };
+    if room.parent_id.is_some() {
+        return ApiResponse::bad_request(BREAKOUT_ROLES);
+    }
+    if role.role == Role::Owner {
+        return ApiResponse::bad_request("groups can't own rooms");
+    }
+    match db::set_room_group_grant(&mut conn, id, group_id, role.role).await {
+        Ok(grant) => ApiResponse::ok(grant),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[delete("/rooms/<id>/groups/<group_id>")]
+pub async fn room_group_remove(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    group_id: Uuid,
+) -> ApiResponse {
+    let room = match room_for(&mut conn, &caller, id, Action::Manage).await {
+        Ok((room, _)) => room,
+        Err(response) => return response,
+    };
+    if room.parent_id.is_some() {
+        return ApiResponse::bad_request(BREAKOUT_ROLES);
+    }
+    match db::remove_room_group_grant(&mut conn, id, group_id).await {
+        Ok(0) => ApiResponse::not_found(),
+        Ok(_) => ApiResponse::ok(json!({"group_id": group_id})),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
@@ -0,0 +1,627 @@
+use super::moderation::announce;
+use super::{check_not_guest, check_not_muted, room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::db::requests::MessageRequest;
+use crate::db::responses::{Message, Role, Room};
+use crate::db::{ModerationRecord, NewMessage};
+use crate::realtime::events::Event;
+use crate::realtime::Hub;
+use crate::users;
+use crate::users::Caller;
+use chrono::{Duration, Utc};
+use rocket::http::Status;
+use rocket::serde::json::json;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::State;
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+
+const MAX_MESSAGE_LENGTH: usize = 4000;
+const MAX_EMOJI_LENGTH: usize = 32;
+const MAX_MENTIONS: usize = 20;
+const MAX_ATTACHMENTS: usize = 10;
+const DEFAULT_HISTORY_LIMIT: i64 = 50;
+
+fn validate_body(body: &str) -> Result<&str, ApiResponse> {
+    let body = body.trim();
+    if body.is_empty() || body.chars().count() > MAX_MESSAGE_LENGTH {
+        return Err(ApiResponse::bad_request(
+            "message must be between 1 and 4000 characters",
+        ));
+    }
+    Ok(body)
+}
+
+/// The ids mentioned in a message body. Clients write mentions of users and
+/// groups alike as `<@id>` and show them by name. Mentioning someone twice
+/// counts once, and anything between `<@` and `>` that isn't an id is text.
+fn mentioned_ids(body: &str) -> Result<Vec<Uuid>, ApiResponse> {
+    let mut ids = Vec::new();
+    for (start, _) in body.match_indices("<@") {
+        let rest = &body[start + 2..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let Ok(id) = Uuid::parse_str(&rest[..end]) else {
+            continue;
+        };
+        if ids.contains(&id) {
+            continue;
+        }
+        if ids.len() == MAX_MENTIONS {
+            return Err(ApiResponse::bad_request(
+                "a message can mention at most 20 users and groups",
+            ));
+        }
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+/// Work out who a message body mentions with the user service's help,
+/// returning the mentioned ids it recognised and every user they reach
+async fn resolve_mentions(body: &str) -> Result<(Vec<Uuid>, Vec<Uuid>), ApiResponse> {
+    let ids = mentioned_ids(body)?;
+    if ids.is_empty() {
+        return Ok((ids, Vec::new()));
+    }
+    match users::resolve_mentions(&ids).await {
+        Ok(mentions) => {
+            let mentioned = mentions.iter().map(|mention| mention.id).collect();
+            let user_ids = mentions
+                .into_iter()
+                .flat_map(|mention| mention.user_ids)
+                .collect();
+            Ok((mentioned, user_ids))
+        }
+        Err(_) => Err(ApiResponse::error(
+            Status::ServiceUnavailable,
+            "Unavailable",
+            "mentions could not be resolved, try again later",
+        )),
+    }
+}
+
+/// A reaction is a single emoji or a short code like `:tada:`, never text
+fn validate_emoji(emoji: &str) -> Result<(), ApiResponse> {
+    if emoji.is_empty()
+        || emoji.len() > MAX_EMOJI_LENGTH
+        || emoji.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(ApiResponse::bad_request(
+            "emoji must be a single short token",
+        ));
+    }
+    Ok(())
+}
+
+/// Look up a message in a room the caller may take `action` in
+async fn message_for(
+    conn: &mut PgConnection,
+    caller: &Caller,
+    id: Uuid,
+    message_id: Uuid,
+    action: Action,
+) -> Result<(Room, Role, Message), ApiResponse> {
+    let (room, role) = room_for(conn, caller, id, action).await?;
+    match db::get_message(conn, id, message_id).await {
+        Ok(Some(message)) => Ok((room, role, message)),
+        Ok(None) => Err(ApiResponse::not_found()),
+        Err(_) => Err(ApiResponse::internal_error()),
+    }
+}
+
+fn deleted() -> ApiResponse {
+    ApiResponse::error(Status::Conflict, "Deleted", "the message has been deleted")
+}
+
+/// Reply with a page of messages, newest first, and where the next one starts
+fn page(messages: Vec<Message>, limit: i64) -> ApiResponse {
+    let next_cursor = match messages.last() {
+        Some(oldest) if messages.len() as i64 == limit => Some(oldest.id),
+        _ => None,
+    };
+    ApiResponse {
+        json: json!({"result": messages, "next_cursor": next_cursor}),
+        status: Status::Ok,
+    }
+}
+
+/// Store a message and broadcast it to everyone in the room. The sender must
+/// have joined it, mustn't be muted, and in slow mode must have waited long
+/// enough since their last message. Replying to a reply puts the new message
+/// in the same thread, so threads are never nested.
+///
+/// Users and groups are mentioned by writing `<@id>` in the body, with the id
+/// of the user or group. Names aren't unique, so `@name` is only text; clients
+/// let people pick who to mention by name and send the id in its place.
+#[post("/rooms/<id>/messages", format = "json", data = "<message>")]
+pub async fn room_message_send(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    message: Json<db::requests::MessageRequest>,
+) -> ApiResponse {
+    match room_for(&mut conn, &caller, id, Action::Post).await {
+        Ok((room, role)) => send(&mut conn, hub, &caller, &room, role, &message).await,
+        Err(response) => response,
+    }
+}
+
+/// Check a message before sending it anywhere, returning its body
+pub(super) fn validate_message(request: &MessageRequest) -> Result<&str, ApiResponse> {
+    if request.attachment_ids.len() > MAX_ATTACHMENTS {
+        return Err(ApiResponse::bad_request(
+            "a message can have at most 10 attachments",
+        ));
+    }
+    // A message can be just the files attached to it
+    match validate_body(&request.body) {
+        Err(_) if request.body.trim().is_empty() && !request.attachment_ids.is_empty() => Ok(""),
+        result => result,
+    }
+}
+
+/// Send a message to a room the caller may post in. Direct conversations
+/// don't have to be joined first.
+pub(super) async fn send(
+    conn: &mut PgConnection,
+    hub: &Hub,
+    caller: &Caller,
+    room: &Room,
+    role: Role,
+    request: &MessageRequest,
+) -> ApiResponse {
+    let body = match validate_message(request) {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_not_muted(conn, caller, room, role).await {
+        return response;
+    }
+    if room.slow_mode > 0 && !role.allows(Action::Moderate) {
+        let next_allowed = match db::last_message_at(conn, room.id, caller.id).await {
+            Ok(sent_at) => {
+                sent_at.map(|sent_at| sent_at + Duration::seconds(room.slow_mode.into()))
+            }
+            Err(_) => return ApiResponse::internal_error(),
+        };
+        if let Some(next_allowed) = next_allowed {
+            let wait = (next_allowed - Utc::now().naive_utc()).num_seconds();
+            if wait >= 0 {
+                return ApiResponse {
+                    json: json!({"error": {
+                        "short": "SlowMode",
+                        "long": "the room is in slow mode",
+                        "retry_after": wait + 1,
+                    }}),
+                    status: Status::TooManyRequests,
+                };
+            }
+        }
+    }
+    if !room.direct && !hub.is_present(room.id, caller.id) {
+        return ApiResponse::error(
+            Status::Conflict,
+            "NotJoined",
+            "join the room before sending messages",
+        );
+    }
+    let thread_id = match request.thread_id {
+        Some(parent_id) => match db::get_message(conn, room.id, parent_id).await {
+            Ok(Some(parent)) => Some(parent.thread_id.unwrap_or(parent.id)),
+            Ok(None) => {
+                return ApiResponse::bad_request("thread_id must be a message in this room")
+            }
+            Err(_) => return ApiResponse::internal_error(),
+        },
+        None => None,
+    };
+    if !request.attachment_ids.is_empty() {
+        match db::count_attachable(conn, room.id, caller.id, &request.attachment_ids).await {
+            Ok(count) if count == request.attachment_ids.len() as i64 => {}
+            Ok(_) => {
+                return ApiResponse::bad_request(
+                    "attachment_ids must be files you uploaded to this room and haven't sent",
+                )
+            }
+            Err(_) => return ApiResponse::internal_error(),
+        }
+    }
+    let (mentions, mentioned_user_ids) = match resolve_mentions(body).await {
+        Ok(resolved) => resolved,
+        Err(response) => return response,
+    };
+    let new_message = NewMessage {
+        room_id: room.id,
+        user_id: caller.id,
+        body,
+        thread_id,
+        mentions: &mentions,
+        mentioned_user_ids: &mentioned_user_ids,
+        attachment_ids: &request.attachment_ids,
+    };
+    match db::create_message(conn, &new_message).await {
+        Ok(message) => {
+            hub.broadcast(
+                room.id,
+                Event::Message {
+                    message: message.clone(),
+                },
+            );
+            ApiResponse::created(message)
+        }
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Page backwards through a room's history, newest first. Replies are left
+/// out; each message says how many it has. `before` is the `next_cursor` from
+/// the previous page.
+#[get("/rooms/<id>/messages?<before>&<limit>")]
+pub async fn room_messages_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    before: Option<Uuid>,
+    limit: Option<i64>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::View).await {
+        return response;
+    }
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, 100);
+    match db::get_messages(&mut conn, id, None, before, limit).await {
+        Ok(messages) => page(messages, limit),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Page backwards through the messages that mention the caller, directly or
+/// through one of their groups, across every room they can see
+#[get("/mentions?<before>&<limit>")]
+pub async fn mentions_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    before: Option<Uuid>,
+    limit: Option<i64>,
+) -> ApiResponse {
+    if let Err(response) = check_not_guest(&caller) {
+        return response;
+    }
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, 100);
+    match db::get_mentions(
+        &mut conn,
+        caller.id,
+        &caller.groups,
+        caller.is_superuser,
+        before,
+        limit,
+    )
+    .await
+    {
+        Ok(messages) => page(messages, limit),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[get("/rooms/<id>/messages/<message_id>")]
+pub async fn room_message_show(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    message_id: Uuid,
+) -> ApiResponse {
+    match message_for(&mut conn, &caller, id, message_id, Action::View).await {
+        Ok((_, _, message)) => ApiResponse::ok(message),
+        Err(response) => response,
+    }
+}
+
+/// Page backwards through the replies in a message's thread, newest first
+#[get("/rooms/<id>/messages/<message_id>/replies?<before>&<limit>")]
+pub async fn room_message_replies_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    message_id: Uuid,
+    before: Option<Uuid>,
+    limit: Option<i64>,
+) -> ApiResponse {
+    let message = match message_for(&mut conn, &caller, id, message_id, Action::View).await {
+        Ok((_, _, message)) => message,
+        Err(response) => return response,
+    };
+    let thread_id = message.thread_id.unwrap_or(message.id);
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, 100);
+    match db::get_messages(&mut conn, id, Some(thread_id), before, limit).await {
+        Ok(messages) => page(messages, limit),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Change what one of the caller's own messages says. What it said before is
+/// kept and can be listed.
+#[put(
+    "/rooms/<id>/messages/<message_id>",
+    format = "json",
+    data = "<request>"
+)]
+pub async fn room_message_edit(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    message_id: Uuid,
+    request: Json<db::requests::MessageUpdateRequest>,
+) -> ApiResponse {
+    let body = match validate_body(&request.body) {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let (room, role, message) =
+        match message_for(&mut conn, &caller, id, message_id, Action::Post).await {
+            Ok(found) => found,
+            Err(response) => return response,
+        };
+    if message.user_id != caller.id {
+        return ApiResponse::forbidden();
+    }
+    if message.deleted_at.is_some() {
+        return deleted();
+    }
+    if let Err(response) = check_not_muted(&mut conn, &caller, &room, role).await {
+        return response;
+    }
+    let (mentions, mentioned_user_ids) = match resolve_mentions(body).await {
+        Ok(resolved) => resolved,
+        Err(response) => return response,
+    };
+    match db::edit_message(
+        &mut conn,
+        id,
+        message_id,
+        caller.id,
+        body,
+        &mentions,
+        &mentioned_user_ids,
+    )
+    .await
+    {
+        Ok(Some(message)) => {
+            hub.broadcast(
+                id,
+                Event::MessageEdited {
+                    message: message.clone(),
+                },
+            );
+            ApiResponse::ok(message)
+        }
+        Ok(None) => deleted(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// What a message said before each edit, newest first. Only its author and
+/// moderators can see what a deleted message said.
+#[get("/rooms/<id>/messages/<message_id>/edits")]
+pub async fn room_message_edits_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    message_id: Uuid,
+) -> ApiResponse {
+    let (_, role, message) =
+        match message_for(&mut conn, &caller, id, message_id, Action::View).await {
+            Ok(found) => found,
+            Err(response) => return response,
+        };
+    if message.deleted_at.is_some()
+        && message.user_id != caller.id
+        && !role.allows(Action::Moderate)
+    {
+        return deleted();
+    }
+    match db::get_message_edits(&mut conn, message_id).await {
+        Ok(edits) => ApiResponse::ok(edits),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Delete a message. Authors can delete their own; deleting anyone else's is
+/// moderation and goes in the room's log.
+#[delete("/rooms/<id>/messages/<message_id>")]
+pub async fn room_message_remove(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    message_id: Uuid,
+) -> ApiResponse {
+    let message = match message_for(&mut conn, &caller, id, message_id, Action::View).await {
+        Ok((_, _, message)) => message,
+        Err(response) => return response,
+    };
+    if message.deleted_at.is_some() {
+        return ApiResponse::not_found();
+    }
+    // Looking the message up only needed View; deleting it is a change to the
+    // room, which archived rooms don't allow
+    let action = if message.user_id == caller.id {
+        Action::Post
+    } else {
+        Action::Moderate
+    };
+    if let Err(response) = room_for(&mut conn, &caller, id, action).await {
+        return response;
+    }
+    if message.user_id == caller.id {
+        return match db::delete_message(&mut conn, id, message_id, caller.id).await {
+            Ok(0) => ApiResponse::not_found(),
+            Ok(_) => {
+                hub.broadcast(id, Event::MessageDeleted { message_id });
+                ApiResponse::ok(json!({"message_id": message_id}))
+            }
+            Err(_) => ApiResponse::internal_error(),
+        };
+    }
+    let record = ModerationRecord {
+        room_id: id,
+        moderator_id: caller.id,
+        user_id: None,
+        reason: None,
+        expires_at: None,
+    };
+    match db::moderate_message(&mut conn, &record, message_id).await {
+        Ok(Some(entry)) => {
+            hub.broadcast(id, Event::MessageDeleted { message_id });
+            announce(hub, entry)
+        }
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Tell everyone in a room who has reacted to a message now
+async fn reactions_changed(
+    conn: &mut PgConnection,
+    hub: &Hub,
+    id: Uuid,
+    message_id: Uuid,
+) -> ApiResponse {
+    match db::get_message(conn, id, message_id).await {
+        Ok(Some(message)) => {
+            let reactions = message.reactions.0;
+            hub.broadcast(
+                id,
+                Event::ReactionsChanged {
+                    message_id,
+                    reactions: reactions.clone(),
+                },
+            );
+            ApiResponse::ok(reactions)
+        }
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[put("/rooms/<id>/messages/<message_id>/reactions/<emoji>")]
+pub async fn room_message_react(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    message_id: Uuid,
+    emoji: &str,
+) -> ApiResponse {
+    if let Err(response) = validate_emoji(emoji) {
+        return response;
+    }
+    let (room, role, message) =
+        match message_for(&mut conn, &caller, id, message_id, Action::Post).await {
+            Ok(found) => found,
+            Err(response) => return response,
+        };
+    if message.deleted_at.is_some() {
+        return deleted();
+    }
+    if let Err(response) = check_not_muted(&mut conn, &caller, &room, role).await {
+        return response;
+    }
+    match db::add_reaction(&mut conn, message_id, caller.id, emoji).await {
+        Ok(0) => ApiResponse::ok(message.reactions.0),
+        Ok(_) => reactions_changed(&mut conn, hub, id, message_id).await,
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[delete("/rooms/<id>/messages/<message_id>/reactions/<emoji>")]
+pub async fn room_message_unreact(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    message_id: Uuid,
+    emoji: &str,
+) -> ApiResponse {
+    if let Err(response) = message_for(&mut conn, &caller, id, message_id, Action::Post).await {
+        return response;
+    }
+    match db::remove_reaction(&mut conn, message_id, caller.id, emoji).await {
+        Ok(0) => ApiResponse::not_found(),
+        Ok(_) => reactions_changed(&mut conn, hub, id, message_id).await,
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mention(id: Uuid) -> String {
+        format!("<@{}>", id)
+    }
+
+    #[test]
+    fn mentioned_ids_in_order() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let body = format!("{} and {}, have a look", mention(a), mention(b));
+        assert_eq!(mentioned_ids(&body).unwrap(), [a, b]);
+        assert!(mentioned_ids("nobody in particular").unwrap().is_empty());
+    }
+
+    #[test]
+    fn mentioned_ids_skip_malformed_mentions() {
+        let id = Uuid::new_v4();
+        for body in [
+            String::from("<@>"),
+            String::from("<@everyone>"),
+            String::from("<@1234>"),
+            format!("<@ {}>", id),
+            format!("<@{}x>", id),
+            format!("<{}>", id),
+            format!("<@{}", id),
+            format!("@{}", id),
+        ] {
+            assert!(mentioned_ids(&body).unwrap().is_empty(), "{}", body);
+        }
+        // A stray `<@` doesn't swallow the mention after it
+        let body = format!("a <@ b {}", mention(id));
+        assert_eq!(mentioned_ids(&body).unwrap(), [id]);
+        let body = format!("<@{}", mention(id));
+        assert_eq!(mentioned_ids(&body).unwrap(), [id]);
+    }
+
+    #[test]
+    fn mentioned_ids_count_duplicates_once() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let body = format!("{0} {1} {0} {0}", mention(a), mention(b));
+        assert_eq!(mentioned_ids(&body).unwrap(), [a, b]);
+        // The same id written in capitals
+        let body = format!("{} {}", mention(a), mention(a).to_uppercase());
+        assert_eq!(mentioned_ids(&body).unwrap(), [a]);
+    }
+
+    #[test]
+    fn mentioned_ids_between_adjacent_mentions() {
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let body = format!("{}{}{}", mention(a), mention(b), mention(c));
+        assert_eq!(mentioned_ids(&body).unwrap(), [a, b, c]);
+    }
+
+    #[test]
+    fn mentioned_ids_are_capped() {
+        let ids: Vec<Uuid> = (0..=MAX_MENTIONS).map(|_| Uuid::new_v4()).collect();
+        let body: String = ids[..MAX_MENTIONS].iter().copied().map(mention).collect();
+        assert_eq!(mentioned_ids(&body).unwrap(), ids[..MAX_MENTIONS]);
+        // Mentioning the same ones again doesn't count against the cap
+        let again = format!("{}{}", body, body);
+        assert_eq!(mentioned_ids(&again).unwrap(), ids[..MAX_MENTIONS]);
+        let over = format!("{}{}", body, mention(ids[MAX_MENTIONS]));
+        let response = mentioned_ids(&over).unwrap_err();
+        assert_eq!(response.status, Status::BadRequest);
+    }
+}
@@ -0,0 +1,305 @@
+pub mod activity;
+pub mod attachments;
+pub mod breakouts;
+pub mod direct;
+pub mod invites;
+pub mod lobby;
+pub mod members;
+pub mod messages;
+pub mod moderation;
+pub mod realtime;
+pub mod rooms;
+pub mod schedule;
+pub mod search;
+pub mod signaling;
+pub mod spatial;
+
+use super::access;
+use super::access::Action;
+use super::db;
+use super::realtime::Hub;
+use super::users::Caller;
+use rocket::http::ContentType;
+use rocket::http::Status;
+use rocket::response;
+use rocket::response::{Responder, Response};
+use rocket::serde::json::json;
+use rocket::serde::json::Value;
+use rocket::Request;
+use sqlx::PgConnection;
+use std::cmp;
+use uuid::Uuid;
+
+const DEFAULT_PAGE: i32 = 1;
+const DEFAULT_PER_PAGE: i32 = 10;
+
+#[derive(Debug)]
+pub struct ApiResponse {
+    json: Value,
+    status: Status,
+}
+
+impl<'r> Responder<'r, 'static> for ApiResponse {
+    fn respond_to(self, req: &Request) -> response::Result<'static> {
+        Response::build_from(self.json.respond_to(req).unwrap())
+            .status(self.status)
+            .header(ContentType::JSON)
+            .ok()
+    }
+}
+
+impl ApiResponse {
+    fn ok(result: impl serde::Serialize) -> Self {
+        ApiResponse {
+            json: json!({ "result": result }),
+            status: Status::Ok,
+        }
+    }
+
+    fn created(result: impl serde::Serialize) -> Self {
+        ApiResponse {
+            json: json!({ "result": result }),
+            status: Status::Created,
+        }
+    }
+
+    fn error(status: Status, short: &str, long: &str) -> Self {
+        ApiResponse {
+            json: json!({"error": {"short": short, "long": long}}),
+            status,
+        }
+    }
+
+    fn bad_request(long: &str) -> Self {
+        Self::error(Status::BadRequest, "BadRequest", long)
+    }
+
+    fn forbidden() -> Self {
+        Self::error(Status::Forbidden, "Forbidden", "operation not permitted")
+    }
+
+    fn not_found() -> Self {
+        Self::error(Status::NotFound, "NotFound", "resource not found")
+    }
+
+    fn internal_error() -> Self {
+        Self::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "something went wrong",
+        )
+    }
+}
+
+/// Clamp the requested page and page size to sane bounds given the total
+/// number of items, returning `(page, per_page, total_pages)`
+fn paginate(page: Option<i32>, per_page: Option<i32>, count: i64) -> (i32, i32, i32) {
+    let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(10, 100);
+    let total_pages = cmp::max(1, (count + i64::from(per_page) - 1) / i64::from(per_page));
+    let total_pages = i32::try_from(total_pages).unwrap_or(i32::MAX);
+    let page = page.unwrap_or(DEFAULT_PAGE).clamp(1, total_pages);
+    (page, per_page, total_pages)
+}
+
+/// Look up a room and check that the caller's Role in it allows `action`.
+/// Rooms the caller can't see at all are reported as missing, archived rooms
+/// can only be viewed, and users banned from a room can't do anything there.
+/// Breakout rooms take their Roles from the parent, and only the users sent
+/// to one may take part in it unless they can moderate.
+async fn room_for(
+    conn: &mut PgConnection,
+    caller: &Caller,
+    id: Uuid,
+    action: Action,
+) -> Result<(db::responses::Room, db::responses::Role), ApiResponse> {
+    let room = match db::get_room(conn, id).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return Err(ApiResponse::not_found()),
+        Err(_) => return Err(ApiResponse::internal_error()),
+    };
+    let parent = match room.parent_id {
+        Some(parent_id) => match db::get_room(conn, parent_id).await {
+            Ok(Some(parent)) => Some(parent),
+            Ok(None) => return Err(ApiResponse::not_found()),
+            Err(_) => return Err(ApiResponse::internal_error()),
+        },
+        None => None,
+    };
+    let scope = parent.as_ref().unwrap_or(&room);
+    let roles = db::get_roles(conn, scope.id, caller.id, &caller.groups)
+        .await
+        .map_err(|_| ApiResponse::internal_error())?;
+    let role = match access::effective_role(caller, scope, &roles) {
+        Some(role) => role,
+        None => return Err(ApiResponse::not_found()),
+    };
+    if !role.allows(action) {
+        return Err(ApiResponse::forbidden());
+    }
+    if !role.allows(Action::Moderate) {
+        let banned = db::is_banned(conn, &[room.id, scope.id], caller.id)
+            .await
+            .map_err(|_| ApiResponse::internal_error())?;
+        if banned {
+            return Err(ApiResponse::error(
+                Status::Forbidden,
+                "Banned",
+                "you have been banned from the room",
+            ));
+        }
+    }
+    if parent.is_some() && action != Action::View && !role.allows(Action::Moderate) {
+        let assigned = db::is_assigned(conn, id, caller.id)
+            .await
+            .map_err(|_| ApiResponse::internal_error())?;
+        if !assigned {
+            return Err(ApiResponse::forbidden());
+        }
+    }
+    if room.archived_at.is_some() && action != Action::View {
+        return Err(ApiResponse::error(
+            Status::Conflict,
+            "Archived",
+            "the room has been archived",
+        ));
+    }
+    Ok((room, role))
+}
+
+/// Check that a session is connected to a room and belongs to the caller
+fn own_session(hub: &Hub, caller: &Caller, room_id: Uuid, session_id: Uuid) -> bool {
+    hub.session_user(room_id, session_id) == Some(caller.id)
+}
+
+/// Whether the caller may change another user's direct Role in a room, or
+/// moderate them. Moderators may act on members and guests; only owners may
+/// act on moderators.
+async fn can_manage_member(
+    conn: &mut PgConnection,
+    caller_role: db::responses::Role,
+    room_id: Uuid,
+    user_id: Uuid,
+) -> Result<bool, ApiResponse> {
+    if caller_role.allows(Action::Manage) {
+        return Ok(true);
+    }
+    let current = db::get_roles(conn, room_id, user_id, &[])
+        .await
+        .map_err(|_| ApiResponse::internal_error())?;
+    Ok(current
+        .iter()
+        .all(|role| *role < db::responses::Role::Moderator))
+}
+
+/// Check that the caller hasn't been muted in a room, which stops them posting
+/// or publishing media. Moderators can't be muted.
+async fn check_not_muted(
+    conn: &mut PgConnection,
+    caller: &Caller,
+    room: &db::responses::Room,
+    role: db::responses::Role,
+) -> Result<(), ApiResponse> {
+    if role.allows(Action::Moderate) {
+        return Ok(());
+    }
+    let rooms: Vec<Uuid> = room.parent_id.into_iter().chain([room.id]).collect();
+    match db::is_muted(conn, &rooms, caller.id).await {
+        Ok(false) => Ok(()),
+        Ok(true) => Err(ApiResponse::error(
+            Status::Forbidden,
+            "Muted",
+            "you have been muted in the room",
+        )),
+        Err(_) => Err(ApiResponse::internal_error()),
+    }
+}
+
+/// Guests can only reach the room they were let into, so turn them away from
+/// anything that isn't about one room
+fn check_not_guest(caller: &Caller) -> Result<(), ApiResponse> {
+    match caller.guest {
+        Some(_) => Err(ApiResponse::forbidden()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, Events, User};
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rocket::serde::json::json;
+    use uuid::Uuid;
+
+    /// Create a public room owned by `owner` that guests may visit
+    async fn guest_room(client: &Client, owner: &User) -> Uuid {
+        let room_id = testing::create_room(client, owner, "public").await;
+        let uri = format!("/rooms/{}", room_id);
+        let (status, _) = testing::put(client, owner, uri, json!({"guest_access": true})).await;
+        assert_eq!(status, Status::Ok);
+        room_id
+    }
+
+    #[rocket::async_test]
+    async fn guests_only_reach_their_own_room_as_guests() {
+        let client = testing::client().await;
+        let owner = User::new();
+        let room_id = guest_room(&client, &owner).await;
+        let other_room_id = guest_room(&client, &owner).await;
+        let guest = User::guest(room_id);
+
+        let (status, _) = testing::get(&client, &guest, format!("/rooms/{}", room_id)).await;
+        assert_eq!(status, Status::Ok);
+        let (_events, _) = Events::join_session(&client, &guest, room_id).await;
+        let message = json!({"body": "hello"});
+        let uri = format!("/rooms/{}/messages", room_id);
+        let (status, _) = testing::post(&client, &guest, uri, message.clone()).await;
+        assert_eq!(status, Status::Forbidden);
+
+        let uri = format!("/rooms/{}", other_room_id);
+        let (status, _) = testing::get(&client, &guest, uri).await;
+        assert_eq!(status, Status::NotFound);
+        let uri = format!("/rooms/{}/events", other_room_id);
+        let (status, _) = testing::get(&client, &guest, uri).await;
+        assert_eq!(status, Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn guests_are_turned_away_without_guest_access() {
+        let client = testing::client().await;
+        let owner = User::new();
+        let room_id = testing::create_room(&client, &owner, "public").await;
+        let guest = User::guest(room_id);
+
+        let (status, _) = testing::get(&client, &guest, format!("/rooms/{}", room_id)).await;
+        assert_eq!(status, Status::NotFound);
+        let uri = format!("/rooms/{}/events", room_id);
+        let (status, _) = testing::get(&client, &guest, uri).await;
+        assert_eq!(status, Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn guests_cannot_use_what_needs_an_account() {
+        let client = testing::client().await;
+        let owner = User::new();
+        let room_id = guest_room(&client, &owner).await;
+        let guest = User::guest(room_id);
+
+        let uris = [
+            "/rooms",
+            "/search?q=hello",
+            "/direct",
+            "/unread",
+            "/mentions",
+            "/calendar/feed",
+        ];
+        for uri in uris {
+            let (status, _) = testing::get(&client, &guest, String::from(uri)).await;
+            assert_eq!(status, Status::Forbidden, "{}", uri);
+        }
+        let message = json!({"user_ids": [owner.id], "body": "hello"});
+        let uri = String::from("/direct/messages");
+        let (status, _) = testing::post(&client, &guest, uri, message).await;
+        assert_eq!(status, Status::Forbidden);
+    }
+}
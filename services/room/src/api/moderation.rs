@@ -0,0 +1,380 @@
+use super::{can_manage_member, paginate, room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::db::requests::{ModerationRequest, SlowModeRequest};
+use crate::db::responses::{ModerationEntry, Room};
+use crate::db::ModerationRecord;
+use crate::realtime::events::Event;
+use crate::realtime::Hub;
+use crate::users::Caller;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::State;
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+
+const MAX_RESTRICTION: i64 = 365 * 24 * 60 * 60;
+const MAX_SLOW_MODE: i32 = 60 * 60;
+const MAX_REASON_LENGTH: usize = 500;
+
+/// Check that the caller may moderate a room and act on one of its users,
+/// returning the room. Nobody can act on the room's owner or themselves, and
+/// only owners can act on moderators.
+async fn moderate_user(
+    conn: &mut PgConnection,
+    caller: &Caller,
+    id: Uuid,
+    user_id: Uuid,
+) -> Result<Room, ApiResponse> {
+    let (room, role) = room_for(conn, caller, id, Action::Moderate).await?;
+    if user_id == caller.id || user_id == room.owner_id {
+        return Err(ApiResponse::bad_request(
+            "you can't moderate yourself or the room's owner",
+        ));
+    }
+    let scope = room.parent_id.unwrap_or(room.id);
+    match can_manage_member(conn, role, scope, user_id).await? {
+        true => Ok(room),
+        false => Err(ApiResponse::forbidden()),
+    }
+}
+
+fn validate_reason(reason: Option<&str>) -> Result<(), ApiResponse> {
+    match reason {
+        Some(reason) if reason.chars().count() > MAX_REASON_LENGTH => Err(
+            ApiResponse::bad_request("reason must be at most 500 characters"),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Check a moderation request, returning when a ban or mute would end
+fn validate(request: &ModerationRequest) -> Result<Option<NaiveDateTime>, ApiResponse> {
+    validate_reason(request.reason.as_deref())?;
+    match request.duration {
+        Some(duration) if !(1..=MAX_RESTRICTION).contains(&duration) => Err(
+            ApiResponse::bad_request("duration must be between 1 second and 365 days"),
+        ),
+        Some(duration) => Ok(Some((Utc::now() + Duration::seconds(duration)).naive_utc())),
+        None => Ok(None),
+    }
+}
+
+/// Tell everyone in a room what a moderator did
+pub(super) fn announce(hub: &Hub, entry: ModerationEntry) -> ApiResponse {
+    hub.broadcast(
+        entry.room_id,
+        Event::Moderation {
+            entry: entry.clone(),
+        },
+    );
+    ApiResponse::ok(entry)
+}
+
+/// Disconnect a user from a room and any of its breakout rooms
+async fn kick_everywhere(
+    conn: &mut PgConnection,
+    hub: &Hub,
+    room_id: Uuid,
+    user_id: Uuid,
+    reason: Option<String>,
+) {
+    hub.kick(room_id, user_id, reason.clone());
+    if let Ok(breakouts) = db::get_breakouts(conn, room_id).await {
+        for breakout in breakouts {
+            hub.kick(breakout.room.id, user_id, reason.clone());
+        }
+    }
+}
+
+#[get("/rooms/<id>/moderation?<page>&<per_page>")]
+pub async fn room_moderation_log(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    page: Option<i32>,
+    per_page: Option<i32>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Moderate).await {
+        return response;
+    }
+    let count = db::get_moderation_log_count(&mut conn, id)
+        .await
+        .unwrap_or(0);
+    let (resolved_page, resolved_per_page, total_pages) = paginate(page, per_page, count);
+    match db::get_moderation_log(&mut conn, id, resolved_page, resolved_per_page, total_pages).await
+    {
+        Ok(entries) => ApiResponse {
+            json: entries,
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[get("/rooms/<id>/bans")]
+pub async fn room_bans_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Moderate).await {
+        return response;
+    }
+    match db::get_bans(&mut conn, id).await {
+        Ok(bans) => ApiResponse::ok(bans),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Ban a user from a room for `duration` seconds, or until the ban is lifted,
+/// disconnecting them if they're there
+#[put("/rooms/<id>/bans/<user_id>", format = "json", data = "<request>")]
+pub async fn room_ban_set(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    user_id: Uuid,
+    request: Json<ModerationRequest>,
+) -> ApiResponse {
+    if let Err(response) = moderate_user(&mut conn, &caller, id, user_id).await {
+        return response;
+    }
+    let expires_at = match validate(&request) {
+        Ok(expires_at) => expires_at,
+        Err(response) => return response,
+    };
+    let record = ModerationRecord {
+        room_id: id,
+        moderator_id: caller.id,
+        user_id: Some(user_id),
+        reason: request.reason.as_deref(),
+        expires_at,
+    };
+    match db::ban_user(&mut conn, &record).await {
+        Ok(entry) => {
+            kick_everywhere(&mut conn, hub, id, user_id, request.reason.clone()).await;
+            announce(hub, entry)
+        }
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[delete("/rooms/<id>/bans/<user_id>")]
+pub async fn room_ban_remove(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    user_id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = moderate_user(&mut conn, &caller, id, user_id).await {
+        return response;
+    }
+    let record = ModerationRecord {
+        room_id: id,
+        moderator_id: caller.id,
+        user_id: Some(user_id),
+        reason: None,
+        expires_at: None,
+    };
+    match db::unban_user(&mut conn, &record).await {
+        Ok(Some(entry)) => announce(hub, entry),
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[get("/rooms/<id>/mutes")]
+pub async fn room_mutes_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Moderate).await {
+        return response;
+    }
+    match db::get_mutes(&mut conn, id).await {
+        Ok(mutes) => ApiResponse::ok(mutes),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Stop a user posting or publishing media in a room for `duration` seconds,
+/// or until they're unmuted. Anything they're publishing is stopped.
+#[put("/rooms/<id>/mutes/<user_id>", format = "json", data = "<request>")]
+pub async fn room_mute_set(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    user_id: Uuid,
+    request: Json<ModerationRequest>,
+) -> ApiResponse {
+    if let Err(response) = moderate_user(&mut conn, &caller, id, user_id).await {
+        return response;
+    }
+    let expires_at = match validate(&request) {
+        Ok(expires_at) => expires_at,
+        Err(response) => return response,
+    };
+    let record = ModerationRecord {
+        room_id: id,
+        moderator_id: caller.id,
+        user_id: Some(user_id),
+        reason: request.reason.as_deref(),
+        expires_at,
+    };
+    match db::mute_user(&mut conn, &record).await {
+        Ok(entry) => {
+            for session_id in hub.sessions_of(id, user_id) {
+                hub.set_media(id, session_id, Vec::new());
+            }
+            announce(hub, entry)
+        }
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[delete("/rooms/<id>/mutes/<user_id>")]
+pub async fn room_mute_remove(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    user_id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = moderate_user(&mut conn, &caller, id, user_id).await {
+        return response;
+    }
+    let record = ModerationRecord {
+        room_id: id,
+        moderator_id: caller.id,
+        user_id: Some(user_id),
+        reason: None,
+        expires_at: None,
+    };
+    match db::unmute_user(&mut conn, &record).await {
+        Ok(Some(entry)) => announce(hub, entry),
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Disconnect a user from a room. Unlike a ban, they can come straight back.
+#[post("/rooms/<id>/kicks/<user_id>", format = "json", data = "<request>")]
+pub async fn room_kick(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    user_id: Uuid,
+    request: Json<ModerationRequest>,
+) -> ApiResponse {
+    if let Err(response) = moderate_user(&mut conn, &caller, id, user_id).await {
+        return response;
+    }
+    if let Err(response) = validate(&request) {
+        return response;
+    }
+    if hub.sessions_of(id, user_id).is_empty() {
+        return ApiResponse::not_found();
+    }
+    let record = ModerationRecord {
+        room_id: id,
+        moderator_id: caller.id,
+        user_id: Some(user_id),
+        reason: request.reason.as_deref(),
+        expires_at: None,
+    };
+    match db::log_kick(&mut conn, &record).await {
+        Ok(entry) => {
+            kick_everywhere(&mut conn, hub, id, user_id, request.reason.clone()).await;
+            announce(hub, entry)
+        }
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Make members wait between messages, or let them post freely again
+#[put("/rooms/<id>/slow_mode", format = "json", data = "<request>")]
+pub async fn room_slow_mode_set(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    request: Json<SlowModeRequest>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Moderate).await {
+        return response;
+    }
+    if !(0..=MAX_SLOW_MODE).contains(&request.seconds) {
+        return ApiResponse::bad_request("seconds must be between 0 and 3600");
+    }
+    if let Err(response) = validate_reason(request.reason.as_deref()) {
+        return response;
+    }
+    let record = ModerationRecord {
+        room_id: id,
+        moderator_id: caller.id,
+        user_id: None,
+        reason: request.reason.as_deref(),
+        expires_at: None,
+    };
+    match db::set_slow_mode(&mut conn, &record, request.seconds).await {
+        Ok(entry) => announce(hub, entry),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, Events, User};
+    use rocket::http::{ContentType, Status};
+    use rocket::serde::json::json;
+
+    #[rocket::async_test]
+    async fn bans_keep_users_out_until_they_expire() {
+        let client = testing::client().await;
+        let (owner, banned) = (User::new(), User::new());
+        let room_id = testing::create_room(&client, &owner, "public").await;
+        let response = client
+            .put(format!("/rooms/{}/bans/{}", room_id, banned.id))
+            .header(ContentType::JSON)
+            .header(owner.authorization())
+            .body(json!({"reason": "spam", "duration": 60 * 60}).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let events = format!("/rooms/{}/events", room_id);
+        let messages = format!("/rooms/{}/messages", room_id);
+        let message = json!({"body": "hello"});
+
+        let (status, error) = testing::get(&client, &banned, events).await;
+        assert_eq!(status, Status::Forbidden);
+        assert_eq!(error["error"]["short"], "Banned");
+        let (status, error) = testing::get(&client, &banned, messages.clone()).await;
+        assert_eq!(status, Status::Forbidden);
+        assert_eq!(error["error"]["short"], "Banned");
+        let (status, error) =
+            testing::post(&client, &banned, messages.clone(), message.clone()).await;
+        assert_eq!(status, Status::Forbidden);
+        assert_eq!(error["error"]["short"], "Banned");
+
+        sqlx::query(
+            r#"UPDATE public."RoomBan" SET expires_at = '2000-01-01'
+            WHERE room_id = $1 AND user_id = $2"#,
+        )
+        .bind(room_id)
+        .bind(banned.id)
+        .execute(testing::database(&client))
+        .await
+        .unwrap();
+        let _events = Events::join_session(&client, &banned, room_id).await;
+        let (status, _) = testing::post(&client, &banned, messages, message).await;
+        assert_eq!(status, Status::Created);
+    }
+}
@@ -0,0 +1,194 @@
+use super::lobby::check_entry;
+use super::{room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::realtime::{Cursor, Hub};
+use crate::users::Caller;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::stream::{Event as StreamEvent, EventStream};
+use rocket::serde::uuid::Uuid;
+use rocket::tokio::select;
+use rocket::Shutdown;
+use rocket::State;
+use rocket_db_pools::Connection;
+use std::convert::Infallible;
+
+/// How many of the latest messages a client that can't pick up where it left
+/// off is sent
+const SNAPSHOT_MESSAGES: i64 = 50;
+
+/// The `Last-Event-ID` header browsers send when reconnecting an event stream
+pub struct LastEventId(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let id = req.headers().get_one("Last-Event-ID").map(str::to_string);
+        request::Outcome::Success(LastEventId(id))
+    }
+}
+
+/// Join a room, streaming its events until the client disconnects. Every
+/// event has an id saying where it is in the room's events; a client that
+/// reconnects with the last one it saw, as `Last-Event-ID` or `since`, is sent
+/// the events it missed. If too many have happened since, the room has been
+/// empty for a while or the client has reconnected to another instance of the
+/// service, it is sent the latest messages with the room's presence instead.
+/// Rooms that are locked or full have to be knocked on first.
+#[get("/rooms/<id>/events?<since>")]
+pub async fn room_events(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    mut end: Shutdown,
+    last_event_id: LastEventId,
+    id: Uuid,
+    since: Option<String>,
+) -> Result<EventStream![], ApiResponse> {
+    let (room, role) = room_for(&mut conn, &caller, id, Action::Join).await?;
+    check_entry(&mut conn, hub, &caller, &room, role).await?;
+    let map = db::get_room_map(&mut conn, id)
+        .await
+        .map_err(|_| ApiResponse::internal_error())?;
+    let since = last_event_id.0.or(since);
+    let cursor = since
+        .as_deref()
+        .and_then(|since| since.parse::<Cursor>().ok());
+    let guest_name = caller.guest.as_ref().map(|guest| guest.name.clone());
+    let mut session = hub.join(id, caller.id, guest_name, map, cursor);
+    let messages = if since.is_some() && !session.resumed() {
+        db::get_messages(&mut conn, id, None, None, SNAPSHOT_MESSAGES)
+            .await
+            .map_err(|_| ApiResponse::internal_error())?
+    } else {
+        Vec::new()
+    };
+    let missed = session.take_missed();
+    Ok(EventStream! {
+        yield StreamEvent::json(&session.presence(messages)).id(session.cursor().to_string());
+        for event in missed {
+            yield StreamEvent::json(&event).id(session.cursor_at(&event).to_string());
+        }
+        loop {
+            let event = select! {
+                event = session.recv() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                _ = &mut end => break,
+            };
+            yield StreamEvent::json(&event).id(session.cursor_at(&event).to_string());
+        }
+    })
+}
+
+#[get("/rooms/<id>/participants")]
+pub async fn room_participants(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+) -> ApiResponse {
+    match room_for(&mut conn, &caller, id, Action::View).await {
+        Ok(_) => ApiResponse::ok(hub.participants(id)),
+        Err(response) => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, Events, User};
+    use rocket::http::Status;
+    use rocket::serde::json::json;
+
+    #[rocket::async_test]
+    async fn participants_see_each_other_join_and_leave() {
+        let client = testing::client().await;
+        let (a, b) = (User::new(), User::new());
+        let room_id = testing::create_room(&client, &a, "public").await;
+
+        let mut a_events = Events::join(&client, &a, room_id).await;
+        let presence = a_events.next().await;
+        assert_eq!(presence["type"], "presence");
+        assert_eq!(presence["participants"][0]["user_id"], json!(a.id));
+        assert_eq!(presence["participants"].as_array().unwrap().len(), 1);
+
+        let mut b_events = Events::join(&client, &b, room_id).await;
+        let presence = b_events.next().await;
+        assert_eq!(presence["type"], "presence");
+        let present: Vec<_> = presence["participants"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|participant| participant["user_id"].clone())
+            .collect();
+        assert_eq!(present, [json!(a.id), json!(b.id)]);
+
+        let joined = a_events.next().await;
+        assert_eq!(joined["type"], "joined");
+        assert_eq!(joined["participant"]["user_id"], json!(b.id));
+        assert_eq!(
+            testing::participants(&client, &a, room_id).await,
+            [json!(a.id), json!(b.id)]
+        );
+
+        drop(b_events);
+        let left = a_events.next().await;
+        assert_eq!(left["type"], "left");
+        assert_eq!(left["participant"]["user_id"], json!(b.id));
+        assert_eq!(
+            testing::participants(&client, &a, room_id).await,
+            [json!(a.id)]
+        );
+
+        drop(a_events);
+        assert!(testing::participants(&client, &a, room_id).await.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn broadcasts_reach_everyone_in_the_room() {
+        let client = testing::client().await;
+        let (a, b) = (User::new(), User::new());
+        let room_id = testing::create_room(&client, &a, "public").await;
+        let (mut a_events, _) = Events::join_session(&client, &a, room_id).await;
+        let (mut b_events, _) = Events::join_session(&client, &b, room_id).await;
+        assert_eq!(a_events.next().await["type"], "joined");
+
+        let (status, _) = testing::post(
+            &client,
+            &a,
+            format!("/rooms/{}/messages", room_id),
+            json!({"body": "hello"}),
+        )
+        .await;
+        assert_eq!(status, Status::Created);
+        for events in [&mut a_events, &mut b_events] {
+            let message = events.next().await;
+            assert_eq!(message["type"], "message");
+            assert_eq!(message["message"]["body"], "hello");
+            assert_eq!(message["message"]["user_id"], json!(a.id));
+        }
+    }
+
+    #[rocket::async_test]
+    async fn only_members_join_a_private_room() {
+        let client = testing::client().await;
+        let (owner, member, outsider) = (User::new(), User::new(), User::new());
+        let room_id = testing::create_room(&client, &owner, "private").await;
+        testing::add_member(&client, &owner, room_id, &member, "member").await;
+
+        let response = client
+            .get(format!("/rooms/{}/events", room_id))
+            .header(outsider.authorization())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+        let (_events, _) = Events::join_session(&client, &member, room_id).await;
+        assert_eq!(
+            testing::participants(&client, &owner, room_id).await,
+            [json!(member.id)]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use super::{check_not_guest, paginate, room_for, ApiResponse};
+use crate::access::Action;
+use crate::breakouts;
+use crate::db;
+use crate::realtime::Hub;
+use crate::users::Caller;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::State;
+use rocket_db_pools::Connection;
+use sqlx::PgConnection;
+use std::collections::HashSet;
+
+#[get("/rooms?<page>&<per_page>")]
+pub async fn rooms_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    page: Option<i32>,
+    per_page: Option<i32>,
+) -> ApiResponse {
+    if let Err(response) = check_not_guest(&caller) {
+        return response;
+    }
+    let count = db::get_room_count(&mut conn, caller.id, &caller.groups)
+        .await
+        .unwrap_or(0);
+    let (resolved_page, resolved_per_page, total_pages) = paginate(page, per_page, count);
+    match db::get_rooms(
+        &mut conn,
+        caller.id,
+        &caller.groups,
+        resolved_page,
+        resolved_per_page,
+        total_pages,
+    )
+    .await
+    {
+        Ok(rooms) => ApiResponse {
+            json: rooms,
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[post("/rooms", format = "json", data = "<room>")]
+pub async fn rooms_add(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    room: Json<db::requests::RoomRequest>,
+) -> ApiResponse {
+    if let Err(response) = check_not_guest(&caller) {
+        return response;
+    }
+    if room.name.trim().is_empty() {
+        return ApiResponse::bad_request("room name must not be empty");
+    }
+    match db::create_room(&mut conn, caller.id, &room).await {
+        Ok(room) => ApiResponse::created(room),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[get("/rooms/<id>")]
+pub async fn room_show(caller: Caller, mut conn: Connection<db::RoomDb>, id: Uuid) -> ApiResponse {
+    match room_for(&mut conn, &caller, id, Action::View).await {
+        Ok((room, _)) => ApiResponse::ok(room),
+        Err(response) => response,
+    }
+}
+
+/// Disconnect every guest from a room and its breakout rooms
+async fn kick_guests(conn: &mut PgConnection, hub: &Hub, room_id: Uuid) {
+    let mut rooms = vec![room_id];
+    if let Ok(breakouts) = db::get_breakouts(conn, room_id).await {
+        rooms.extend(breakouts.into_iter().map(|breakout| breakout.room.id));
+    }
+    for room_id in rooms {
+        let guests: HashSet<Uuid> = hub
+            .participants(room_id)
+            .into_iter()
+            .filter(|participant| participant.guest_name.is_some())
+            .map(|participant| participant.user_id)
+            .collect();
+        for user_id in guests {
+            hub.kick(
+                room_id,
+                user_id,
+                Some(String::from("guests are no longer allowed in the room")),
+            );
+        }
+    }
+}
+
+/// Change a room. Turning guest access off disconnects any guests there.
+#[put("/rooms/<id>", format = "json", data = "<room>")]
+pub async fn room_update(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    room: Json<db::requests::RoomUpdateRequest>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Manage).await {
+        return response;
+    }
+    if room
+        .name
+        .as_deref()
+        .is_some_and(|name| name.trim().is_empty())
+    {
+        return ApiResponse::bad_request("room name must not be empty");
+    }
+    if room.capacity.is_some_and(|capacity| capacity < 0) {
+        return ApiResponse::bad_request("capacity must be a number of users, or 0 for no limit");
+    }
+    match db::update_room(&mut conn, id, &room).await {
+        Ok(Some(updated)) => {
+            if room.guest_access == Some(false) {
+                kick_guests(&mut conn, hub, id).await;
+            }
+            ApiResponse::ok(updated)
+        }
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Archive a room, closing any breakout rooms it has open
+#[post("/rooms/<id>/archive")]
+pub async fn room_archive(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Manage).await {
+        return response;
+    }
+    let room = match db::archive_room(&mut conn, id).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return ApiResponse::not_found(),
+        Err(_) => return ApiResponse::internal_error(),
+    };
+    match db::close_breakouts(&mut conn, id).await {
+        Ok(closed) => breakouts::announce_closed(hub, &closed),
+        Err(_) => return ApiResponse::internal_error(),
+    }
+    ApiResponse::ok(room)
+}
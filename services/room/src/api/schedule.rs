@@ -0,0 +1,300 @@
+use super::{check_not_guest, paginate, room_for, ApiResponse};
+use crate::access::Action;
+use crate::calendar;
+use crate::db;
+use crate::db::requests::ScheduledSessionRequest;
+use crate::users::{self, Caller};
+use chrono::NaiveDateTime;
+use rocket::http::{ContentType, Status};
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::serde::Serialize;
+use rocket_db_pools::Connection;
+
+const MAX_TITLE_LENGTH: usize = 200;
+const MAX_DESCRIPTION_LENGTH: usize = 4000;
+const MAX_DURATION: i32 = 24 * 60 * 60;
+const MAX_INVITEES: usize = 50;
+
+/// Where a calendar app can subscribe to a user's scheduled sessions. The
+/// path is on the room service, and anyone who has it can read the calendar.
+#[derive(Serialize, Debug)]
+#[serde(crate = "rocket::serde")]
+struct Subscription {
+    path: String,
+    created_at: NaiveDateTime,
+}
+
+impl From<db::responses::CalendarFeed> for Subscription {
+    fn from(feed: db::responses::CalendarFeed) -> Self {
+        Subscription {
+            path: format!("/calendar/{}/feed.ics", feed.token),
+            created_at: feed.created_at,
+        }
+    }
+}
+
+/// Check a session's details, returning its recurrence rule as calendars
+/// expect it
+fn validate_session(request: &ScheduledSessionRequest) -> Result<Option<String>, ApiResponse> {
+    let title = request.title.trim();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_LENGTH {
+        return Err(ApiResponse::bad_request(
+            "title must be between 1 and 200 characters",
+        ));
+    }
+    if request
+        .description
+        .as_deref()
+        .is_some_and(|description| description.chars().count() > MAX_DESCRIPTION_LENGTH)
+    {
+        return Err(ApiResponse::bad_request(
+            "description must be at most 4000 characters",
+        ));
+    }
+    if request.duration <= 0 || request.duration > MAX_DURATION {
+        return Err(ApiResponse::bad_request(
+            "duration must be between 1 second and 24 hours",
+        ));
+    }
+    if request.invitee_ids.len() > MAX_INVITEES {
+        return Err(ApiResponse::bad_request(
+            "a session can have at most 50 users and groups invited",
+        ));
+    }
+    match request.recurrence.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(rule) => match calendar::normalize_rule(rule) {
+            Some(rule) => Ok(Some(rule)),
+            None => Err(ApiResponse::bad_request(
+                "recurrence must be an RRULE such as FREQ=WEEKLY;BYDAY=MO,WE",
+            )),
+        },
+    }
+}
+
+/// Work out who is invited with the user service's help, returning the
+/// invitees and every user they reach. Every invitee must be a user or group
+/// it knows.
+async fn resolve_invitees(ids: &[Uuid]) -> Result<(Vec<Uuid>, Vec<Uuid>), ApiResponse> {
+    let mut ids = ids.to_vec();
+    ids.sort();
+    ids.dedup();
+    if ids.is_empty() {
+        return Ok((ids, Vec::new()));
+    }
+    let invitees = match users::resolve_mentions(&ids).await {
+        Ok(invitees) => invitees,
+        Err(_) => {
+            return Err(ApiResponse::error(
+                Status::ServiceUnavailable,
+                "Unavailable",
+                "invitees could not be looked up, try again later",
+            ))
+        }
+    };
+    if invitees.len() != ids.len() {
+        return Err(ApiResponse::bad_request(
+            "invitee_ids must all be users or groups",
+        ));
+    }
+    let mut user_ids: Vec<Uuid> = invitees
+        .into_iter()
+        .flat_map(|invitee| invitee.user_ids)
+        .collect();
+    user_ids.sort();
+    user_ids.dedup();
+    Ok((ids, user_ids))
+}
+
+#[get("/rooms/<id>/schedule?<page>&<per_page>")]
+pub async fn room_schedule_list(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    page: Option<i32>,
+    per_page: Option<i32>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::View).await {
+        return response;
+    }
+    let count = db::get_scheduled_session_count(&mut conn, id)
+        .await
+        .unwrap_or(0);
+    let (resolved_page, resolved_per_page, total_pages) = paginate(page, per_page, count);
+    match db::get_scheduled_sessions(&mut conn, id, resolved_page, resolved_per_page, total_pages)
+        .await
+    {
+        Ok(sessions) => ApiResponse {
+            json: sessions,
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Schedule a session in a room, once or repeating. Invited groups are
+/// expanded to their members as they are now.
+#[post("/rooms/<id>/schedule", format = "json", data = "<session>")]
+pub async fn room_schedule_add(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    session: Json<ScheduledSessionRequest>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Moderate).await {
+        return response;
+    }
+    let recurrence = match validate_session(&session) {
+        Ok(recurrence) => recurrence,
+        Err(response) => return response,
+    };
+    let (invitee_ids, user_ids) = match resolve_invitees(&session.invitee_ids).await {
+        Ok(resolved) => resolved,
+        Err(response) => return response,
+    };
+    let record = db::ScheduledSessionRecord {
+        room_id: id,
+        created_by: caller.id,
+        title: session.title.trim(),
+        description: session.description.as_deref().unwrap_or_default(),
+        starts_at: session.starts_at.naive_utc(),
+        duration: session.duration,
+        recurrence: recurrence.as_deref(),
+        invitee_ids: &invitee_ids,
+        user_ids: &user_ids,
+    };
+    match db::create_scheduled_session(&mut conn, &record).await {
+        Ok(session) => ApiResponse::created(session),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[get("/rooms/<id>/schedule/<session_id>")]
+pub async fn room_schedule_show(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    session_id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::View).await {
+        return response;
+    }
+    match db::get_scheduled_session(&mut conn, id, session_id).await {
+        Ok(Some(session)) => ApiResponse::ok(session),
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Change everything about a scheduled session, inviting people afresh
+#[put(
+    "/rooms/<id>/schedule/<session_id>",
+    format = "json",
+    data = "<session>"
+)]
+pub async fn room_schedule_update(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    session_id: Uuid,
+    session: Json<ScheduledSessionRequest>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Moderate).await {
+        return response;
+    }
+    let recurrence = match validate_session(&session) {
+        Ok(recurrence) => recurrence,
+        Err(response) => return response,
+    };
+    let (invitee_ids, user_ids) = match resolve_invitees(&session.invitee_ids).await {
+        Ok(resolved) => resolved,
+        Err(response) => return response,
+    };
+    let record = db::ScheduledSessionRecord {
+        room_id: id,
+        created_by: caller.id,
+        title: session.title.trim(),
+        description: session.description.as_deref().unwrap_or_default(),
+        starts_at: session.starts_at.naive_utc(),
+        duration: session.duration,
+        recurrence: recurrence.as_deref(),
+        invitee_ids: &invitee_ids,
+        user_ids: &user_ids,
+    };
+    match db::update_scheduled_session(&mut conn, session_id, &record).await {
+        Ok(Some(session)) => ApiResponse::ok(session),
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[delete("/rooms/<id>/schedule/<session_id>")]
+pub async fn room_schedule_remove(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+    session_id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Moderate).await {
+        return response;
+    }
+    match db::delete_scheduled_session(&mut conn, id, session_id).await {
+        Ok(Some(session)) => ApiResponse::ok(session),
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Where the caller's calendar app can subscribe to the sessions they're in
+#[get("/calendar/feed")]
+pub async fn calendar_feed_show(caller: Caller, mut conn: Connection<db::RoomDb>) -> ApiResponse {
+    if let Err(response) = check_not_guest(&caller) {
+        return response;
+    }
+    match db::get_calendar_feed(&mut conn, caller.id).await {
+        Ok(Some(feed)) => ApiResponse::ok(Subscription::from(feed)),
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Give the caller a calendar feed, or a new address for theirs if the old
+/// one has got out
+#[put("/calendar/feed")]
+pub async fn calendar_feed_reset(caller: Caller, mut conn: Connection<db::RoomDb>) -> ApiResponse {
+    if let Err(response) = check_not_guest(&caller) {
+        return response;
+    }
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    match db::reset_calendar_feed(&mut conn, caller.id, &token).await {
+        Ok(feed) => ApiResponse::ok(Subscription::from(feed)),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+#[delete("/calendar/feed")]
+pub async fn calendar_feed_remove(caller: Caller, mut conn: Connection<db::RoomDb>) -> ApiResponse {
+    if let Err(response) = check_not_guest(&caller) {
+        return response;
+    }
+    match db::delete_calendar_feed(&mut conn, caller.id).await {
+        Ok(Some(feed)) => ApiResponse::ok(Subscription::from(feed)),
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// A user's scheduled sessions as an iCalendar feed. Calendar apps can't send
+/// a token in a header, so the one in the path is all it takes.
+#[get("/calendar/<token>/feed.ics")]
+pub async fn calendar_feed(
+    mut conn: Connection<db::RoomDb>,
+    token: &str,
+) -> Result<(ContentType, String), ApiResponse> {
+    match db::get_calendar_entries(&mut conn, token).await {
+        Ok(Some(entries)) => Ok((ContentType::Calendar, calendar::feed(&entries))),
+        Ok(None) => Err(ApiResponse::not_found()),
+        Err(_) => Err(ApiResponse::internal_error()),
+    }
+}
@@ -0,0 +1,93 @@
+use super::{check_not_guest, paginate, room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::db::Search;
+use crate::users::Caller;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use rocket::http::Status;
+use rocket::serde::uuid::Uuid;
+use rocket_db_pools::Connection;
+
+const MAX_QUERY_LENGTH: usize = 200;
+
+/// Read a date like `2024-06-11`, meaning its start, or a time in RFC 3339
+fn parse_time(name: &str, value: Option<&str>) -> Result<Option<NaiveDateTime>, ApiResponse> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(Some(time.naive_utc()));
+    }
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => Ok(date.and_hms_opt(0, 0, 0)),
+        Err(_) => Err(ApiResponse::bad_request(&format!(
+            "{} must be a date or an RFC 3339 time",
+            name
+        ))),
+    }
+}
+
+/// Search the messages in every room the caller can see. `q` takes quoted
+/// phrases, `or` and `-word` like a web search engine. Results can be
+/// narrowed to one room, one author, and messages sent from `since` up to
+/// but not including `until`.
+#[allow(clippy::too_many_arguments)]
+#[get("/search?<q>&<room_id>&<user_id>&<since>&<until>&<page>&<per_page>")]
+pub async fn messages_search(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    q: &str,
+    room_id: Option<Uuid>,
+    user_id: Option<Uuid>,
+    since: Option<&str>,
+    until: Option<&str>,
+    page: Option<i32>,
+    per_page: Option<i32>,
+) -> ApiResponse {
+    if let Err(response) = check_not_guest(&caller) {
+        return response;
+    }
+    let query = q.trim();
+    if query.is_empty() || query.chars().count() > MAX_QUERY_LENGTH {
+        return ApiResponse::bad_request("q must be between 1 and 200 characters");
+    }
+    let (since, until) = match (parse_time("since", since), parse_time("until", until)) {
+        (Ok(since), Ok(until)) => (since, until),
+        (Err(response), _) | (_, Err(response)) => return response,
+    };
+    if let Some(room_id) = room_id {
+        if let Err(response) = room_for(&mut conn, &caller, room_id, Action::View).await {
+            return response;
+        }
+    }
+    let search = Search {
+        viewer_id: caller.id,
+        viewer_groups: &caller.groups,
+        is_superuser: caller.is_superuser,
+        query,
+        room_id,
+        user_id,
+        since,
+        until,
+    };
+    let count = match db::search_message_count(&mut conn, &search).await {
+        Ok(count) => count,
+        Err(_) => return ApiResponse::internal_error(),
+    };
+    let (resolved_page, resolved_per_page, total_pages) = paginate(page, per_page, count);
+    match db::search_messages(
+        &mut conn,
+        &search,
+        resolved_page,
+        resolved_per_page,
+        total_pages,
+    )
+    .await
+    {
+        Ok(hits) => ApiResponse {
+            json: hits,
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
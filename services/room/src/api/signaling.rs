@@ -0,0 +1,233 @@
+use super::{check_not_muted, own_session, room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::realtime::events::{Event, Signal};
+use crate::realtime::Hub;
+use crate::users::Caller;
+use rocket::http::Status;
+use rocket::serde::json::json;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::State;
+use rocket_db_pools::Connection;
+
+/// Generous enough for an SDP describing several tracks
+const MAX_SIGNAL_SIZE: usize = 64 * 1024;
+
+fn signal_size(signal: &Signal) -> usize {
+    match signal {
+        Signal::Offer { sdp } | Signal::Answer { sdp } => sdp.len(),
+        Signal::Candidate { candidate } => candidate.candidate.len(),
+    }
+}
+
+/// Relay an SDP offer or answer, or an ICE candidate, from one of the
+/// caller's sessions to another session in the same room
+#[post("/rooms/<id>/signals", format = "json", data = "<signal>")]
+pub async fn room_signal_send(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    signal: Json<db::requests::SignalRequest>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Join).await {
+        return response;
+    }
+    if !own_session(hub, &caller, id, signal.session_id) {
+        return ApiResponse::error(
+            Status::Conflict,
+            "NotJoined",
+            "session_id must be one of your sessions in the room",
+        );
+    }
+    if hub.session_user(id, signal.to).is_none() {
+        return ApiResponse::not_found();
+    }
+    if signal_size(&signal.signal) > MAX_SIGNAL_SIZE {
+        return ApiResponse::error(
+            Status::PayloadTooLarge,
+            "PayloadTooLarge",
+            "signal is too large",
+        );
+    }
+    let signal = signal.into_inner();
+    hub.broadcast(
+        id,
+        Event::Signal {
+            from: signal.session_id,
+            from_user: caller.id,
+            to: signal.to,
+            signal: signal.signal,
+        },
+    );
+    ApiResponse {
+        json: json!({"result": {"to": signal.to}}),
+        status: Status::Accepted,
+    }
+}
+
+/// Set what one of the caller's sessions is publishing. Publishing anything
+/// requires being allowed to post in the room and not being muted; anyone
+/// may stop.
+#[put(
+    "/rooms/<id>/sessions/<session_id>/media",
+    format = "json",
+    data = "<media>"
+)]
+pub async fn room_session_media(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    session_id: Uuid,
+    media: Json<db::requests::MediaRequest>,
+) -> ApiResponse {
+    let action = if media.media.is_empty() {
+        Action::Join
+    } else {
+        Action::Post
+    };
+    let (room, role) = match room_for(&mut conn, &caller, id, action).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    if action == Action::Post {
+        if let Err(response) = check_not_muted(&mut conn, &caller, &room, role).await {
+            return response;
+        }
+    }
+    if !own_session(hub, &caller, id, session_id) {
+        return ApiResponse::not_found();
+    }
+    let mut kinds = Vec::new();
+    for kind in media.into_inner().media {
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+    match hub.set_media(id, session_id, kinds) {
+        Some(participant) => ApiResponse::ok(participant),
+        None => ApiResponse::not_found(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, Events, User};
+    use rocket::http::Status;
+    use rocket::serde::json::{json, Value};
+    use rocket::serde::uuid::Uuid;
+
+    fn offer(from: Uuid, to: Uuid) -> Value {
+        json!({
+            "session_id": from,
+            "to": to,
+            "signal": {"kind": "offer", "sdp": "v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\n"},
+        })
+    }
+
+    #[rocket::async_test]
+    async fn signals_reach_only_the_session_they_are_for() {
+        let client = testing::client().await;
+        let (a, b) = (User::new(), User::new());
+        let room_id = testing::create_room(&client, &a, "public").await;
+        let (mut a_events, a_session) = Events::join_session(&client, &a, room_id).await;
+        let (mut b_events, b_session) = Events::join_session(&client, &b, room_id).await;
+        assert_eq!(a_events.next().await["type"], "joined");
+        let signals = format!("/rooms/{}/signals", room_id);
+
+        let (status, _) =
+            testing::post(&client, &a, signals.clone(), offer(a_session, b_session)).await;
+        assert_eq!(status, Status::Accepted);
+        let signal = b_events.next().await;
+        assert_eq!(signal["type"], "signal");
+        assert_eq!(signal["from"], json!(a_session));
+        assert_eq!(signal["from_user"], json!(a.id));
+        assert_eq!(signal["to"], json!(b_session));
+        assert_eq!(signal["signal"]["kind"], "offer");
+
+        let candidate = json!({
+            "session_id": a_session,
+            "to": b_session,
+            "signal": {
+                "kind": "candidate",
+                "candidate": {
+                    "candidate": "candidate:1 1 udp 2130706431 127.0.0.1 50000 typ host",
+                    "sdpMid": "0",
+                    "sdpMLineIndex": 0,
+                },
+            },
+        });
+        let (status, _) = testing::post(&client, &a, signals, candidate).await;
+        assert_eq!(status, Status::Accepted);
+        let signal = b_events.next().await;
+        assert_eq!(signal["signal"]["kind"], "candidate");
+        assert_eq!(signal["signal"]["candidate"]["sdpMLineIndex"], 0);
+
+        // A never hears its own signals; the next thing it hears is B leaving
+        drop(b_events);
+        assert_eq!(a_events.next().await["type"], "left");
+    }
+
+    #[rocket::async_test]
+    async fn signals_to_a_session_not_in_the_room_are_rejected() {
+        let client = testing::client().await;
+        let (a, b) = (User::new(), User::new());
+        let room_id = testing::create_room(&client, &a, "public").await;
+        let other_id = testing::create_room(&client, &b, "public").await;
+        let (_a_events, a_session) = Events::join_session(&client, &a, room_id).await;
+        let (_b_events, b_session) = Events::join_session(&client, &b, other_id).await;
+        let signals = format!("/rooms/{}/signals", room_id);
+
+        let (status, _) = testing::post(
+            &client,
+            &a,
+            signals.clone(),
+            offer(a_session, Uuid::new_v4()),
+        )
+        .await;
+        assert_eq!(status, Status::NotFound);
+        // B is connected, but to another room
+        let (status, _) = testing::post(&client, &a, signals, offer(a_session, b_session)).await;
+        assert_eq!(status, Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn signals_from_outside_the_room_are_rejected() {
+        let client = testing::client().await;
+        let (owner, member, outsider) = (User::new(), User::new(), User::new());
+        let room_id = testing::create_room(&client, &owner, "private").await;
+        testing::add_member(&client, &owner, room_id, &member, "member").await;
+        let (_owner_events, owner_session) = Events::join_session(&client, &owner, room_id).await;
+        let signals = format!("/rooms/{}/signals", room_id);
+
+        // Someone who isn't a member can't even see the room
+        let (status, _) = testing::post(
+            &client,
+            &outsider,
+            signals.clone(),
+            offer(owner_session, owner_session),
+        )
+        .await;
+        assert_eq!(status, Status::NotFound);
+        // A member has to send from a session of their own in the room
+        let (status, body) = testing::post(
+            &client,
+            &member,
+            signals.clone(),
+            offer(owner_session, owner_session),
+        )
+        .await;
+        assert_eq!(status, Status::Conflict);
+        assert_eq!(body["error"]["short"], "NotJoined");
+        let (status, _) = testing::post(
+            &client,
+            &member,
+            signals,
+            offer(Uuid::new_v4(), owner_session),
+        )
+        .await;
+        assert_eq!(status, Status::Conflict);
+    }
+}
@@ -0,0 +1,196 @@
+use super::{own_session, room_for, ApiResponse};
+use crate::access::Action;
+use crate::db;
+use crate::db::requests::MapRequest;
+use crate::db::responses::Point;
+use crate::realtime::Hub;
+use crate::users::Caller;
+use rocket::http::Status;
+use rocket::serde::json::json;
+use rocket::serde::json::Json;
+use rocket::serde::uuid::Uuid;
+use rocket::State;
+use rocket_db_pools::Connection;
+
+const MAX_MAP_SIZE: f64 = 10_000.0;
+const MAX_WALLS: usize = 500;
+const MAX_ZONES: usize = 100;
+const MAX_ZONE_NAME_LENGTH: usize = 100;
+const DEFAULT_EARSHOT: f64 = 150.0;
+
+fn within(width: f64, height: f64, point: Point) -> bool {
+    point.x.is_finite()
+        && point.y.is_finite()
+        && (0.0..=width).contains(&point.x)
+        && (0.0..=height).contains(&point.y)
+}
+
+/// Check that a floor plan makes sense, describing the first problem found
+fn validate_map(map: &MapRequest) -> Result<(), &'static str> {
+    let size = 0.0..=MAX_MAP_SIZE;
+    if !(map.width > 0.0
+        && size.contains(&map.width)
+        && map.height > 0.0
+        && size.contains(&map.height))
+    {
+        return Err("width and height must be between 0 and 10000");
+    }
+    if map
+        .earshot
+        .is_some_and(|earshot| !(earshot > 0.0 && size.contains(&earshot)))
+    {
+        return Err("earshot must be between 0 and 10000");
+    }
+    if map.walls.len() > MAX_WALLS {
+        return Err("a map can have at most 500 walls");
+    }
+    let inside = |point| within(map.width, map.height, point);
+    if !map
+        .walls
+        .iter()
+        .all(|wall| inside(wall.from) && inside(wall.to))
+    {
+        return Err("walls must be inside the map");
+    }
+    if map.zones.len() > MAX_ZONES {
+        return Err("a map can have at most 100 zones");
+    }
+    for (index, zone) in map.zones.iter().enumerate() {
+        let name = zone.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_ZONE_NAME_LENGTH {
+            return Err("zone names must be between 1 and 100 characters");
+        }
+        if map.zones[..index]
+            .iter()
+            .any(|other| other.name.trim() == name)
+        {
+            return Err("zone names must be unique");
+        }
+        let origin = Point {
+            x: zone.x,
+            y: zone.y,
+        };
+        let corner = Point {
+            x: zone.x + zone.width,
+            y: zone.y + zone.height,
+        };
+        let sized = zone.width > 0.0 && zone.height > 0.0;
+        if !(sized && inside(origin) && inside(corner)) {
+            return Err("zones must be inside the map");
+        }
+    }
+    Ok(())
+}
+
+#[get("/rooms/<id>/map")]
+pub async fn room_map_show(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::View).await {
+        return response;
+    }
+    match db::get_room_map(&mut conn, id).await {
+        Ok(Some(map)) => ApiResponse::ok(map),
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Lay a room out as a 2D space, or change its floor plan. Everyone connected
+/// is regrouped straight away.
+#[put("/rooms/<id>/map", format = "json", data = "<map>")]
+pub async fn room_map_set(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    map: Json<MapRequest>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Manage).await {
+        return response;
+    }
+    let mut map = map.into_inner();
+    if let Err(long) = validate_map(&map) {
+        return ApiResponse::bad_request(long);
+    }
+    for zone in &mut map.zones {
+        zone.name = zone.name.trim().to_string();
+    }
+    let earshot = map.earshot.unwrap_or(DEFAULT_EARSHOT);
+    match db::set_room_map(&mut conn, id, map, earshot).await {
+        Ok(map) => {
+            hub.set_map(id, Some(map.clone()));
+            ApiResponse::ok(map)
+        }
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Stop treating a room as a 2D space
+#[delete("/rooms/<id>/map")]
+pub async fn room_map_remove(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Manage).await {
+        return response;
+    }
+    match db::remove_room_map(&mut conn, id).await {
+        Ok(0) => ApiResponse::not_found(),
+        Ok(_) => {
+            hub.set_map(id, None);
+            ApiResponse {
+                json: json!({"result": {"room_id": id}}),
+                status: Status::Ok,
+            }
+        }
+        Err(_) => ApiResponse::internal_error(),
+    }
+}
+
+/// Move one of the caller's sessions around a room's map, or take it off the
+/// map with a `null` position
+#[put(
+    "/rooms/<id>/sessions/<session_id>/position",
+    format = "json",
+    data = "<position>"
+)]
+pub async fn room_session_position(
+    caller: Caller,
+    mut conn: Connection<db::RoomDb>,
+    hub: &State<Hub>,
+    id: Uuid,
+    session_id: Uuid,
+    position: Json<db::requests::PositionRequest>,
+) -> ApiResponse {
+    if let Err(response) = room_for(&mut conn, &caller, id, Action::Join).await {
+        return response;
+    }
+    if !own_session(hub, &caller, id, session_id) {
+        return ApiResponse::not_found();
+    }
+    let map = match db::get_room_map(&mut conn, id).await {
+        Ok(Some(map)) => map,
+        Ok(None) => {
+            return ApiResponse::error(
+                Status::Conflict,
+                "NoMap",
+                "the room isn't laid out as a 2D space",
+            )
+        }
+        Err(_) => return ApiResponse::internal_error(),
+    };
+    if let Some(point) = position.position {
+        if !map.contains(point) {
+            return ApiResponse::bad_request("position must be inside the map");
+        }
+    }
+    match hub.set_position(id, session_id, position.position) {
+        Some(participant) => ApiResponse::ok(participant),
+        None => ApiResponse::not_found(),
+    }
+}
@@ -0,0 +1,55 @@
+//! Closing breakout rooms when their time is up.
+//!
+//! Breakout rooms are closed in the database by whichever sweep first finds
+//! them due, so they still close on time after a restart.
+
+use std::time::Duration;
+
+use rocket::tokio::{select, time};
+use rocket::{Orbit, Rocket};
+use rocket_db_pools::Database;
+
+use crate::db;
+use crate::db::responses::Room;
+use crate::realtime::Hub;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tell everyone in some newly closed breakout rooms to return to the parent
+pub fn announce_closed(hub: &Hub, rooms: &[Room]) {
+    for room in rooms {
+        if let Some(parent_id) = room.parent_id {
+            hub.close_breakout(room.id, parent_id);
+        }
+    }
+}
+
+/// Start closing breakout rooms as they fall due, until the server shuts down
+pub async fn start(rocket: &Rocket<Orbit>) {
+    let (Some(pool), Some(hub)) = (db::RoomDb::fetch(rocket), rocket.state::<Hub>()) else {
+        return;
+    };
+    let pool = (**pool).clone();
+    let hub = hub.clone();
+    let mut shutdown = rocket.shutdown();
+    rocket::tokio::spawn(async move {
+        let mut interval = time::interval(SWEEP_INTERVAL);
+        loop {
+            select! {
+                _ = interval.tick() => {},
+                _ = &mut shutdown => break,
+            }
+            let mut conn = match pool.acquire().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    error!("Failed to close breakout rooms: {}", e);
+                    continue;
+                }
+            };
+            match db::close_due_breakouts(&mut conn).await {
+                Ok(rooms) => announce_closed(&hub, &rooms),
+                Err(e) => error!("Failed to close breakout rooms: {:?}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,313 @@
+//! Scheduled sessions as iCalendar (RFC 5545).
+//!
+//! Recurring sessions keep their `RRULE` as given, once it has been checked,
+//! and calendar apps work out the occurrences themselves. Times are all UTC,
+//! so a session that repeats keeps the same UTC time across daylight saving
+//! changes.
+
+use chrono::{Duration, NaiveDateTime};
+
+use crate::db::responses::CalendarEntry;
+
+const DAYS: &[&str] = &["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+/// Longest a content line may be, in bytes, before it is folded
+const MAX_LINE_LENGTH: usize = 75;
+
+fn is_number_in(value: &str, min: i32, max: i32) -> bool {
+    value
+        .parse::<i32>()
+        .is_ok_and(|n| n != 0 && (min..=max).contains(&n))
+}
+
+/// A weekday, optionally with which one in the month or year, like `2MO` or
+/// `-1FR`
+fn is_weekday(value: &str) -> bool {
+    let day = value.trim_start_matches(|c: char| c == '+' || c == '-' || c.is_ascii_digit());
+    let ordinal = &value[..value.len() - day.len()];
+    DAYS.contains(&day) && (ordinal.is_empty() || is_number_in(ordinal, -53, 53))
+}
+
+/// A UTC time like `20240716T090000Z`, which `UNTIL` has to be since sessions
+/// start at UTC times
+fn is_time(value: &str) -> bool {
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ").is_ok()
+}
+
+/// Check a recurrence rule, an `RRULE` value like `FREQ=WEEKLY;BYDAY=MO,WE`,
+/// and write it the way calendars expect. Only the parts people use for
+/// meetings are accepted. Returns `None` if the rule isn't valid.
+pub fn normalize_rule(rule: &str) -> Option<String> {
+    let mut parts: Vec<(String, String)> = Vec::new();
+    for part in rule.trim().split(';') {
+        let (name, value) = part.split_once('=')?;
+        let name = name.trim().to_ascii_uppercase();
+        let value = value.trim().to_ascii_uppercase();
+        let valid = match name.as_str() {
+            "FREQ" => ["DAILY", "WEEKLY", "MONTHLY", "YEARLY"].contains(&value.as_str()),
+            "INTERVAL" => is_number_in(&value, 1, 999),
+            "COUNT" => is_number_in(&value, 1, 9999),
+            "UNTIL" => is_time(&value),
+            "BYDAY" => value.split(',').all(is_weekday),
+            "BYMONTHDAY" => value.split(',').all(|day| is_number_in(day, -31, 31)),
+            "BYMONTH" => value.split(',').all(|month| is_number_in(month, 1, 12)),
+            "WKST" => DAYS.contains(&value.as_str()),
+            _ => false,
+        };
+        if !valid || parts.iter().any(|(other, _)| *other == name) {
+            return None;
+        }
+        parts.push((name, value));
+    }
+    let has = |name: &str| parts.iter().any(|(other, _)| other == name);
+    if !has("FREQ") || (has("COUNT") && has("UNTIL")) {
+        return None;
+    }
+    // FREQ comes first, for the sake of older calendar apps
+    parts.sort_by_key(|(name, _)| name != "FREQ");
+    let parts: Vec<String> = parts
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    Some(parts.join(";"))
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn format_time(time: NaiveDateTime) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Add a content line, folding it onto continuation lines if it is too long
+fn push_line(ics: &mut String, line: &str) {
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_LENGTH {
+            ics.push_str("\r\n ");
+            // The space starting a continuation line counts towards it
+            length = 1;
+        }
+        ics.push(c);
+        length += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+/// Write a calendar with an event for each scheduled session
+pub fn feed(entries: &[CalendarEntry]) -> String {
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, "PRODID:-//Yonder//Rooms//EN");
+    push_line(&mut ics, "CALSCALE:GREGORIAN");
+    push_line(&mut ics, "METHOD:PUBLISH");
+    push_line(&mut ics, "X-WR-CALNAME:Yonder");
+    for entry in entries {
+        let ends_at = entry.starts_at + Duration::seconds(i64::from(entry.duration));
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(&mut ics, &format!("UID:{}@yonder", entry.id));
+        push_line(
+            &mut ics,
+            &format!("DTSTAMP:{}", format_time(entry.updated_at)),
+        );
+        push_line(
+            &mut ics,
+            &format!("CREATED:{}", format_time(entry.created_at)),
+        );
+        push_line(
+            &mut ics,
+            &format!("LAST-MODIFIED:{}", format_time(entry.updated_at)),
+        );
+        push_line(
+            &mut ics,
+            &format!("DTSTART:{}", format_time(entry.starts_at)),
+        );
+        push_line(&mut ics, &format!("DTEND:{}", format_time(ends_at)));
+        if let Some(recurrence) = &entry.recurrence {
+            push_line(&mut ics, &format!("RRULE:{}", recurrence));
+        }
+        push_line(&mut ics, &format!("SUMMARY:{}", escape(&entry.title)));
+        if !entry.description.is_empty() {
+            push_line(
+                &mut ics,
+                &format!("DESCRIPTION:{}", escape(&entry.description)),
+            );
+        }
+        push_line(&mut ics, &format!("LOCATION:{}", escape(&entry.room_name)));
+        push_line(&mut ics, "END:VEVENT");
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use uuid::Uuid;
+
+    fn entry(title: &str, description: &str) -> CalendarEntry {
+        let time = NaiveDate::from_ymd_opt(2024, 7, 16)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap();
+        CalendarEntry {
+            id: Uuid::new_v4(),
+            room_name: String::from("Standup"),
+            title: title.to_string(),
+            description: description.to_string(),
+            starts_at: time,
+            duration: 30 * 60,
+            recurrence: Some(String::from("FREQ=WEEKLY;BYDAY=MO,WE")),
+            created_at: time,
+            updated_at: time,
+        }
+    }
+
+    /// The content lines of a calendar, with folded lines put back together
+    fn unfold(ics: &str) -> Vec<String> {
+        ics.replace("\r\n ", "")
+            .split_terminator("\r\n")
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn normalize_rule_puts_freq_first_in_upper_case() {
+        assert_eq!(
+            normalize_rule(" byday=mo,we ; freq=weekly ").as_deref(),
+            Some("FREQ=WEEKLY;BYDAY=MO,WE")
+        );
+        assert_eq!(
+            normalize_rule("FREQ=MONTHLY;BYDAY=2MO,-1FR;INTERVAL=2").as_deref(),
+            Some("FREQ=MONTHLY;BYDAY=2MO,-1FR;INTERVAL=2")
+        );
+    }
+
+    #[test]
+    fn normalize_rule_rejects_parts_it_does_not_know() {
+        for rule in [
+            "",
+            "FREQ=HOURLY",
+            "FREQ=DAILY;BYHOUR=9",
+            "FREQ=WEEKLY;BYDAY=XX",
+            "FREQ=WEEKLY;BYDAY=0MO",
+            "FREQ=MONTHLY;BYDAY=54MO",
+            "FREQ=MONTHLY;BYMONTHDAY=32",
+            "FREQ=YEARLY;BYMONTH=13",
+            "FREQ=WEEKLY;WKST=XX",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;FREQ=WEEKLY",
+            "FREQ=DAILY;",
+            "FREQ",
+            "INTERVAL=2",
+        ] {
+            assert_eq!(normalize_rule(rule), None, "{:?}", rule);
+        }
+    }
+
+    #[test]
+    fn normalize_rule_checks_count_and_until() {
+        assert_eq!(
+            normalize_rule("FREQ=DAILY;COUNT=10").as_deref(),
+            Some("FREQ=DAILY;COUNT=10")
+        );
+        assert_eq!(
+            normalize_rule("FREQ=DAILY;UNTIL=20240801T090000Z").as_deref(),
+            Some("FREQ=DAILY;UNTIL=20240801T090000Z")
+        );
+        for rule in [
+            "FREQ=DAILY;COUNT=0",
+            "FREQ=DAILY;COUNT=-1",
+            "FREQ=DAILY;COUNT=10000",
+            "FREQ=DAILY;COUNT=many",
+            // UNTIL has to be a UTC time, since sessions start at one
+            "FREQ=DAILY;UNTIL=20240801",
+            "FREQ=DAILY;UNTIL=20240801T090000",
+            "FREQ=DAILY;UNTIL=20241301T090000Z",
+            // Only one way of ending may be given
+            "FREQ=DAILY;COUNT=10;UNTIL=20240801T090000Z",
+        ] {
+            assert_eq!(normalize_rule(rule), None, "{:?}", rule);
+        }
+    }
+
+    #[test]
+    fn push_line_folds_at_75_octets() {
+        let mut ics = String::new();
+        let line = format!("SUMMARY:{}", "a".repeat(MAX_LINE_LENGTH - 8));
+        push_line(&mut ics, &line);
+        assert_eq!(ics, format!("{}\r\n", line));
+
+        let mut ics = String::new();
+        let line = format!("SUMMARY:{}", "a".repeat(200));
+        push_line(&mut ics, &line);
+        let lines: Vec<&str> = ics.split_terminator("\r\n").collect();
+        let lengths: Vec<usize> = lines.iter().map(|line| line.len()).collect();
+        assert_eq!(lengths, [75, 75, 60]);
+        assert!(lines[1..].iter().all(|line| line.starts_with(' ')));
+        assert_eq!(unfold(&ics), [line]);
+    }
+
+    #[test]
+    fn push_line_keeps_multi_byte_characters_whole() {
+        // Two and three byte characters, so no fold lands on a whole line
+        let line = format!("DESCRIPTION:{}", "é€".repeat(40));
+        let mut ics = String::new();
+        push_line(&mut ics, &line);
+        let lines: Vec<&str> = ics.split_terminator("\r\n").collect();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.len() <= MAX_LINE_LENGTH));
+        assert!(lines[1..].iter().all(|line| line.starts_with(' ')));
+        assert_eq!(unfold(&ics), [line]);
+
+        // 74 octets leaves no room for a three byte character
+        let mut ics = String::new();
+        push_line(&mut ics, &format!("SUMMARY:{}€", "a".repeat(66)));
+        assert_eq!(ics, format!("SUMMARY:{}\r\n €\r\n", "a".repeat(66)));
+    }
+
+    #[test]
+    fn escape_text_values() {
+        assert_eq!(
+            escape("Plans, notes; more\\less\r\nnext\nline"),
+            r"Plans\, notes\; more\\less\nnext\nline"
+        );
+    }
+
+    #[test]
+    fn feed_escapes_summary_and_description() {
+        let ics = feed(&[entry(
+            "Standup; daily, short\nish",
+            "Bring notes,\nand; coffee",
+        )]);
+        let lines = unfold(&ics);
+        assert!(lines.contains(&String::from(r"SUMMARY:Standup\; daily\, short\nish")));
+        assert!(lines.contains(&String::from(r"DESCRIPTION:Bring notes\,\nand\; coffee")));
+        assert!(lines.contains(&String::from("RRULE:FREQ=WEEKLY;BYDAY=MO,WE")));
+        assert!(lines.contains(&String::from("DTSTART:20240716T090000Z")));
+        assert!(lines.contains(&String::from("DTEND:20240716T093000Z")));
+        assert_eq!(lines.first().map(String::as_str), Some("BEGIN:VCALENDAR"));
+        assert_eq!(lines.last().map(String::as_str), Some("END:VCALENDAR"));
+    }
+
+    #[test]
+    fn feed_leaves_out_an_empty_description() {
+        let ics = feed(&[entry("Standup", "")]);
+        assert!(!unfold(&ics)
+            .iter()
+            .any(|line| line.starts_with("DESCRIPTION")));
+    }
+}
@@ -0,0 +1,2251 @@
+pub mod requests;
+pub mod responses;
+
+use std::cmp;
+
+use chrono::NaiveDateTime;
+use rocket::fairing;
+use rocket::serde::json::json;
+use rocket::serde::json::Value;
+use rocket::Build;
+use rocket::Rocket;
+use rocket_db_pools::sqlx;
+use rocket_db_pools::Database;
+use serde::Deserialize;
+use serde::Serialize;
+use sqlx::Acquire;
+use sqlx::PgConnection;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use responses::{
+    AttachedFile, KnockState, ModerationKind, Reaction, Role, RoomMap, Visibility, Wall, Zone,
+};
+use sqlx::types::Json;
+
+pub type DBResult<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
+
+#[derive(Database)]
+#[database("roomdb")]
+pub struct RoomDb(PgPool);
+
+pub async fn run_migrations(rocket: Rocket<Build>) -> fairing::Result {
+    match RoomDb::fetch(&rocket) {
+        Some(db) => match sqlx::migrate!("./migrations").run(&**db).await {
+            Ok(_) => Ok(rocket),
+            Err(e) => {
+                error!("Failed to run database migrations: {}", e);
+                Err(rocket)
+            }
+        },
+        None => Err(rocket),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+struct PaginatedQueryResult<T> {
+    result: Vec<T>,
+    prev_page: i32,
+    next_page: i32,
+    last_page: i32,
+}
+
+impl<T> PaginatedQueryResult<T> {
+    fn new(result: Vec<T>, page: i32, total_pages: i32) -> Self {
+        PaginatedQueryResult {
+            result,
+            prev_page: cmp::max(1, page - 1),
+            next_page: cmp::min(total_pages, page + 1),
+            last_page: total_pages,
+        }
+    }
+}
+
+/// List the unarchived Rooms a user can see: every public Room, plus any
+/// private Rooms they have a Role in, directly or through one of their Groups.
+/// Breakout Rooms are left out; they're listed under their parent.
+pub async fn get_rooms(
+    conn: &mut PgConnection,
+    viewer_id: Uuid,
+    viewer_groups: &[Uuid],
+    page: i32,
+    per_page: i32,
+    total_pages: i32,
+) -> DBResult<Value> {
+    let query = sqlx::query_as!(
+        responses::Room,
+        r#"
+        SELECT id, name, description, owner_id, visibility AS "visibility: Visibility",
+            archived_at, parent_id, closes_at, slow_mode, guest_access, capacity, lobby,
+            direct_key IS NOT NULL AS "direct!", created_at, updated_at
+        FROM public."Room"
+        WHERE archived_at IS NULL AND parent_id IS NULL AND direct_key IS NULL AND (
+            visibility = 'public'
+            OR EXISTS(SELECT 1 FROM public."RoomMember" m WHERE m.room_id = id AND m.user_id = $1)
+            OR EXISTS(SELECT 1 FROM public."RoomGroupGrant" g WHERE g.room_id = id AND g.group_id = ANY($2))
+        )
+        ORDER BY created_at, id LIMIT $3 OFFSET $4;
+        "#,
+        viewer_id,
+        viewer_groups,
+        i64::from(per_page),
+        i64::from((page - 1) * per_page)
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(json!(PaginatedQueryResult::new(query, page, total_pages)))
+}
+
+pub async fn get_room_count(
+    conn: &mut PgConnection,
+    viewer_id: Uuid,
+    viewer_groups: &[Uuid],
+) -> DBResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM public."Room"
+        WHERE archived_at IS NULL AND parent_id IS NULL AND direct_key IS NULL AND (
+            visibility = 'public'
+            OR EXISTS(SELECT 1 FROM public."RoomMember" m WHERE m.room_id = id AND m.user_id = $1)
+            OR EXISTS(SELECT 1 FROM public."RoomGroupGrant" g WHERE g.room_id = id AND g.group_id = ANY($2))
+        );
+        "#,
+        viewer_id,
+        viewer_groups
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(count)
+}
+
+pub async fn get_room(conn: &mut PgConnection, id: Uuid) -> DBResult<Option<responses::Room>> {
+    let room = sqlx::query_as!(
+        responses::Room,
+        r#"
+        SELECT id, name, description, owner_id, visibility AS "visibility: Visibility",
+            archived_at, parent_id, closes_at, slow_mode, guest_access, capacity, lobby,
+            direct_key IS NOT NULL AS "direct!", created_at, updated_at
+        FROM public."Room" WHERE id = $1;
+        "#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(room)
+}
+
+/// Create a Room, making its creator the owner
+pub async fn create_room(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    room: &requests::RoomRequest,
+) -> DBResult<responses::Room> {
+    let mut tx = conn.begin().await?;
+    let room = sqlx::query_as!(
+        responses::Room,
+        r#"
+        INSERT INTO public."Room" (name, description, owner_id, visibility)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, name, description, owner_id, visibility AS "visibility: Visibility",
+            archived_at, parent_id, closes_at, slow_mode, guest_access, capacity, lobby,
+            direct_key IS NOT NULL AS "direct!", created_at, updated_at;
+        "#,
+        room.name.trim(),
+        room.description.as_deref().unwrap_or(""),
+        owner_id,
+        room.visibility.unwrap_or(Visibility::Public) as Visibility
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO public."RoomMember" (room_id, user_id, role) VALUES ($1, $2, 'owner');
+        "#,
+        room.id,
+        owner_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(room)
+}
+
+/// Apply a partial update to a Room, leaving any fields that weren't provided
+/// untouched. Archived Rooms can't be updated, so this returns `None` for them
+/// as well as for Rooms that don't exist.
+pub async fn update_room(
+    conn: &mut PgConnection,
+    id: Uuid,
+    room: &requests::RoomUpdateRequest,
+) -> DBResult<Option<responses::Room>> {
+    let room = sqlx::query_as!(
+        responses::Room,
+        r#"
+        UPDATE public."Room" SET
+            name = COALESCE($2, name),
+            description = COALESCE($3, description),
+            visibility = COALESCE($4, visibility),
+            guest_access = COALESCE($5, guest_access),
+            capacity = CASE WHEN $6::integer IS NULL THEN capacity ELSE NULLIF($6, 0) END,
+            lobby = COALESCE($7, lobby),
+            updated_at = now()
+        WHERE id = $1 AND archived_at IS NULL
+        RETURNING id, name, description, owner_id, visibility AS "visibility: Visibility",
+            archived_at, parent_id, closes_at, slow_mode, guest_access, capacity, lobby,
+            direct_key IS NOT NULL AS "direct!", created_at, updated_at;
+        "#,
+        id,
+        room.name.as_deref().map(str::trim),
+        room.description.as_deref(),
+        room.visibility as Option<Visibility>,
+        room.guest_access,
+        room.capacity,
+        room.lobby
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(room)
+}
+
+/// Archive a Room. Archived Rooms are hidden from listings and can no longer
+/// be changed, but are kept around along with their history.
+pub async fn archive_room(conn: &mut PgConnection, id: Uuid) -> DBResult<Option<responses::Room>> {
+    let room = sqlx::query_as!(
+        responses::Room,
+        r#"
+        UPDATE public."Room" SET archived_at = now(), updated_at = now()
+        WHERE id = $1 AND archived_at IS NULL
+        RETURNING id, name, description, owner_id, visibility AS "visibility: Visibility",
+            archived_at, parent_id, closes_at, slow_mode, guest_access, capacity, lobby,
+            direct_key IS NOT NULL AS "direct!", created_at, updated_at;
+        "#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(room)
+}
+
+/// A message to store, with who it mentions and what is attached to it
+pub struct NewMessage<'a> {
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+    pub body: &'a str,
+    /// The first message of the thread it is a reply in
+    pub thread_id: Option<Uuid>,
+    /// The user and group ids it mentions
+    pub mentions: &'a [Uuid],
+    /// Everyone those mentions reach
+    pub mentioned_user_ids: &'a [Uuid],
+    /// Files the author uploaded to the room and hasn't sent yet
+    pub attachment_ids: &'a [Uuid],
+}
+
+/// Store a message. Authors aren't recorded as mentioning themselves.
+pub async fn create_message(
+    conn: &mut PgConnection,
+    message: &NewMessage<'_>,
+) -> DBResult<responses::Message> {
+    let id = sqlx::query_scalar!(
+        r#"
+        WITH message AS (
+            INSERT INTO public."Message" (room_id, user_id, body, thread_id, mentions)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id
+        ), mention AS (
+            INSERT INTO public."MessageMention" (message_id, user_id)
+            SELECT DISTINCT message.id, mentioned.user_id
+            FROM message, unnest($6::uuid[]) AS mentioned(user_id)
+            WHERE mentioned.user_id <> $2
+        ), attached AS (
+            UPDATE public."Attachment" a SET message_id = message.id FROM message
+            WHERE a.id = ANY($7) AND a.room_id = $1 AND a.user_id = $2 AND a.message_id IS NULL
+        )
+        SELECT id AS "id!" FROM message;
+        "#,
+        message.room_id,
+        message.user_id,
+        message.body,
+        message.thread_id,
+        message.mentions,
+        message.mentioned_user_ids,
+        message.attachment_ids
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let message = get_message(conn, message.room_id, id).await?;
+    message.ok_or(rocket::response::Debug(sqlx::Error::RowNotFound))
+}
+
+pub async fn get_message(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    id: Uuid,
+) -> DBResult<Option<responses::Message>> {
+    let message = sqlx::query_as!(
+        responses::Message,
+        r#"
+        SELECT id AS "id!", room_id AS "room_id!", user_id AS "user_id!", body AS "body!",
+            thread_id, reply_count AS "reply_count!",
+            reactions AS "reactions!: Json<Vec<Reaction>>", mentions AS "mentions!",
+            attachments AS "attachments!: Json<Vec<AttachedFile>>",
+            edited_at, deleted_at,
+            created_at AS "created_at!"
+        FROM public."MessageView" WHERE id = $1 AND room_id = $2;
+        "#,
+        id,
+        room_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(message)
+}
+
+/// Fetch up to `limit` of a Room's messages, newest first: either those
+/// outside any thread, or the replies in the thread started by `thread_id`.
+/// Passing the id of the oldest message from the previous page as `before`
+/// continues from there, which stays stable while new messages are being
+/// appended. Deleted messages keep their place but not their body.
+pub async fn get_messages(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    thread_id: Option<Uuid>,
+    before: Option<Uuid>,
+    limit: i64,
+) -> DBResult<Vec<responses::Message>> {
+    let messages = sqlx::query_as!(
+        responses::Message,
+        r#"
+        SELECT id AS "id!", room_id AS "room_id!", user_id AS "user_id!", body AS "body!",
+            thread_id, reply_count AS "reply_count!",
+            reactions AS "reactions!: Json<Vec<Reaction>>", mentions AS "mentions!",
+            attachments AS "attachments!: Json<Vec<AttachedFile>>",
+            edited_at, deleted_at,
+            created_at AS "created_at!"
+        FROM public."MessageView"
+        WHERE room_id = $1 AND thread_id IS NOT DISTINCT FROM $2 AND (
+            $3::uuid IS NULL
+            OR (created_at, id) < (SELECT created_at, id FROM public."Message" WHERE id = $3)
+        )
+        ORDER BY created_at DESC, id DESC LIMIT $4;
+        "#,
+        room_id,
+        thread_id,
+        before,
+        limit
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(messages)
+}
+
+/// Change what a user's message says, keeping what it said before, and who
+/// it mentions. Returns `None` if the user has no such message or it has been
+/// deleted.
+pub async fn edit_message(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    id: Uuid,
+    user_id: Uuid,
+    body: &str,
+    mentions: &[Uuid],
+    mentioned_user_ids: &[Uuid],
+) -> DBResult<Option<responses::Message>> {
+    let edited = sqlx::query_scalar!(
+        r#"
+        WITH previous AS (
+            SELECT id, body FROM public."Message"
+            WHERE id = $1 AND room_id = $2 AND user_id = $3 AND deleted_at IS NULL
+            FOR UPDATE
+        ), edit AS (
+            INSERT INTO public."MessageEdit" (message_id, body) SELECT id, body FROM previous
+        ), unmentioned AS (
+            DELETE FROM public."MessageMention" mm USING previous
+            WHERE mm.message_id = previous.id AND mm.user_id <> ALL($6)
+        ), mention AS (
+            INSERT INTO public."MessageMention" (message_id, user_id)
+            SELECT DISTINCT previous.id, mentioned.user_id
+            FROM previous, unnest($6::uuid[]) AS mentioned(user_id)
+            WHERE mentioned.user_id <> $3
+            ON CONFLICT DO NOTHING
+        )
+        UPDATE public."Message" m SET body = $4, mentions = $5, edited_at = now()
+        FROM previous WHERE m.id = previous.id
+        RETURNING m.id;
+        "#,
+        id,
+        room_id,
+        user_id,
+        body,
+        mentions,
+        mentioned_user_ids
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    match edited {
+        Some(id) => get_message(conn, room_id, id).await,
+        None => Ok(None),
+    }
+}
+
+/// Fetch up to `limit` messages that mention a user, newest first, from every
+/// Room they can still see. `before` works as in `get_messages`.
+pub async fn get_mentions(
+    conn: &mut PgConnection,
+    viewer_id: Uuid,
+    viewer_groups: &[Uuid],
+    is_superuser: bool,
+    before: Option<Uuid>,
+    limit: i64,
+) -> DBResult<Vec<responses::Message>> {
+    let messages = sqlx::query_as!(
+        responses::Message,
+        r#"
+        SELECT m.id AS "id!", m.room_id AS "room_id!", m.user_id AS "user_id!",
+            m.body AS "body!", m.thread_id, m.reply_count AS "reply_count!",
+            m.reactions AS "reactions!: Json<Vec<Reaction>>", m.mentions AS "mentions!",
+            m.attachments AS "attachments!: Json<Vec<AttachedFile>>",
+            m.edited_at, m.deleted_at, m.created_at AS "created_at!"
+        FROM public."MessageMention" mm
+        JOIN public."MessageView" m ON m.id = mm.message_id
+        JOIN public."Room" r ON r.id = m.room_id
+        JOIN public."Room" scope ON scope.id = COALESCE(r.parent_id, r.id)
+        WHERE mm.user_id = $1 AND m.deleted_at IS NULL AND (
+            $3
+            OR scope.owner_id = $1
+            OR (
+                NOT EXISTS(
+                    SELECT 1 FROM public."RoomBan" b
+                    WHERE b.room_id IN (r.id, scope.id) AND b.user_id = $1
+                        AND (b.expires_at IS NULL OR b.expires_at > now())
+                )
+                AND (
+                    scope.visibility = 'public'
+                    OR EXISTS(SELECT 1 FROM public."RoomMember" rm WHERE rm.room_id = scope.id AND rm.user_id = $1)
+                    OR EXISTS(SELECT 1 FROM public."RoomGroupGrant" g WHERE g.room_id = scope.id AND g.group_id = ANY($2))
+                )
+            )
+        ) AND (
+            $4::uuid IS NULL
+            OR (m.created_at, m.id) < (SELECT created_at, id FROM public."Message" WHERE id = $4)
+        )
+        ORDER BY m.created_at DESC, m.id DESC LIMIT $5;
+        "#,
+        viewer_id,
+        viewer_groups,
+        is_superuser,
+        before,
+        limit
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(messages)
+}
+
+/// What a message said before each time it was edited, newest first
+pub async fn get_message_edits(
+    conn: &mut PgConnection,
+    message_id: Uuid,
+) -> DBResult<Vec<responses::MessageEdit>> {
+    let edits = sqlx::query_as!(
+        responses::MessageEdit,
+        r#"
+        SELECT body, created_at FROM public."MessageEdit" WHERE message_id = $1
+        ORDER BY created_at DESC, id DESC;
+        "#,
+        message_id
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(edits)
+}
+
+/// Delete one of a user's own messages. It keeps its place in the history
+/// but no longer says anything.
+pub async fn delete_message(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    id: Uuid,
+    user_id: Uuid,
+) -> DBResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE public."Message" SET deleted_at = now(), deleted_by = $3
+        WHERE id = $1 AND room_id = $2 AND user_id = $3 AND deleted_at IS NULL;
+        "#,
+        id,
+        room_id,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn add_reaction(
+    conn: &mut PgConnection,
+    message_id: Uuid,
+    user_id: Uuid,
+    emoji: &str,
+) -> DBResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO public."MessageReaction" (message_id, user_id, emoji) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING;
+        "#,
+        message_id,
+        user_id,
+        emoji
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn remove_reaction(
+    conn: &mut PgConnection,
+    message_id: Uuid,
+    user_id: Uuid,
+    emoji: &str,
+) -> DBResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM public."MessageReaction"
+        WHERE message_id = $1 AND user_id = $2 AND emoji = $3;
+        "#,
+        message_id,
+        user_id,
+        emoji
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Every Role a user holds in a Room, whether directly or through their Groups
+pub async fn get_roles(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    user_id: Uuid,
+    groups: &[Uuid],
+) -> DBResult<Vec<Role>> {
+    let roles = sqlx::query_scalar!(
+        r#"
+        SELECT role AS "role!: Role" FROM public."RoomMember" WHERE room_id = $1 AND user_id = $2
+        UNION ALL
+        SELECT role AS "role!: Role" FROM public."RoomGroupGrant" WHERE room_id = $1 AND group_id = ANY($3);
+        "#,
+        room_id,
+        user_id,
+        groups
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(roles)
+}
+
+pub async fn get_room_members(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    page: i32,
+    per_page: i32,
+    total_pages: i32,
+) -> DBResult<Value> {
+    let query = sqlx::query_as!(
+        responses::RoomMember,
+        r#"
+        SELECT user_id, role AS "role: Role", created_at, updated_at
+        FROM public."RoomMember" WHERE room_id = $1
+        ORDER BY role DESC, created_at, user_id LIMIT $2 OFFSET $3;
+        "#,
+        room_id,
+        i64::from(per_page),
+        i64::from((page - 1) * per_page)
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(json!(PaginatedQueryResult::new(query, page, total_pages)))
+}
+
+pub async fn get_room_member_count(conn: &mut PgConnection, room_id: Uuid) -> DBResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM public."RoomMember" WHERE room_id = $1;
+        "#,
+        room_id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(count)
+}
+
+/// Give a user a Role in a Room, replacing any Role they already had
+pub async fn set_room_member(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    user_id: Uuid,
+    role: Role,
+) -> DBResult<responses::RoomMember> {
+    let member = sqlx::query_as!(
+        responses::RoomMember,
+        r#"
+        INSERT INTO public."RoomMember" (room_id, user_id, role) VALUES ($1, $2, $3)
+        ON CONFLICT (room_id, user_id) DO UPDATE SET role = EXCLUDED.role, updated_at = now()
+        RETURNING user_id, role AS "role: Role", created_at, updated_at;
+        "#,
+        room_id,
+        user_id,
+        role as Role
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(member)
+}
+
+/// Take away a user's direct Role in a Room. Owners can't be removed.
+pub async fn remove_room_member(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    user_id: Uuid,
+) -> DBResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM public."RoomMember" WHERE room_id = $1 AND user_id = $2 AND role <> 'owner';
+        "#,
+        room_id,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn get_room_group_grants(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+) -> DBResult<Vec<responses::RoomGroupGrant>> {
+    let grants = sqlx::query_as!(
+        responses::RoomGroupGrant,
+        r#"
+        SELECT group_id, role AS "role: Role", created_at, updated_at
+        FROM public."RoomGroupGrant" WHERE room_id = $1
+        ORDER BY role DESC, created_at, group_id;
+        "#,
+        room_id
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(grants)
+}
+
+/// Give every user in a Group a Role in a Room, replacing any Role the Group
+/// already had
+pub async fn set_room_group_grant(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    group_id: Uuid,
+    role: Role,
+) -> DBResult<responses::RoomGroupGrant> {
+    let grant = sqlx::query_as!(
+        responses::RoomGroupGrant,
+        r#"
+        INSERT INTO public."RoomGroupGrant" (room_id, group_id, role) VALUES ($1, $2, $3)
+        ON CONFLICT (room_id, group_id) DO UPDATE SET role = EXCLUDED.role, updated_at = now()
+        RETURNING group_id, role AS "role: Role", created_at, updated_at;
+        "#,
+        room_id,
+        group_id,
+        role as Role
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(grant)
+}
+
+pub async fn remove_room_group_grant(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    group_id: Uuid,
+) -> DBResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM public."RoomGroupGrant" WHERE room_id = $1 AND group_id = $2;
+        "#,
+        room_id,
+        group_id
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn create_invite(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    created_by: Uuid,
+    role: Role,
+    max_uses: i32,
+    expires_at: NaiveDateTime,
+) -> DBResult<responses::RoomInvite> {
+    let invite = sqlx::query_as!(
+        responses::RoomInvite,
+        r#"
+        INSERT INTO public."RoomInvite" (room_id, created_by, role, max_uses, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, room_id, created_by, role AS "role: Role", max_uses, uses,
+            expires_at, revoked_at, created_at;
+        "#,
+        room_id,
+        created_by,
+        role as Role,
+        max_uses,
+        expires_at
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(invite)
+}
+
+/// List a Room's invites that can still be redeemed
+pub async fn get_invites(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+) -> DBResult<Vec<responses::RoomInvite>> {
+    let invites = sqlx::query_as!(
+        responses::RoomInvite,
+        r#"
+        SELECT id, room_id, created_by, role AS "role: Role", max_uses, uses,
+            expires_at, revoked_at, created_at
+        FROM public."RoomInvite"
+        WHERE room_id = $1 AND revoked_at IS NULL AND uses < max_uses AND expires_at > now()
+        ORDER BY created_at DESC, id;
+        "#,
+        room_id
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(invites)
+}
+
+pub async fn revoke_invite(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    id: Uuid,
+) -> DBResult<Option<responses::RoomInvite>> {
+    let invite = sqlx::query_as!(
+        responses::RoomInvite,
+        r#"
+        UPDATE public."RoomInvite" SET revoked_at = now()
+        WHERE id = $1 AND room_id = $2 AND revoked_at IS NULL
+        RETURNING id, room_id, created_by, role AS "role: Role", max_uses, uses,
+            expires_at, revoked_at, created_at;
+        "#,
+        id,
+        room_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(invite)
+}
+
+/// Use up one of an invite's redemptions and make the user a member of its
+/// Room. A user who already has a higher Role there keeps it. Returns `None`
+/// if the invite isn't to `room_id` or is revoked, expired or used up.
+pub async fn redeem_invite(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    id: Uuid,
+    user_id: Uuid,
+) -> DBResult<Option<responses::RoomInvite>> {
+    let mut tx = conn.begin().await?;
+    let invite = sqlx::query_as!(
+        responses::RoomInvite,
+        r#"
+        UPDATE public."RoomInvite" SET uses = uses + 1
+        WHERE id = $1 AND room_id = $2 AND revoked_at IS NULL AND uses < max_uses
+            AND expires_at > now()
+        RETURNING id, room_id, created_by, role AS "role: Role", max_uses, uses,
+            expires_at, revoked_at, created_at;
+        "#,
+        id,
+        room_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(invite) = invite else {
+        tx.rollback().await?;
+        return Ok(None);
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO public."RoomMember" (room_id, user_id, role) VALUES ($1, $2, $3)
+        ON CONFLICT (room_id, user_id) DO UPDATE
+            SET role = GREATEST("RoomMember".role, EXCLUDED.role), updated_at = now();
+        "#,
+        invite.room_id,
+        user_id,
+        invite.role as Role
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(Some(invite))
+}
+
+pub async fn get_room_map(conn: &mut PgConnection, room_id: Uuid) -> DBResult<Option<RoomMap>> {
+    let map = sqlx::query_as!(
+        RoomMap,
+        r#"
+        SELECT room_id, width, height, earshot,
+            walls AS "walls: Json<Vec<Wall>>", zones AS "zones: Json<Vec<Zone>>",
+            created_at, updated_at
+        FROM public."RoomMap" WHERE room_id = $1;
+        "#,
+        room_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(map)
+}
+
+/// Lay a Room out as a 2D space, replacing any map it already had
+pub async fn set_room_map(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    map: requests::MapRequest,
+    earshot: f64,
+) -> DBResult<RoomMap> {
+    let map = sqlx::query_as!(
+        RoomMap,
+        r#"
+        INSERT INTO public."RoomMap" (room_id, width, height, earshot, walls, zones)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (room_id) DO UPDATE SET
+            width = EXCLUDED.width, height = EXCLUDED.height, earshot = EXCLUDED.earshot,
+            walls = EXCLUDED.walls, zones = EXCLUDED.zones, updated_at = now()
+        RETURNING room_id, width, height, earshot,
+            walls AS "walls: Json<Vec<Wall>>", zones AS "zones: Json<Vec<Zone>>",
+            created_at, updated_at;
+        "#,
+        room_id,
+        map.width,
+        map.height,
+        earshot,
+        Json(map.walls) as _,
+        Json(map.zones) as _
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(map)
+}
+
+pub async fn remove_room_map(conn: &mut PgConnection, room_id: Uuid) -> DBResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM public."RoomMap" WHERE room_id = $1;
+        "#,
+        room_id
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Split breakout Rooms off a Room, each with the users sent to it. They share
+/// the parent's owner and visibility, and all close at the same time.
+pub async fn create_breakouts(
+    conn: &mut PgConnection,
+    parent: &responses::Room,
+    breakouts: &[(String, Vec<Uuid>)],
+    closes_at: NaiveDateTime,
+) -> DBResult<Vec<responses::Breakout>> {
+    let mut tx = conn.begin().await?;
+    let mut created = Vec::new();
+    for (name, user_ids) in breakouts {
+        let room = sqlx::query_as!(
+            responses::Room,
+            r#"
+            INSERT INTO public."Room" (name, owner_id, visibility, parent_id, closes_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, name, description, owner_id, visibility AS "visibility: Visibility",
+                archived_at, parent_id, closes_at, slow_mode, guest_access, capacity, lobby,
+            direct_key IS NOT NULL AS "direct!", created_at, updated_at;
+            "#,
+            name,
+            parent.owner_id,
+            parent.visibility as Visibility,
+            parent.id,
+            closes_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO public."BreakoutAssignment" (room_id, user_id)
+            SELECT $1, * FROM UNNEST($2::uuid[]);
+            "#,
+            room.id,
+            user_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+        created.push(responses::Breakout {
+            room,
+            user_ids: user_ids.clone(),
+        });
+    }
+    tx.commit().await?;
+    Ok(created)
+}
+
+/// List a Room's breakout Rooms that are still open
+pub async fn get_breakouts(
+    conn: &mut PgConnection,
+    parent_id: Uuid,
+) -> DBResult<Vec<responses::Breakout>> {
+    let rooms = sqlx::query_as!(
+        responses::Room,
+        r#"
+        SELECT id, name, description, owner_id, visibility AS "visibility: Visibility",
+            archived_at, parent_id, closes_at, slow_mode, guest_access, capacity, lobby,
+            direct_key IS NOT NULL AS "direct!", created_at, updated_at
+        FROM public."Room" WHERE parent_id = $1 AND archived_at IS NULL
+        ORDER BY created_at, name, id;
+        "#,
+        parent_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let room_ids: Vec<Uuid> = rooms.iter().map(|room| room.id).collect();
+    let assignments = sqlx::query!(
+        r#"
+        SELECT room_id, user_id FROM public."BreakoutAssignment"
+        WHERE room_id = ANY($1) ORDER BY created_at, user_id;
+        "#,
+        &room_ids
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(rooms
+        .into_iter()
+        .map(|room| responses::Breakout {
+            user_ids: assignments
+                .iter()
+                .filter(|a| a.room_id == room.id)
+                .map(|a| a.user_id)
+                .collect(),
+            room,
+        })
+        .collect())
+}
+
+/// Whether a user has been sent to a breakout Room
+pub async fn is_assigned(conn: &mut PgConnection, room_id: Uuid, user_id: Uuid) -> DBResult<bool> {
+    let assigned = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM public."BreakoutAssignment" WHERE room_id = $1 AND user_id = $2
+        ) AS "assigned!";
+        "#,
+        room_id,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(assigned)
+}
+
+/// Close all of a Room's open breakout Rooms straight away
+pub async fn close_breakouts(
+    conn: &mut PgConnection,
+    parent_id: Uuid,
+) -> DBResult<Vec<responses::Room>> {
+    let rooms = sqlx::query_as!(
+        responses::Room,
+        r#"
+        UPDATE public."Room" SET archived_at = now(), updated_at = now()
+        WHERE parent_id = $1 AND archived_at IS NULL
+        RETURNING id, name, description, owner_id, visibility AS "visibility: Visibility",
+            archived_at, parent_id, closes_at, slow_mode, guest_access, capacity, lobby,
+            direct_key IS NOT NULL AS "direct!", created_at, updated_at;
+        "#,
+        parent_id
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(rooms)
+}
+
+/// Close every breakout Room whose time is up
+pub async fn close_due_breakouts(conn: &mut PgConnection) -> DBResult<Vec<responses::Room>> {
+    let rooms = sqlx::query_as!(
+        responses::Room,
+        r#"
+        UPDATE public."Room" SET archived_at = now(), updated_at = now()
+        WHERE parent_id IS NOT NULL AND archived_at IS NULL AND closes_at <= now()
+        RETURNING id, name, description, owner_id, visibility AS "visibility: Visibility",
+            archived_at, parent_id, closes_at, slow_mode, guest_access, capacity, lobby,
+            direct_key IS NOT NULL AS "direct!", created_at, updated_at;
+        "#
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(rooms)
+}
+
+pub async fn notify(conn: &mut PgConnection, channel: &str, payload: &str) -> DBResult<()> {
+    sqlx::query!(r#"SELECT pg_notify($1, $2);"#, channel, payload)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Keep an update too large to send with NOTIFY for other instances to read
+pub async fn stash_update(conn: &mut PgConnection, payload: &str) -> DBResult<i64> {
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO public."RoomUpdate" (payload) VALUES ($1) RETURNING id;
+        "#,
+        payload
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(id)
+}
+
+pub async fn get_stashed_update(conn: &mut PgConnection, id: i64) -> DBResult<Option<String>> {
+    let payload = sqlx::query_scalar!(
+        r#"
+        SELECT payload FROM public."RoomUpdate" WHERE id = $1;
+        "#,
+        id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(payload)
+}
+
+/// Forget stashed updates more than `lifetime` seconds old
+pub async fn prune_stashed_updates(conn: &mut PgConnection, lifetime: i64) -> DBResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM public."RoomUpdate" WHERE created_at < now() - make_interval(secs => $1);
+        "#,
+        lifetime as f64
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// What a moderator did, for the moderation log
+pub struct ModerationRecord<'a> {
+    pub room_id: Uuid,
+    pub moderator_id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub reason: Option<&'a str>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// Whether a user is banned from any of the given Rooms
+pub async fn is_banned(
+    conn: &mut PgConnection,
+    room_ids: &[Uuid],
+    user_id: Uuid,
+) -> DBResult<bool> {
+    let banned = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM public."RoomBan"
+            WHERE room_id = ANY($1) AND user_id = $2 AND (expires_at IS NULL OR expires_at > now())
+        ) AS "banned!";
+        "#,
+        room_ids,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(banned)
+}
+
+/// Whether a user is muted in any of the given Rooms
+pub async fn is_muted(conn: &mut PgConnection, room_ids: &[Uuid], user_id: Uuid) -> DBResult<bool> {
+    let muted = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM public."RoomMute"
+            WHERE room_id = ANY($1) AND user_id = $2 AND (expires_at IS NULL OR expires_at > now())
+        ) AS "muted!";
+        "#,
+        room_ids,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(muted)
+}
+
+pub async fn get_bans(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+) -> DBResult<Vec<responses::RoomRestriction>> {
+    let bans = sqlx::query_as!(
+        responses::RoomRestriction,
+        r#"
+        SELECT user_id, created_by, reason, expires_at, created_at FROM public."RoomBan"
+        WHERE room_id = $1 AND (expires_at IS NULL OR expires_at > now())
+        ORDER BY created_at DESC, user_id;
+        "#,
+        room_id
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(bans)
+}
+
+pub async fn get_mutes(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+) -> DBResult<Vec<responses::RoomRestriction>> {
+    let mutes = sqlx::query_as!(
+        responses::RoomRestriction,
+        r#"
+        SELECT user_id, created_by, reason, expires_at, created_at FROM public."RoomMute"
+        WHERE room_id = $1 AND (expires_at IS NULL OR expires_at > now())
+        ORDER BY created_at DESC, user_id;
+        "#,
+        room_id
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(mutes)
+}
+
+/// Ban a user from a Room, replacing any ban they already had, and log it
+pub async fn ban_user(
+    conn: &mut PgConnection,
+    record: &ModerationRecord<'_>,
+) -> DBResult<responses::ModerationEntry> {
+    let entry = sqlx::query_as!(
+        responses::ModerationEntry,
+        r#"
+        WITH ban AS (
+            INSERT INTO public."RoomBan" (room_id, user_id, created_by, reason, expires_at)
+            VALUES ($1, $3, $2, $4, $5)
+            ON CONFLICT (room_id, user_id) DO UPDATE SET
+                created_by = EXCLUDED.created_by, reason = EXCLUDED.reason,
+                expires_at = EXCLUDED.expires_at, created_at = now()
+            RETURNING room_id, user_id, created_by, reason, expires_at
+        )
+        INSERT INTO public."ModerationAction"
+            (room_id, moderator_id, action, user_id, reason, expires_at)
+        SELECT room_id, created_by, 'ban', user_id, reason, expires_at FROM ban
+        RETURNING id, room_id, moderator_id, action AS "action: ModerationKind", user_id,
+            message_id, reason, expires_at, slow_mode, created_at;
+        "#,
+        record.room_id,
+        record.moderator_id,
+        record.user_id,
+        record.reason,
+        record.expires_at
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(entry)
+}
+
+/// Lift a user's ban from a Room and log it. Returns `None` if they weren't
+/// banned.
+pub async fn unban_user(
+    conn: &mut PgConnection,
+    record: &ModerationRecord<'_>,
+) -> DBResult<Option<responses::ModerationEntry>> {
+    let entry = sqlx::query_as!(
+        responses::ModerationEntry,
+        r#"
+        WITH ban AS (
+            DELETE FROM public."RoomBan"
+            WHERE room_id = $1 AND user_id = $3 AND (expires_at IS NULL OR expires_at > now())
+            RETURNING room_id, user_id
+        )
+        INSERT INTO public."ModerationAction" (room_id, moderator_id, action, user_id, reason)
+        SELECT room_id, $2, 'unban', user_id, $4 FROM ban
+        RETURNING id, room_id, moderator_id, action AS "action: ModerationKind", user_id,
+            message_id, reason, expires_at, slow_mode, created_at;
+        "#,
+        record.room_id,
+        record.moderator_id,
+        record.user_id,
+        record.reason
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(entry)
+}
+
+/// Mute a user in a Room, replacing any mute they already had, and log it
+pub async fn mute_user(
+    conn: &mut PgConnection,
+    record: &ModerationRecord<'_>,
+) -> DBResult<responses::ModerationEntry> {
+    let entry = sqlx::query_as!(
+        responses::ModerationEntry,
+        r#"
+        WITH mute AS (
+            INSERT INTO public."RoomMute" (room_id, user_id, created_by, reason, expires_at)
+            VALUES ($1, $3, $2, $4, $5)
+            ON CONFLICT (room_id, user_id) DO UPDATE SET
+                created_by = EXCLUDED.created_by, reason = EXCLUDED.reason,
+                expires_at = EXCLUDED.expires_at, created_at = now()
+            RETURNING room_id, user_id, created_by, reason, expires_at
+        )
+        INSERT INTO public."ModerationAction"
+            (room_id, moderator_id, action, user_id, reason, expires_at)
+        SELECT room_id, created_by, 'mute', user_id, reason, expires_at FROM mute
+        RETURNING id, room_id, moderator_id, action AS "action: ModerationKind", user_id,
+            message_id, reason, expires_at, slow_mode, created_at;
+        "#,
+        record.room_id,
+        record.moderator_id,
+        record.user_id,
+        record.reason,
+        record.expires_at
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(entry)
+}
+
+/// Unmute a user in a Room and log it. Returns `None` if they weren't muted.
+pub async fn unmute_user(
+    conn: &mut PgConnection,
+    record: &ModerationRecord<'_>,
+) -> DBResult<Option<responses::ModerationEntry>> {
+    let entry = sqlx::query_as!(
+        responses::ModerationEntry,
+        r#"
+        WITH mute AS (
+            DELETE FROM public."RoomMute"
+            WHERE room_id = $1 AND user_id = $3 AND (expires_at IS NULL OR expires_at > now())
+            RETURNING room_id, user_id
+        )
+        INSERT INTO public."ModerationAction" (room_id, moderator_id, action, user_id, reason)
+        SELECT room_id, $2, 'unmute', user_id, $4 FROM mute
+        RETURNING id, room_id, moderator_id, action AS "action: ModerationKind", user_id,
+            message_id, reason, expires_at, slow_mode, created_at;
+        "#,
+        record.room_id,
+        record.moderator_id,
+        record.user_id,
+        record.reason
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(entry)
+}
+
+/// Log that a user was kicked out of a Room
+pub async fn log_kick(
+    conn: &mut PgConnection,
+    record: &ModerationRecord<'_>,
+) -> DBResult<responses::ModerationEntry> {
+    let entry = sqlx::query_as!(
+        responses::ModerationEntry,
+        r#"
+        INSERT INTO public."ModerationAction" (room_id, moderator_id, action, user_id, reason)
+        VALUES ($1, $2, 'kick', $3, $4)
+        RETURNING id, room_id, moderator_id, action AS "action: ModerationKind", user_id,
+            message_id, reason, expires_at, slow_mode, created_at;
+        "#,
+        record.room_id,
+        record.moderator_id,
+        record.user_id,
+        record.reason
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(entry)
+}
+
+/// Delete a message on a moderator's behalf and log it. Returns `None` if
+/// there is no such message in the Room.
+pub async fn moderate_message(
+    conn: &mut PgConnection,
+    record: &ModerationRecord<'_>,
+    message_id: Uuid,
+) -> DBResult<Option<responses::ModerationEntry>> {
+    let entry = sqlx::query_as!(
+        responses::ModerationEntry,
+        r#"
+        WITH message AS (
+            UPDATE public."Message" SET deleted_at = now(), deleted_by = $2
+            WHERE id = $3 AND room_id = $1 AND deleted_at IS NULL
+            RETURNING room_id, id, user_id
+        )
+        INSERT INTO public."ModerationAction"
+            (room_id, moderator_id, action, user_id, message_id, reason)
+        SELECT room_id, $2, 'delete_message', user_id, id, $4 FROM message
+        RETURNING id, room_id, moderator_id, action AS "action: ModerationKind", user_id,
+            message_id, reason, expires_at, slow_mode, created_at;
+        "#,
+        record.room_id,
+        record.moderator_id,
+        message_id,
+        record.reason
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(entry)
+}
+
+/// Set how long members must wait between messages in a Room and log it
+pub async fn set_slow_mode(
+    conn: &mut PgConnection,
+    record: &ModerationRecord<'_>,
+    slow_mode: i32,
+) -> DBResult<responses::ModerationEntry> {
+    let entry = sqlx::query_as!(
+        responses::ModerationEntry,
+        r#"
+        WITH room AS (
+            UPDATE public."Room" SET slow_mode = $3, updated_at = now()
+            WHERE id = $1
+            RETURNING id
+        )
+        INSERT INTO public."ModerationAction" (room_id, moderator_id, action, slow_mode, reason)
+        SELECT id, $2, 'slow_mode', $3, $4 FROM room
+        RETURNING id, room_id, moderator_id, action AS "action: ModerationKind", user_id,
+            message_id, reason, expires_at, slow_mode, created_at;
+        "#,
+        record.room_id,
+        record.moderator_id,
+        slow_mode,
+        record.reason
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(entry)
+}
+
+/// List what moderators have done in a Room, newest first
+pub async fn get_moderation_log(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    page: i32,
+    per_page: i32,
+    total_pages: i32,
+) -> DBResult<Value> {
+    let entries = sqlx::query_as!(
+        responses::ModerationEntry,
+        r#"
+        SELECT id, room_id, moderator_id, action AS "action: ModerationKind", user_id,
+            message_id, reason, expires_at, slow_mode, created_at
+        FROM public."ModerationAction" WHERE room_id = $1
+        ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3;
+        "#,
+        room_id,
+        i64::from(per_page),
+        i64::from((page - 1) * per_page)
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(json!(PaginatedQueryResult::new(entries, page, total_pages)))
+}
+
+pub async fn get_moderation_log_count(conn: &mut PgConnection, room_id: Uuid) -> DBResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM public."ModerationAction" WHERE room_id = $1;
+        "#,
+        room_id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(count)
+}
+
+/// When a user last sent a message in a Room, deleted or not
+pub async fn last_message_at(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    user_id: Uuid,
+) -> DBResult<Option<NaiveDateTime>> {
+    let sent_at = sqlx::query_scalar!(
+        r#"
+        SELECT created_at FROM public."Message" WHERE room_id = $1 AND user_id = $2
+        ORDER BY created_at DESC LIMIT 1;
+        "#,
+        room_id,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(sent_at)
+}
+
+pub async fn create_attachment(
+    conn: &mut PgConnection,
+    id: Uuid,
+    room_id: Uuid,
+    user_id: Uuid,
+    name: &str,
+    content_type: &str,
+    size: i64,
+) -> DBResult<responses::Attachment> {
+    let attachment = sqlx::query_as!(
+        responses::Attachment,
+        r#"
+        INSERT INTO public."Attachment" (id, room_id, user_id, name, content_type, size)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, room_id, user_id, message_id, name, content_type, size, created_at;
+        "#,
+        id,
+        room_id,
+        user_id,
+        name,
+        content_type,
+        size
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(attachment)
+}
+
+/// Fetch an Attachment a user may see: one sent in a Message that hasn't been
+/// deleted, or one they uploaded and haven't sent yet
+pub async fn get_attachment(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    id: Uuid,
+    viewer_id: Uuid,
+) -> DBResult<Option<responses::Attachment>> {
+    let attachment = sqlx::query_as!(
+        responses::Attachment,
+        r#"
+        SELECT a.id, a.room_id, a.user_id, a.message_id, a.name, a.content_type, a.size,
+            a.created_at
+        FROM public."Attachment" a
+        LEFT OUTER JOIN public."Message" m ON m.id = a.message_id
+        WHERE a.id = $1 AND a.room_id = $2 AND m.deleted_at IS NULL
+            AND (a.message_id IS NOT NULL OR a.user_id = $3);
+        "#,
+        id,
+        room_id,
+        viewer_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(attachment)
+}
+
+/// List the Attachments in a Room a user may see, newest first
+pub async fn get_attachments(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    viewer_id: Uuid,
+    page: i32,
+    per_page: i32,
+    total_pages: i32,
+) -> DBResult<Value> {
+    let attachments = sqlx::query_as!(
+        responses::Attachment,
+        r#"
+        SELECT a.id, a.room_id, a.user_id, a.message_id, a.name, a.content_type, a.size,
+            a.created_at
+        FROM public."Attachment" a
+        LEFT OUTER JOIN public."Message" m ON m.id = a.message_id
+        WHERE a.room_id = $1 AND m.deleted_at IS NULL
+            AND (a.message_id IS NOT NULL OR a.user_id = $2)
+        ORDER BY a.created_at DESC, a.id DESC LIMIT $3 OFFSET $4;
+        "#,
+        room_id,
+        viewer_id,
+        i64::from(per_page),
+        i64::from((page - 1) * per_page)
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(json!(PaginatedQueryResult::new(
+        attachments,
+        page,
+        total_pages
+    )))
+}
+
+pub async fn get_attachment_count(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    viewer_id: Uuid,
+) -> DBResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM public."Attachment" a
+        LEFT OUTER JOIN public."Message" m ON m.id = a.message_id
+        WHERE a.room_id = $1 AND m.deleted_at IS NULL
+            AND (a.message_id IS NOT NULL OR a.user_id = $2);
+        "#,
+        room_id,
+        viewer_id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(count)
+}
+
+/// Count how many of `ids` a user could attach to a new message: files they
+/// uploaded to the Room that haven't been sent yet
+pub async fn count_attachable(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    user_id: Uuid,
+    ids: &[Uuid],
+) -> DBResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM public."Attachment"
+        WHERE id = ANY($1) AND room_id = $2 AND user_id = $3 AND message_id IS NULL;
+        "#,
+        ids,
+        room_id,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(count)
+}
+
+pub async fn delete_attachment(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    id: Uuid,
+) -> DBResult<Option<responses::Attachment>> {
+    let attachment = sqlx::query_as!(
+        responses::Attachment,
+        r#"
+        DELETE FROM public."Attachment" WHERE id = $1 AND room_id = $2
+        RETURNING id, room_id, user_id, message_id, name, content_type, size, created_at;
+        "#,
+        id,
+        room_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(attachment)
+}
+
+/// What to search for, where, and who is searching
+pub struct Search<'a> {
+    pub viewer_id: Uuid,
+    pub viewer_groups: &'a [Uuid],
+    pub is_superuser: bool,
+    /// Words to find, in the syntax of web search engines
+    pub query: &'a str,
+    pub room_id: Option<Uuid>,
+    /// Only messages by this user
+    pub user_id: Option<Uuid>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+/// Find the messages matching a search in every Room a user can see, best
+/// matches first
+pub async fn search_messages(
+    conn: &mut PgConnection,
+    search: &Search<'_>,
+    page: i32,
+    per_page: i32,
+    total_pages: i32,
+) -> DBResult<Value> {
+    let hits = sqlx::query_as!(
+        responses::SearchHit,
+        r#"
+        SELECT m.id, m.room_id, m.user_id, m.thread_id,
+            ts_headline(
+                'english',
+                replace(replace(replace(m.body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'),
+                q.query,
+                'StartSel=<mark>, StopSel=</mark>, MaxFragments=2, MaxWords=20, MinWords=5'
+            ) AS "snippet!",
+            m.created_at
+        FROM public."Message" m
+        CROSS JOIN websearch_to_tsquery('english', $1) AS q(query)
+        JOIN public."Room" r ON r.id = m.room_id
+        JOIN public."Room" scope ON scope.id = COALESCE(r.parent_id, r.id)
+        WHERE m.search @@ q.query AND m.deleted_at IS NULL AND (
+            (($4 OR scope.owner_id = $2) AND r.direct_key IS NULL)
+            OR (
+                NOT EXISTS(
+                    SELECT 1 FROM public."RoomBan" b
+                    WHERE b.room_id IN (r.id, scope.id) AND b.user_id = $2
+                        AND (b.expires_at IS NULL OR b.expires_at > now())
+                )
+                AND (
+                    scope.visibility = 'public'
+                    OR EXISTS(SELECT 1 FROM public."RoomMember" rm WHERE rm.room_id = scope.id AND rm.user_id = $2)
+                    OR EXISTS(SELECT 1 FROM public."RoomGroupGrant" g WHERE g.room_id = scope.id AND g.group_id = ANY($3))
+                )
+            )
+        )
+        AND ($5::uuid IS NULL OR m.room_id = $5)
+        AND ($6::uuid IS NULL OR m.user_id = $6)
+        AND ($7::timestamp IS NULL OR m.created_at >= $7)
+        AND ($8::timestamp IS NULL OR m.created_at < $8)
+        ORDER BY ts_rank(m.search, q.query) DESC, m.created_at DESC, m.id DESC
+        LIMIT $9 OFFSET $10;
+        "#,
+        search.query,
+        search.viewer_id,
+        search.viewer_groups,
+        search.is_superuser,
+        search.room_id,
+        search.user_id,
+        search.since,
+        search.until,
+        i64::from(per_page),
+        i64::from((page - 1) * per_page)
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(json!(PaginatedQueryResult::new(hits, page, total_pages)))
+}
+
+pub async fn search_message_count(conn: &mut PgConnection, search: &Search<'_>) -> DBResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM public."Message" m
+        JOIN public."Room" r ON r.id = m.room_id
+        JOIN public."Room" scope ON scope.id = COALESCE(r.parent_id, r.id)
+        WHERE m.search @@ websearch_to_tsquery('english', $1) AND m.deleted_at IS NULL AND (
+            (($4 OR scope.owner_id = $2) AND r.direct_key IS NULL)
+            OR (
+                NOT EXISTS(
+                    SELECT 1 FROM public."RoomBan" b
+                    WHERE b.room_id IN (r.id, scope.id) AND b.user_id = $2
+                        AND (b.expires_at IS NULL OR b.expires_at > now())
+                )
+                AND (
+                    scope.visibility = 'public'
+                    OR EXISTS(SELECT 1 FROM public."RoomMember" rm WHERE rm.room_id = scope.id AND rm.user_id = $2)
+                    OR EXISTS(SELECT 1 FROM public."RoomGroupGrant" g WHERE g.room_id = scope.id AND g.group_id = ANY($3))
+                )
+            )
+        )
+        AND ($5::uuid IS NULL OR m.room_id = $5)
+        AND ($6::uuid IS NULL OR m.user_id = $6)
+        AND ($7::timestamp IS NULL OR m.created_at >= $7)
+        AND ($8::timestamp IS NULL OR m.created_at < $8);
+        "#,
+        search.query,
+        search.viewer_id,
+        search.viewer_groups,
+        search.is_superuser,
+        search.room_id,
+        search.user_id,
+        search.since,
+        search.until
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(count)
+}
+
+/// Move a user's read marker in a Room forward to a Message. Returns `None`
+/// if the Message isn't in the Room or the marker is already past it.
+pub async fn set_read_marker(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    user_id: Uuid,
+    message_id: Uuid,
+) -> DBResult<Option<responses::ReadMarker>> {
+    let marker = sqlx::query_as!(
+        responses::ReadMarker,
+        r#"
+        WITH message AS (
+            SELECT id, created_at FROM public."Message" WHERE id = $3 AND room_id = $1
+        )
+        INSERT INTO public."RoomReadMarker" (room_id, user_id, message_id)
+        SELECT $1, $2, id FROM message
+        ON CONFLICT (room_id, user_id) DO UPDATE
+        SET message_id = EXCLUDED.message_id, read_at = now()
+        WHERE (
+            SELECT ROW(created_at, id) FROM public."Message"
+            WHERE id = "RoomReadMarker".message_id
+        ) < (SELECT ROW(created_at, id) FROM message)
+        RETURNING room_id, user_id, message_id, read_at;
+        "#,
+        room_id,
+        user_id,
+        message_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(marker)
+}
+
+pub async fn get_read_marker(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    user_id: Uuid,
+) -> DBResult<Option<responses::ReadMarker>> {
+    let marker = sqlx::query_as!(
+        responses::ReadMarker,
+        r#"
+        SELECT room_id, user_id, message_id, read_at FROM public."RoomReadMarker"
+        WHERE room_id = $1 AND user_id = $2;
+        "#,
+        room_id,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(marker)
+}
+
+pub async fn get_read_markers(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+) -> DBResult<Vec<responses::ReadMarker>> {
+    let markers = sqlx::query_as!(
+        responses::ReadMarker,
+        r#"
+        SELECT room_id, user_id, message_id, read_at FROM public."RoomReadMarker"
+        WHERE room_id = $1
+        ORDER BY read_at DESC, user_id;
+        "#,
+        room_id
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(markers)
+}
+
+/// Count what a user hasn't read in each Room they belong to or have read
+/// something in. Their own messages don't count.
+pub async fn get_unread_counts(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    user_groups: &[Uuid],
+) -> DBResult<Vec<responses::UnreadCount>> {
+    let counts = sqlx::query_as!(
+        responses::UnreadCount,
+        r#"
+        WITH room AS (
+            SELECT r.id, rm.message_id, m.created_at AS read_until
+            FROM public."Room" r
+            LEFT OUTER JOIN public."RoomReadMarker" rm ON rm.room_id = r.id AND rm.user_id = $1
+            LEFT OUTER JOIN public."Message" m ON m.id = rm.message_id
+            WHERE r.archived_at IS NULL AND (
+                rm.message_id IS NOT NULL
+                OR EXISTS(SELECT 1 FROM public."RoomMember" rmb WHERE rmb.room_id = r.id AND rmb.user_id = $1)
+                OR EXISTS(SELECT 1 FROM public."RoomGroupGrant" g WHERE g.room_id = r.id AND g.group_id = ANY($2))
+            ) AND NOT EXISTS(
+                SELECT 1 FROM public."RoomBan" b
+                WHERE b.room_id IN (r.id, COALESCE(r.parent_id, r.id)) AND b.user_id = $1
+                    AND (b.expires_at IS NULL OR b.expires_at > now())
+            )
+        ), unread AS (
+            SELECT room.id AS room_id, m.id
+            FROM room
+            JOIN public."Message" m ON m.room_id = room.id
+            WHERE m.deleted_at IS NULL AND m.user_id <> $1 AND (
+                room.message_id IS NULL
+                OR (m.created_at, m.id) > (room.read_until, room.message_id)
+            )
+        )
+        SELECT room.id AS "room_id!", room.message_id AS "message_id?",
+            COUNT(unread.id) AS "unread!",
+            COUNT(mm.message_id) AS "mentions!"
+        FROM room
+        LEFT OUTER JOIN unread ON unread.room_id = room.id
+        LEFT OUTER JOIN public."MessageMention" mm ON mm.message_id = unread.id AND mm.user_id = $1
+        GROUP BY room.id, room.message_id
+        ORDER BY room.id;
+        "#,
+        user_id,
+        user_groups
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(counts)
+}
+
+/// Find the direct conversation between exactly these users, starting it if
+/// there isn't one yet. `user_ids` must be sorted and include `user_id`, who
+/// is recorded as having started it.
+pub async fn open_direct_room(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    user_ids: &[Uuid],
+) -> DBResult<responses::Room> {
+    let key = user_ids
+        .iter()
+        .map(Uuid::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut tx = conn.begin().await?;
+    sqlx::query!(
+        r#"
+        WITH room AS (
+            INSERT INTO public."Room" (name, owner_id, visibility, direct_key)
+            VALUES ('', $1, 'private', $2)
+            ON CONFLICT (direct_key) DO NOTHING
+            RETURNING id
+        )
+        INSERT INTO public."RoomMember" (room_id, user_id, role)
+        SELECT room.id, member.user_id, 'member' FROM room, unnest($3::uuid[]) AS member(user_id);
+        "#,
+        user_id,
+        key,
+        user_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+    let room = sqlx::query_as!(
+        responses::Room,
+        r#"
+        SELECT id, name, description, owner_id, visibility AS "visibility: Visibility",
+            archived_at, parent_id, closes_at, slow_mode, guest_access, capacity, lobby,
+            direct_key IS NOT NULL AS "direct!", created_at, updated_at
+        FROM public."Room" WHERE direct_key = $1;
+        "#,
+        key
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(room)
+}
+
+/// List the direct conversations a user is in, most recently active first
+pub async fn get_direct_rooms(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    page: i32,
+    per_page: i32,
+    total_pages: i32,
+) -> DBResult<Value> {
+    let conversations = sqlx::query_as!(
+        responses::DirectConversation,
+        r#"
+        SELECT r.id AS room_id,
+            ARRAY(
+                SELECT m.user_id FROM public."RoomMember" m
+                WHERE m.room_id = r.id ORDER BY m.user_id
+            ) AS "user_ids!",
+            (SELECT MAX(created_at) FROM public."Message" WHERE room_id = r.id) AS last_message_at,
+            r.created_at
+        FROM public."Room" r
+        JOIN public."RoomMember" me ON me.room_id = r.id AND me.user_id = $1
+        WHERE r.direct_key IS NOT NULL AND r.archived_at IS NULL
+        ORDER BY last_message_at DESC NULLS LAST, r.created_at DESC, r.id LIMIT $2 OFFSET $3;
+        "#,
+        user_id,
+        i64::from(per_page),
+        i64::from((page - 1) * per_page)
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(json!(PaginatedQueryResult::new(
+        conversations,
+        page,
+        total_pages
+    )))
+}
+
+pub async fn get_direct_room_count(conn: &mut PgConnection, user_id: Uuid) -> DBResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM public."Room" r
+        JOIN public."RoomMember" me ON me.room_id = r.id AND me.user_id = $1
+        WHERE r.direct_key IS NOT NULL AND r.archived_at IS NULL;
+        "#,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(count)
+}
+
+/// How many seconds being admitted to a Room lets someone back in for, so
+/// that they can reconnect without knocking again
+const ADMISSION_LIFETIME: i64 = 12 * 60 * 60;
+
+/// Knock on a Room's lobby, or return the knock already waiting there
+pub async fn create_knock(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    user_id: Uuid,
+    guest_name: Option<&str>,
+) -> DBResult<responses::Knock> {
+    let knock = sqlx::query_as!(
+        responses::Knock,
+        r#"
+        INSERT INTO public."RoomKnock" (room_id, user_id, guest_name)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (room_id, user_id) WHERE state = 'waiting'
+        DO UPDATE SET guest_name = EXCLUDED.guest_name
+        RETURNING id, room_id, user_id, guest_name, state AS "state: KnockState",
+            answered_by, answered_at, created_at;
+        "#,
+        room_id,
+        user_id,
+        guest_name
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(knock)
+}
+
+pub async fn get_knock(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    knock_id: Uuid,
+) -> DBResult<Option<responses::Knock>> {
+    let knock = sqlx::query_as!(
+        responses::Knock,
+        r#"
+        SELECT id, room_id, user_id, guest_name, state AS "state: KnockState",
+            answered_by, answered_at, created_at
+        FROM public."RoomKnock"
+        WHERE id = $2 AND room_id = $1;
+        "#,
+        room_id,
+        knock_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(knock)
+}
+
+/// The knocks waiting in a Room's lobby, in the order they came
+pub async fn get_waiting_knocks(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+) -> DBResult<Vec<responses::Knock>> {
+    let knocks = sqlx::query_as!(
+        responses::Knock,
+        r#"
+        SELECT id, room_id, user_id, guest_name, state AS "state: KnockState",
+            answered_by, answered_at, created_at
+        FROM public."RoomKnock"
+        WHERE room_id = $1 AND state = 'waiting'
+        ORDER BY created_at, id;
+        "#,
+        room_id
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(knocks)
+}
+
+/// Settle a knock that is still waiting. Returns `None` if there is no such
+/// knock or it has already been settled.
+pub async fn answer_knock(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    knock_id: Uuid,
+    state: KnockState,
+    answered_by: Uuid,
+) -> DBResult<Option<responses::Knock>> {
+    let knock = sqlx::query_as!(
+        responses::Knock,
+        r#"
+        UPDATE public."RoomKnock"
+        SET state = $3, answered_by = $4, answered_at = now()
+        WHERE id = $2 AND room_id = $1 AND state = 'waiting'
+        RETURNING id, room_id, user_id, guest_name, state AS "state: KnockState",
+            answered_by, answered_at, created_at;
+        "#,
+        room_id,
+        knock_id,
+        state as KnockState,
+        answered_by
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(knock)
+}
+
+/// Whether a moderator has let a user into a Room lately
+pub async fn is_admitted(conn: &mut PgConnection, room_id: Uuid, user_id: Uuid) -> DBResult<bool> {
+    let admitted = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM public."RoomKnock"
+            WHERE room_id = $1 AND user_id = $2 AND state = 'admitted'
+                AND answered_at > now() - make_interval(secs => $3)
+        ) AS "admitted!";
+        "#,
+        room_id,
+        user_id,
+        ADMISSION_LIFETIME as f64
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(admitted)
+}
+
+/// A scheduled session to store, with its invitations worked out
+pub struct ScheduledSessionRecord<'a> {
+    pub room_id: Uuid,
+    pub created_by: Uuid,
+    pub title: &'a str,
+    pub description: &'a str,
+    pub starts_at: NaiveDateTime,
+    pub duration: i32,
+    pub recurrence: Option<&'a str>,
+    /// The user and group ids invited
+    pub invitee_ids: &'a [Uuid],
+    /// Everyone those invitations reach
+    pub user_ids: &'a [Uuid],
+}
+
+pub async fn create_scheduled_session(
+    conn: &mut PgConnection,
+    record: &ScheduledSessionRecord<'_>,
+) -> DBResult<responses::ScheduledSession> {
+    let session = sqlx::query_as!(
+        responses::ScheduledSession,
+        r#"
+        INSERT INTO public."ScheduledSession" (room_id, created_by, title, description,
+            starts_at, duration, recurrence, invitee_ids, user_ids)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id, room_id, title, description, starts_at, duration, recurrence,
+            invitee_ids, user_ids, created_by, created_at, updated_at;
+        "#,
+        record.room_id,
+        record.created_by,
+        record.title,
+        record.description,
+        record.starts_at,
+        record.duration,
+        record.recurrence,
+        record.invitee_ids,
+        record.user_ids
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(session)
+}
+
+pub async fn get_scheduled_session(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    id: Uuid,
+) -> DBResult<Option<responses::ScheduledSession>> {
+    let session = sqlx::query_as!(
+        responses::ScheduledSession,
+        r#"
+        SELECT id, room_id, title, description, starts_at, duration, recurrence,
+            invitee_ids, user_ids, created_by, created_at, updated_at
+        FROM public."ScheduledSession"
+        WHERE id = $2 AND room_id = $1;
+        "#,
+        room_id,
+        id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(session)
+}
+
+/// List a Room's scheduled sessions by when they first start
+pub async fn get_scheduled_sessions(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    page: i32,
+    per_page: i32,
+    total_pages: i32,
+) -> DBResult<Value> {
+    let sessions = sqlx::query_as!(
+        responses::ScheduledSession,
+        r#"
+        SELECT id, room_id, title, description, starts_at, duration, recurrence,
+            invitee_ids, user_ids, created_by, created_at, updated_at
+        FROM public."ScheduledSession"
+        WHERE room_id = $1
+        ORDER BY starts_at, id LIMIT $2 OFFSET $3;
+        "#,
+        room_id,
+        i64::from(per_page),
+        i64::from((page - 1) * per_page)
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(json!(PaginatedQueryResult::new(
+        sessions,
+        page,
+        total_pages
+    )))
+}
+
+pub async fn get_scheduled_session_count(conn: &mut PgConnection, room_id: Uuid) -> DBResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM public."ScheduledSession" WHERE room_id = $1;
+        "#,
+        room_id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(count)
+}
+
+/// Replace what is known about a scheduled session, keeping who made it
+pub async fn update_scheduled_session(
+    conn: &mut PgConnection,
+    id: Uuid,
+    record: &ScheduledSessionRecord<'_>,
+) -> DBResult<Option<responses::ScheduledSession>> {
+    let session = sqlx::query_as!(
+        responses::ScheduledSession,
+        r#"
+        UPDATE public."ScheduledSession" SET
+            title = $3,
+            description = $4,
+            starts_at = $5,
+            duration = $6,
+            recurrence = $7,
+            invitee_ids = $8,
+            user_ids = $9,
+            updated_at = now()
+        WHERE id = $2 AND room_id = $1
+        RETURNING id, room_id, title, description, starts_at, duration, recurrence,
+            invitee_ids, user_ids, created_by, created_at, updated_at;
+        "#,
+        record.room_id,
+        id,
+        record.title,
+        record.description,
+        record.starts_at,
+        record.duration,
+        record.recurrence,
+        record.invitee_ids,
+        record.user_ids
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(session)
+}
+
+pub async fn delete_scheduled_session(
+    conn: &mut PgConnection,
+    room_id: Uuid,
+    id: Uuid,
+) -> DBResult<Option<responses::ScheduledSession>> {
+    let session = sqlx::query_as!(
+        responses::ScheduledSession,
+        r#"
+        DELETE FROM public."ScheduledSession"
+        WHERE id = $2 AND room_id = $1
+        RETURNING id, room_id, title, description, starts_at, duration, recurrence,
+            invitee_ids, user_ids, created_by, created_at, updated_at;
+        "#,
+        room_id,
+        id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(session)
+}
+
+pub async fn get_calendar_feed(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> DBResult<Option<responses::CalendarFeed>> {
+    let feed = sqlx::query_as!(
+        responses::CalendarFeed,
+        r#"
+        SELECT user_id, token, created_at FROM public."CalendarFeed" WHERE user_id = $1;
+        "#,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(feed)
+}
+
+/// Give a user's calendar feed a new token, so the old address stops working
+pub async fn reset_calendar_feed(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    token: &str,
+) -> DBResult<responses::CalendarFeed> {
+    let feed = sqlx::query_as!(
+        responses::CalendarFeed,
+        r#"
+        INSERT INTO public."CalendarFeed" (user_id, token) VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET token = EXCLUDED.token, created_at = now()
+        RETURNING user_id, token, created_at;
+        "#,
+        user_id,
+        token
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(feed)
+}
+
+/// Turn off a user's calendar feed. Returns `None` if it wasn't on.
+pub async fn delete_calendar_feed(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> DBResult<Option<responses::CalendarFeed>> {
+    let feed = sqlx::query_as!(
+        responses::CalendarFeed,
+        r#"
+        DELETE FROM public."CalendarFeed" WHERE user_id = $1
+        RETURNING user_id, token, created_at;
+        "#,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(feed)
+}
+
+/// The scheduled sessions in the calendar with the given feed token: those
+/// its user was invited to or scheduled, in Rooms that are still open.
+/// Returns `None` if no feed has the token.
+pub async fn get_calendar_entries(
+    conn: &mut PgConnection,
+    token: &str,
+) -> DBResult<Option<Vec<responses::CalendarEntry>>> {
+    let user_id = sqlx::query_scalar!(
+        r#"
+        SELECT user_id FROM public."CalendarFeed" WHERE token = $1;
+        "#,
+        token
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(user_id) = user_id else {
+        return Ok(None);
+    };
+    let entries = sqlx::query_as!(
+        responses::CalendarEntry,
+        r#"
+        SELECT s.id, r.name AS room_name, s.title, s.description, s.starts_at,
+            s.duration, s.recurrence, s.created_at, s.updated_at
+        FROM public."ScheduledSession" s
+        JOIN public."Room" r ON r.id = s.room_id
+        WHERE (s.user_ids @> ARRAY[$1::uuid] OR s.created_by = $1) AND r.archived_at IS NULL
+        ORDER BY s.starts_at, s.id;
+        "#,
+        user_id
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(Some(entries))
+}
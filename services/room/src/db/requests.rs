@@ -0,0 +1,188 @@
+use chrono::{DateTime, Utc};
+use rocket::serde::Deserialize;
+use uuid::Uuid;
+
+use super::responses::{Point, Role, Visibility, Wall, Zone};
+use crate::realtime::events::{Media, Signal};
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RoomRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub visibility: Option<Visibility>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RoomUpdateRequest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub visibility: Option<Visibility>,
+    /// Let visitors without an account in with a guest token
+    pub guest_access: Option<bool>,
+    /// How many users may be connected at once, or 0 for no limit
+    pub capacity: Option<i32>,
+    /// Make everyone who can't moderate wait in the lobby to be admitted
+    pub lobby: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct MessageRequest {
+    /// The text of the message, mentioning users and groups as `<@id>`
+    pub body: String,
+    /// The message to reply to, in its thread
+    pub thread_id: Option<Uuid>,
+    /// Files uploaded to the room to send with the message
+    #[serde(default)]
+    pub attachment_ids: Vec<Uuid>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct MessageUpdateRequest {
+    pub body: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RoleRequest {
+    pub role: Role,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct InviteRequest {
+    pub role: Option<Role>,
+    pub max_uses: Option<i32>,
+    /// How long the invite lasts, in seconds
+    pub expires_in: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RedeemRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct SignalRequest {
+    /// The sender's own session
+    pub session_id: Uuid,
+    /// The session to deliver the signal to
+    pub to: Uuid,
+    pub signal: Signal,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct MediaRequest {
+    pub media: Vec<Media>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct MapRequest {
+    pub width: f64,
+    pub height: f64,
+    pub earshot: Option<f64>,
+    #[serde(default)]
+    pub walls: Vec<Wall>,
+    #[serde(default)]
+    pub zones: Vec<Zone>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct PositionRequest {
+    pub position: Option<Point>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct BreakoutRoomRequest {
+    pub name: String,
+    /// Users sent to this breakout by hand
+    #[serde(default)]
+    pub user_ids: Vec<Uuid>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct BreakoutRequest {
+    pub rooms: Vec<BreakoutRoomRequest>,
+    /// How long the breakouts stay open, in seconds
+    pub duration: i64,
+    /// Spread everyone else connected to the room across the breakouts at
+    /// random
+    #[serde(default)]
+    pub shuffle: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct ModerationRequest {
+    pub reason: Option<String>,
+    /// How long a ban or mute lasts, in seconds. Without one it lasts until
+    /// lifted.
+    pub duration: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct SlowModeRequest {
+    /// How many seconds members must wait between messages, or 0 to turn
+    /// slow mode off
+    pub seconds: i32,
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct TypingRequest {
+    pub typing: bool,
+    /// The thread being replied to, if it isn't the room itself
+    pub thread_id: Option<Uuid>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct ReadRequest {
+    /// The last message read
+    pub message_id: Uuid,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct DirectMessageRequest {
+    /// Everyone else in the conversation
+    pub user_ids: Vec<Uuid>,
+    #[serde(flatten)]
+    pub message: MessageRequest,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct KnockAnswerRequest {
+    /// Whether to let them in
+    pub admit: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct ScheduledSessionRequest {
+    pub title: String,
+    pub description: Option<String>,
+    /// When the first occurrence starts
+    pub starts_at: DateTime<Utc>,
+    /// How long each occurrence lasts, in seconds
+    pub duration: i32,
+    /// An RFC 5545 `RRULE` value, such as `FREQ=WEEKLY;BYDAY=MO`, for a
+    /// session that repeats
+    pub recurrence: Option<String>,
+    /// Users and groups to invite
+    #[serde(default)]
+    pub invitee_ids: Vec<Uuid>,
+}
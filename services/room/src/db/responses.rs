@@ -0,0 +1,383 @@
+use chrono::NaiveDateTime;
+use rocket::serde::Deserialize;
+use rocket::serde::Serialize;
+use sqlx::types::Json;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+#[sqlx(type_name = "room_visibility", rename_all = "lowercase")]
+pub enum Visibility {
+    Public,
+    Private,
+}
+
+/// What someone may do in a Room. Each Role can do everything the ones before
+/// it can.
+#[derive(
+    Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+#[sqlx(type_name = "room_role", rename_all = "lowercase")]
+pub enum Role {
+    Guest,
+    Member,
+    Moderator,
+    Owner,
+}
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Room {
+    pub id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub owner_id: Uuid,
+    pub visibility: Visibility,
+    pub archived_at: Option<NaiveDateTime>,
+    /// The Room a breakout Room was split off from
+    pub parent_id: Option<Uuid>,
+    /// When a breakout Room closes and everyone returns to the parent
+    pub closes_at: Option<NaiveDateTime>,
+    /// How many seconds members must wait between messages
+    pub slow_mode: i32,
+    /// Whether visitors without an account may come in with a guest token
+    pub guest_access: bool,
+    /// How many users may be connected at once before others have to wait in
+    /// the lobby
+    pub capacity: Option<i32>,
+    /// Whether everyone who can't moderate waits in the lobby to be admitted
+    pub lobby: bool,
+    /// Whether this is a direct conversation rather than a named Room
+    pub direct: bool,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Everyone who reacted to a Message with the same emoji
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: i64,
+    pub user_ids: Vec<Uuid>,
+}
+
+/// A file attached to a Message, as shown with it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct AttachedFile {
+    pub id: Uuid,
+    pub name: String,
+    pub content_type: String,
+    pub size: i64,
+}
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Message {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+    /// Empty once the Message has been deleted
+    pub body: String,
+    /// The first Message of the thread this is a reply in
+    pub thread_id: Option<Uuid>,
+    pub reply_count: i64,
+    pub reactions: Json<Vec<Reaction>>,
+    /// The users and groups the Message mentions
+    pub mentions: Vec<Uuid>,
+    pub attachments: Json<Vec<AttachedFile>>,
+    pub edited_at: Option<NaiveDateTime>,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+/// What a Message said before it was edited
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct MessageEdit {
+    pub body: String,
+    /// When this was replaced
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RoomMember {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RoomGroupGrant {
+    pub group_id: Uuid,
+    pub role: Role,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RoomInvite {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub created_by: Uuid,
+    pub role: Role,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A spot on a Room's map, measured from its top left corner
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// A straight wall that people on either side can't hear each other through
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Wall {
+    pub from: Point,
+    pub to: Point,
+}
+
+/// A named area of a map, like a meeting room. People inside a zone only hear
+/// others in the same zone.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Zone {
+    pub name: String,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Zone {
+    pub fn contains(&self, point: Point) -> bool {
+        point.x >= self.x
+            && point.x <= self.x + self.width
+            && point.y >= self.y
+            && point.y <= self.y + self.height
+    }
+}
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RoomMap {
+    pub room_id: Uuid,
+    pub width: f64,
+    pub height: f64,
+    /// How far apart two people can be and still hear each other
+    pub earshot: f64,
+    pub walls: Json<Vec<Wall>>,
+    pub zones: Json<Vec<Zone>>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl RoomMap {
+    pub fn contains(&self, point: Point) -> bool {
+        point.x >= 0.0 && point.x <= self.width && point.y >= 0.0 && point.y <= self.height
+    }
+
+    /// The first zone a point is in, if any
+    pub fn zone_at(&self, point: Point) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.contains(point))
+    }
+}
+
+/// A breakout Room and the users sent to it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Breakout {
+    pub room: Room,
+    pub user_ids: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+#[sqlx(type_name = "moderation_action", rename_all = "snake_case")]
+pub enum ModerationKind {
+    Mute,
+    Unmute,
+    Kick,
+    Ban,
+    Unban,
+    DeleteMessage,
+    SlowMode,
+}
+
+/// Something a moderator did in a Room
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct ModerationEntry {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub moderator_id: Uuid,
+    pub action: ModerationKind,
+    /// Who was acted on
+    pub user_id: Option<Uuid>,
+    pub message_id: Option<Uuid>,
+    pub reason: Option<String>,
+    /// When a ban or mute ends, if it does
+    pub expires_at: Option<NaiveDateTime>,
+    /// The new slow mode, in seconds
+    pub slow_mode: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A ban or mute on a user in a Room
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RoomRestriction {
+    pub user_id: Uuid,
+    pub created_by: Uuid,
+    pub reason: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A file uploaded to a Room. Its content is kept by the storage backend.
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Attachment {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+    /// Set once the file has been sent in a Message
+    pub message_id: Option<Uuid>,
+    pub name: String,
+    pub content_type: String,
+    pub size: i64,
+    pub created_at: NaiveDateTime,
+}
+
+/// A Message matching a search
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct SearchHit {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+    pub thread_id: Option<Uuid>,
+    /// The best matching parts of the body, HTML-escaped, with the matching
+    /// words wrapped in `<mark>`
+    pub snippet: String,
+    pub created_at: NaiveDateTime,
+}
+
+/// The last Message a user has read in a Room
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct ReadMarker {
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+    pub message_id: Uuid,
+    pub read_at: NaiveDateTime,
+}
+
+/// How much a user hasn't read in one of their Rooms
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct UnreadCount {
+    pub room_id: Uuid,
+    /// The last Message they read, if they've read any
+    pub message_id: Option<Uuid>,
+    pub unread: i64,
+    /// How many of the unread Messages mention them
+    pub mentions: i64,
+}
+
+/// A direct conversation and the users in it
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct DirectConversation {
+    pub room_id: Uuid,
+    pub user_ids: Vec<Uuid>,
+    pub last_message_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, sqlx::Type, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+#[sqlx(type_name = "knock_state", rename_all = "lowercase")]
+pub enum KnockState {
+    Waiting,
+    Admitted,
+    Denied,
+    /// Whoever knocked stopped waiting
+    Cancelled,
+}
+
+/// Someone asking to be let into a Room that is locked or full
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Knock {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+    /// The name a guest gave, for knocks from guests
+    pub guest_name: Option<String>,
+    pub state: KnockState,
+    pub answered_by: Option<Uuid>,
+    pub answered_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A time set aside to meet in a Room, once or over and over
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct ScheduledSession {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub title: String,
+    pub description: String,
+    /// When the first occurrence starts, in UTC
+    pub starts_at: NaiveDateTime,
+    /// How many seconds each occurrence lasts
+    pub duration: i32,
+    /// An RFC 5545 `RRULE` value, such as `FREQ=WEEKLY;BYDAY=MO`, for
+    /// sessions that repeat
+    pub recurrence: Option<String>,
+    /// The users and groups invited
+    pub invitee_ids: Vec<Uuid>,
+    /// The users invited, directly or through a group, as of the last change
+    pub user_ids: Vec<Uuid>,
+    pub created_by: Uuid,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// A ScheduledSession as it appears in someone's calendar
+#[derive(FromRow, Debug)]
+pub struct CalendarEntry {
+    pub id: Uuid,
+    pub room_name: String,
+    pub title: String,
+    pub description: String,
+    pub starts_at: NaiveDateTime,
+    pub duration: i32,
+    pub recurrence: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// The secret that gives a user's calendar feed its address
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct CalendarFeed {
+    pub user_id: Uuid,
+    pub token: String,
+    pub created_at: NaiveDateTime,
+}
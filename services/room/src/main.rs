@@ -1,12 +1,109 @@
 #[macro_use]
 extern crate rocket;
 
-#[get("/")]
-fn index() -> &'static str {
-    "room service!"
-}
+mod access;
+mod api;
+mod breakouts;
+mod calendar;
+mod db;
+mod realtime;
+mod storage;
+#[cfg(test)]
+mod testing;
+mod users;
+
+use dotenv::dotenv;
+use rocket::fairing::AdHoc;
+use rocket_db_pools::Database;
 
 #[launch]
 fn rocket() -> _ {
-    rocket::build().mount("/", routes![index])
+    dotenv().ok();
+    let migrations = AdHoc::try_on_ignite("database migrations", db::run_migrations);
+    rocket::build()
+        .attach(db::RoomDb::init())
+        .attach(migrations)
+        .attach(AdHoc::on_liftoff("breakout timer", |rocket| {
+            Box::pin(breakouts::start(rocket))
+        }))
+        .attach(AdHoc::try_on_ignite(
+            "realtime fanout",
+            realtime::fanout::init,
+        ))
+        .attach(AdHoc::try_on_ignite("attachment storage", storage::init))
+        .mount(
+            "/",
+            routes![
+                api::rooms::rooms_list,
+                api::rooms::rooms_add,
+                api::rooms::room_show,
+                api::rooms::room_update,
+                api::rooms::room_archive,
+                api::breakouts::room_breakouts_list,
+                api::breakouts::room_breakouts_open,
+                api::breakouts::room_breakouts_close,
+                api::members::room_members_list,
+                api::members::room_member_set,
+                api::members::room_member_remove,
+                api::members::room_groups_list,
+                api::members::room_group_set,
+                api::members::room_group_remove,
+                api::invites::room_invites_add,
+                api::invites::room_invites_list,
+                api::invites::room_invite_revoke,
+                api::invites::invite_redeem,
+                api::realtime::room_events,
+                api::lobby::room_knock,
+                api::lobby::room_knocks_list,
+                api::lobby::room_knock_answer,
+                api::lobby::room_knock_cancel,
+                api::lobby::room_knock_events,
+                api::realtime::room_participants,
+                api::signaling::room_signal_send,
+                api::signaling::room_session_media,
+                api::spatial::room_map_show,
+                api::spatial::room_map_set,
+                api::spatial::room_map_remove,
+                api::spatial::room_session_position,
+                api::messages::room_message_send,
+                api::messages::room_messages_list,
+                api::messages::room_message_show,
+                api::messages::room_message_edit,
+                api::messages::room_message_edits_list,
+                api::messages::room_message_remove,
+                api::messages::room_message_replies_list,
+                api::messages::room_message_react,
+                api::messages::room_message_unreact,
+                api::messages::mentions_list,
+                api::attachments::room_attachment_upload,
+                api::attachments::room_attachments_list,
+                api::attachments::room_attachment_download,
+                api::attachments::room_attachment_remove,
+                api::search::messages_search,
+                api::activity::room_session_typing,
+                api::activity::room_reads_list,
+                api::activity::room_read_set,
+                api::activity::unread_list,
+                api::direct::direct_message_send,
+                api::direct::direct_list,
+                api::schedule::room_schedule_list,
+                api::schedule::room_schedule_add,
+                api::schedule::room_schedule_show,
+                api::schedule::room_schedule_update,
+                api::schedule::room_schedule_remove,
+                api::schedule::calendar_feed_show,
+                api::schedule::calendar_feed_reset,
+                api::schedule::calendar_feed_remove,
+                api::schedule::calendar_feed,
+                api::moderation::room_moderation_log,
+                api::moderation::room_bans_list,
+                api::moderation::room_ban_set,
+                api::moderation::room_ban_remove,
+                api::moderation::room_mutes_list,
+                api::moderation::room_mute_set,
+                api::moderation::room_mute_remove,
+                api::moderation::room_kick,
+                api::moderation::room_slow_mode_set,
+            ],
+        )
 }
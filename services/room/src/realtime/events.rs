@@ -0,0 +1,178 @@
+use chrono::NaiveDateTime;
+use rocket::serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::responses::{
+    Breakout, Knock, Message, ModerationEntry, Point, Reaction, ReadMarker,
+};
+
+/// Someone connected to a room's event stream. A user with the room open in
+/// more than one place has a separate session for each.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub struct Participant {
+    pub session_id: Uuid,
+    pub user_id: Uuid,
+    /// The name a guest gave, since guests have no account to look up
+    pub guest_name: Option<String>,
+    pub joined_at: NaiveDateTime,
+    /// The kinds of media the session is publishing to the room
+    pub media: Vec<Media>,
+    /// Where the session's avatar is, in rooms laid out as a 2D space
+    pub position: Option<Point>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Media {
+    Audio,
+    Video,
+    Screen,
+}
+
+/// An ICE candidate, shaped like a browser's `RTCIceCandidateInit`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct IceCandidate {
+    pub candidate: String,
+    pub sdp_mid: Option<String>,
+    #[serde(rename = "sdpMLineIndex")]
+    pub sdp_m_line_index: Option<u16>,
+    pub username_fragment: Option<String>,
+}
+
+/// WebRTC signaling relayed from one session to another. The room service
+/// doesn't look inside these, it only passes them along.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", tag = "kind", rename_all = "snake_case")]
+pub enum Signal {
+    Offer { sdp: String },
+    Answer { sdp: String },
+    Candidate { candidate: IceCandidate },
+}
+
+/// Events pushed to clients over a room's event stream
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// Sent only to a session that has just joined, describing who is there
+    Presence {
+        session_id: Uuid,
+        participants: Vec<Participant>,
+        /// Where the room's events had got to, for picking up from later
+        cursor: String,
+        /// Whether the client is picking up where it left off, in which case
+        /// the events it missed follow
+        resumed: bool,
+        /// The latest messages, for a client that couldn't pick up where it
+        /// left off
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        messages: Vec<Message>,
+    },
+    Joined {
+        participant: Participant,
+    },
+    Left {
+        participant: Participant,
+    },
+    Message {
+        message: Message,
+    },
+    /// Delivered only to the session it is addressed to
+    Signal {
+        from: Uuid,
+        from_user: Uuid,
+        to: Uuid,
+        signal: Signal,
+    },
+    /// A session started or stopped publishing media
+    Media {
+        participant: Participant,
+    },
+    /// A session's avatar moved, or left the map
+    Moved {
+        participant: Participant,
+    },
+    /// Sent to a room when it is split into breakout rooms
+    BreakoutsOpened {
+        breakouts: Vec<Breakout>,
+    },
+    /// Sent to a breakout room and its parent when the breakout closes.
+    /// Sessions in the breakout should rejoin the parent.
+    BreakoutClosed {
+        room_id: Uuid,
+        parent_id: Uuid,
+    },
+    /// A moderator did something in the room
+    Moderation {
+        entry: ModerationEntry,
+    },
+    MessageEdited {
+        message: Message,
+    },
+    MessageDeleted {
+        message_id: Uuid,
+    },
+    ReactionsChanged {
+        message_id: Uuid,
+        reactions: Vec<Reaction>,
+    },
+    /// A session started or stopped typing. Clients should treat it as
+    /// stopped if they hear nothing more for a few seconds, and these are not
+    /// replayed to clients that reconnect.
+    Typing {
+        session_id: Uuid,
+        user_id: Uuid,
+        thread_id: Option<Uuid>,
+        typing: bool,
+    },
+    /// A user read up to a message
+    Read {
+        marker: ReadMarker,
+    },
+    /// Someone knocked on the room's lobby, or stopped waiting there, or a
+    /// moderator answered them
+    Knock {
+        knock: Knock,
+    },
+    /// Delivered only to the session it is addressed to, which is then
+    /// disconnected
+    Kicked {
+        to: Uuid,
+        reason: Option<String>,
+    },
+    /// Delivered only to the session it is addressed to, whenever the group
+    /// of sessions it can hear changes
+    Earshot {
+        to: Uuid,
+        zone: Option<String>,
+        session_ids: Vec<Uuid>,
+    },
+}
+
+impl Event {
+    /// The one session an event is meant for, if it isn't for everyone
+    pub fn recipient(&self) -> Option<Uuid> {
+        match self {
+            Event::Signal { to, .. } | Event::Earshot { to, .. } | Event::Kicked { to, .. } => {
+                Some(*to)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether an event only matters at the moment it is sent, so isn't
+    /// worth replaying to a client that missed it
+    pub fn is_fleeting(&self) -> bool {
+        matches!(self, Event::Typing { .. })
+    }
+}
+
+/// An event numbered by its place in the room's events
+#[derive(Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Sequenced {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
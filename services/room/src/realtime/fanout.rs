@@ -0,0 +1,348 @@
+//! Passing room updates between instances of the service.
+//!
+//! With a single instance there is nobody to pass them to, which is what the
+//! default `Local` backend does. The `Postgres` backend sends them over
+//! LISTEN/NOTIFY, so replicas only need the database they already share.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use envconfig::Envconfig;
+use rocket::fairing;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::sync::mpsc;
+use rocket::tokio::time;
+use rocket::{Build, Rocket};
+use rocket_db_pools::Database;
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::events::{Event, Participant};
+use super::Hub;
+use crate::db;
+use crate::db::responses::{Knock, RoomMap};
+
+/// The channel instances NOTIFY each other on
+const CHANNEL: &str = "yonder_room_updates";
+/// NOTIFY payloads must be shorter than 8000 bytes. Larger updates are kept
+/// in the database and only a reference to them is sent.
+const MAX_PAYLOAD: usize = 7900;
+/// How often each instance tells the others which sessions it has
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// How long an instance can go unheard before its sessions are dropped
+const INSTANCE_TIMEOUT: Duration = Duration::from_secs(20);
+/// How long updates too large to NOTIFY are kept for the others to read
+const STASH_LIFETIME: i64 = 60;
+
+#[derive(Envconfig)]
+struct Config {
+    /// `memory` for a single instance, or `postgres` to run several
+    #[envconfig(from = "YONDER_FANOUT", default = "memory")]
+    fanout: String,
+}
+
+/// The sessions connected to one instance in a room
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RoomSessions {
+    pub room_id: Uuid,
+    pub participants: Vec<Participant>,
+}
+
+/// A change to the rooms on one instance that the others need to make too
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+pub enum Update {
+    Event {
+        room_id: Uuid,
+        event: Event,
+    },
+    Map {
+        room_id: Uuid,
+        map: Option<RoomMap>,
+    },
+    Close {
+        room_id: Uuid,
+        parent_id: Uuid,
+    },
+    Knock {
+        knock: Knock,
+    },
+    /// Sent regularly by every instance with the sessions connected to it
+    Alive {
+        rooms: Vec<RoomSessions>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Envelope {
+    /// The instance the update came from
+    pub origin: Uuid,
+    pub update: Update,
+}
+
+pub trait Fanout: Send + Sync {
+    /// Pass an update on to the other instances. This is called from
+    /// synchronous code, so it mustn't block.
+    fn publish(&self, envelope: Envelope);
+
+    /// Start applying other instances' updates to a hub
+    fn start(&self, hub: Hub);
+}
+
+/// For running a single instance
+pub struct Local;
+
+impl Fanout for Local {
+    fn publish(&self, _envelope: Envelope) {}
+
+    fn start(&self, _hub: Hub) {}
+}
+
+pub struct Postgres {
+    pool: PgPool,
+    sender: mpsc::UnboundedSender<Envelope>,
+    receiver: Mutex<Option<mpsc::UnboundedReceiver<Envelope>>>,
+}
+
+impl Postgres {
+    pub fn new(pool: PgPool) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Postgres {
+            pool,
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+}
+
+impl Fanout for Postgres {
+    fn publish(&self, envelope: Envelope) {
+        let _ = self.sender.send(envelope);
+    }
+
+    fn start(&self, hub: Hub) {
+        if let Some(receiver) = self.receiver.lock().unwrap().take() {
+            rocket::tokio::spawn(send_updates(self.pool.clone(), receiver));
+        }
+        rocket::tokio::spawn(receive_updates(self.pool.clone(), hub.clone()));
+        rocket::tokio::spawn(heartbeat(self.pool.clone(), hub));
+    }
+}
+
+/// NOTIFY the other instances of updates in the order they were published
+async fn send_updates(pool: PgPool, mut receiver: mpsc::UnboundedReceiver<Envelope>) {
+    while let Some(envelope) = receiver.recv().await {
+        let Ok(payload) = rocket::serde::json::to_string(&envelope) else {
+            continue;
+        };
+        let result = match pool.acquire().await {
+            Ok(mut conn) if payload.len() <= MAX_PAYLOAD => {
+                db::notify(&mut conn, CHANNEL, &payload)
+                    .await
+                    .map_err(|e| e.0)
+            }
+            Ok(mut conn) => match db::stash_update(&mut conn, &payload).await {
+                Ok(id) => db::notify(&mut conn, CHANNEL, &format!("{} {}", envelope.origin, id))
+                    .await
+                    .map_err(|e| e.0),
+                Err(e) => Err(e.0),
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("Failed to publish a room update: {}", e);
+        }
+    }
+}
+
+/// Read an update from a notification's payload, which is either the update
+/// itself or says where it was stashed
+async fn read_update(pool: &PgPool, hub: &Hub, payload: &str) -> Option<Envelope> {
+    let payload = if payload.starts_with('{') {
+        payload.to_string()
+    } else {
+        let (origin, id) = payload.split_once(' ')?;
+        if origin.parse::<Uuid>().ok()? == hub.instance() {
+            return None;
+        }
+        let mut conn = pool.acquire().await.ok()?;
+        db::get_stashed_update(&mut conn, id.parse().ok()?)
+            .await
+            .ok()??
+    };
+    match rocket::serde::json::from_str(&payload) {
+        Ok(envelope) => Some(envelope),
+        Err(e) => {
+            error!("Failed to read a room update: {}", e);
+            None
+        }
+    }
+}
+
+/// LISTEN for other instances' updates and apply them
+async fn receive_updates(pool: PgPool, hub: Hub) {
+    loop {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to listen for room updates: {}", e);
+                time::sleep(HEARTBEAT_INTERVAL).await;
+                continue;
+            }
+        };
+        if let Err(e) = listener.listen(CHANNEL).await {
+            error!("Failed to listen for room updates: {}", e);
+            time::sleep(HEARTBEAT_INTERVAL).await;
+            continue;
+        }
+        loop {
+            match listener.recv().await {
+                Ok(notification) => {
+                    if let Some(envelope) = read_update(&pool, &hub, notification.payload()).await {
+                        hub.apply(envelope);
+                    }
+                }
+                Err(e) => {
+                    error!("Lost the connection for room updates: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Regularly tell the other instances which sessions are here, forget the
+/// ones that have stopped, and clear out old stashed updates
+async fn heartbeat(pool: PgPool, hub: Hub) {
+    let mut interval = time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+        hub.announce();
+        hub.expire(INSTANCE_TIMEOUT);
+        if let Ok(mut conn) = pool.acquire().await {
+            if let Err(e) = db::prune_stashed_updates(&mut conn, STASH_LIFETIME).await {
+                error!("Failed to clear out old room updates: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Set up the hub with the backend named by `YONDER_FANOUT`
+pub async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match Config::init_from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to read the fanout configuration: {}", e);
+            return Err(rocket);
+        }
+    };
+    let fanout: Arc<dyn Fanout> = match config.fanout.as_str() {
+        "memory" => Arc::new(Local),
+        "postgres" => match db::RoomDb::fetch(&rocket) {
+            Some(db) => Arc::new(Postgres::new((**db).clone())),
+            None => return Err(rocket),
+        },
+        other => {
+            error!("Unknown fanout backend: {}", other);
+            return Err(rocket);
+        }
+    };
+    let hub = Hub::new(fanout.clone());
+    fanout.start(hub.clone());
+    Ok(rocket.manage(hub))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::realtime::events::Signal;
+    use crate::realtime::Session;
+    use crate::testing;
+
+    /// A hub passing updates on through the database, as each replica's does
+    fn replica(pool: &PgPool) -> Hub {
+        let fanout = Arc::new(Postgres::new(pool.clone()));
+        let hub = Hub::new(fanout.clone());
+        fanout.start(hub.clone());
+        hub
+    }
+
+    /// Wait for a user to turn up in a room on a hub. Updates sent before a
+    /// replica is listening are missed, but its heartbeats make up for them.
+    async fn wait_until_present(hub: &Hub, room_id: Uuid, user_id: Uuid) {
+        for _ in 0..100 {
+            if hub.is_present(room_id, user_id) {
+                return;
+            }
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("the other replica never heard of the user");
+    }
+
+    /// The next event a session is sent other than someone joining
+    async fn next(session: &mut Session) -> Event {
+        loop {
+            let event = time::timeout(Duration::from_secs(5), session.recv());
+            match event.await.expect("no event came in time").unwrap().event {
+                Event::Joined { .. } => continue,
+                event => return event,
+            }
+        }
+    }
+
+    fn session_id(session: &Session) -> Uuid {
+        match session.presence(Vec::new()) {
+            Event::Presence { session_id, .. } => session_id,
+            _ => unreachable!(),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn replicas_hear_each_others_broadcasts() {
+        let client = testing::client().await;
+        let pool = testing::database(&client);
+        let (a, b) = (replica(pool), replica(pool));
+        let (room_id, a_user, b_user) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut a_session = a.join(room_id, a_user, None, None, None);
+        let mut b_session = b.join(room_id, b_user, None, None, None);
+        wait_until_present(&a, room_id, b_user).await;
+        wait_until_present(&b, room_id, a_user).await;
+
+        // Each replica's sessions hear what was sent on either of them
+        for hub in [&a, &b] {
+            let message_id = Uuid::new_v4();
+            hub.broadcast(room_id, Event::MessageDeleted { message_id });
+            for session in [&mut a_session, &mut b_session] {
+                assert!(matches!(
+                    next(session).await,
+                    Event::MessageDeleted { message_id: id } if id == message_id
+                ));
+            }
+        }
+
+        // Too large to NOTIFY, so it goes through the database
+        let sdp = "a=candidate:0 1 UDP 2122252543 192.0.2.1 49152 typ host\r\n".repeat(200);
+        let offer = Event::Signal {
+            from: session_id(&a_session),
+            from_user: a_user,
+            to: session_id(&b_session),
+            signal: Signal::Offer { sdp: sdp.clone() },
+        };
+        assert!(rocket::serde::json::to_string(&offer).unwrap().len() > MAX_PAYLOAD);
+        a.broadcast(room_id, offer);
+        assert!(matches!(
+            next(&mut b_session).await,
+            Event::Signal { signal: Signal::Offer { sdp: received }, .. } if received == sdp
+        ));
+
+        drop(a_session);
+        assert!(matches!(
+            next(&mut b_session).await,
+            Event::Left { participant } if participant.user_id == a_user
+        ));
+        assert!(!b.is_present(room_id, a_user));
+    }
+}
@@ -0,0 +1,1083 @@
+//! Fanout of room events to the clients connected to each room.
+//!
+//! Every room with at least one participant has a broadcast channel. A
+//! `Session` subscribes to it when joining and announces that it has left when
+//! it is dropped, which happens when the client disconnects.
+//!
+//! Events are numbered as they are sent, and the most recent are kept so that
+//! a client that reconnects can pick up where it left off. A room's channel is
+//! kept for `RESUME_GRACE` after its last participant leaves, so that someone
+//! who was alone in the room can do so too. The numbering starts afresh, with
+//! a new epoch, whenever a room's channel is created.
+//!
+//! When more than one instance of the service is running, each applies changes
+//! made by its own sessions straight away and publishes them through a
+//! `Fanout` backend so that the others can apply them too. Participants
+//! connected to other instances are tracked alongside local ones, so every
+//! instance sees the same presence. Each instance numbers events on its own,
+//! though, so a client can only pick up where it left off when it reconnects
+//! to the same instance; anywhere else it is sent the room afresh.
+
+pub mod events;
+pub mod fanout;
+pub mod spatial;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use rocket::tokio::sync::broadcast;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::db::responses::{Knock, Message, Point, RoomMap};
+use events::{Event, Media, Participant, Sequenced};
+use fanout::{Envelope, Fanout, RoomSessions, Update};
+
+/// How many events a slow session can fall behind before it starts missing them
+const CHANNEL_CAPACITY: usize = 1024;
+/// How many recent events are kept for clients that reconnect
+const HISTORY_CAPACITY: usize = 512;
+/// How long a room's channel outlives its last participant
+const RESUME_GRACE: Duration = Duration::from_secs(2 * 60);
+
+/// Where a client has got to in a room's events
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub epoch: Uuid,
+    pub seq: u64,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.epoch, self.seq)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (epoch, seq) = s.split_once(':').ok_or(())?;
+        Ok(Cursor {
+            epoch: epoch.parse().map_err(|_| ())?,
+            seq: seq.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+struct RoomChannel {
+    sender: broadcast::Sender<Sequenced>,
+    /// Identifies this run of the room's event numbering
+    epoch: Uuid,
+    /// The number of the last event sent
+    seq: u64,
+    /// Recent events sent to the whole room, oldest first
+    history: VecDeque<Sequenced>,
+    /// The number of the last event that no longer fits in `history`
+    trimmed: u64,
+    participants: Vec<Participant>,
+    /// The instance each participant is connected to
+    instances: HashMap<Uuid, Uuid>,
+    map: Option<RoomMap>,
+    /// The other sessions each session was last told it can hear
+    earshot: HashMap<Uuid, Vec<Uuid>>,
+    /// When the last participant left, while nobody is connected
+    emptied_at: Option<Instant>,
+}
+
+impl RoomChannel {
+    fn new() -> Self {
+        RoomChannel {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            epoch: Uuid::new_v4(),
+            seq: 0,
+            history: VecDeque::new(),
+            trimmed: 0,
+            participants: Vec::new(),
+            instances: HashMap::new(),
+            map: None,
+            earshot: HashMap::new(),
+            emptied_at: None,
+        }
+    }
+
+    /// Whether the channel is worth keeping: someone is connected, or was
+    /// recently enough that they may come back and pick up where they left off
+    fn is_live(&self) -> bool {
+        !self.participants.is_empty()
+            || self
+                .emptied_at
+                .is_some_and(|emptied_at| emptied_at.elapsed() < RESUME_GRACE)
+    }
+
+    fn cursor(&self) -> Cursor {
+        Cursor {
+            epoch: self.epoch,
+            seq: self.seq,
+        }
+    }
+
+    /// Number an event and send it to the room, remembering it unless it is
+    /// only for one session or only matters for a moment
+    fn emit(&mut self, event: Event) {
+        self.seq += 1;
+        let event = Sequenced {
+            seq: self.seq,
+            event,
+        };
+        if event.event.recipient().is_none() && !event.event.is_fleeting() {
+            self.history.push_back(event.clone());
+            if self.history.len() > HISTORY_CAPACITY {
+                if let Some(oldest) = self.history.pop_front() {
+                    self.trimmed = oldest.seq;
+                }
+            }
+        }
+        let _ = self.sender.send(event);
+    }
+
+    /// The events sent to the whole room since `since`, or `None` if some
+    /// of them have been forgotten
+    fn since(&self, since: Cursor) -> Option<Vec<Sequenced>> {
+        if since.epoch != self.epoch || since.seq < self.trimmed || since.seq > self.seq {
+            return None;
+        }
+        Some(
+            self.history
+                .iter()
+                .filter(|event| event.seq > since.seq)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Add a participant and tell the room, unless they're already there
+    fn add(&mut self, participant: Participant, instance: Uuid) {
+        if self
+            .participants
+            .iter()
+            .any(|p| p.session_id == participant.session_id)
+        {
+            return;
+        }
+        self.instances.insert(participant.session_id, instance);
+        self.emit(Event::Joined {
+            participant: participant.clone(),
+        });
+        self.participants.push(participant);
+        self.emptied_at = None;
+    }
+
+    /// Remove a participant and tell the room
+    fn remove(&mut self, session_id: Uuid) -> Option<Participant> {
+        let index = self
+            .participants
+            .iter()
+            .position(|p| p.session_id == session_id)?;
+        let participant = self.participants.remove(index);
+        self.instances.remove(&session_id);
+        self.emit(Event::Left {
+            participant: participant.clone(),
+        });
+        self.regroup();
+        if self.participants.is_empty() {
+            self.emptied_at = Some(Instant::now());
+        }
+        Some(participant)
+    }
+
+    /// Replace what is known about a participant, telling the room with
+    /// `event`. Returns `false` if they aren't in the room.
+    fn replace(&mut self, participant: Participant, event: Event) -> bool {
+        let Some(existing) = self
+            .participants
+            .iter_mut()
+            .find(|p| p.session_id == participant.session_id)
+        else {
+            return false;
+        };
+        *existing = participant;
+        self.emit(event);
+        self.regroup();
+        true
+    }
+
+    /// Use a new floor plan. Without one the room is no longer a 2D space, so
+    /// everyone's avatar is taken off the map.
+    fn set_map(&mut self, map: Option<RoomMap>) {
+        if map.is_none() {
+            let mut moved = Vec::new();
+            for participant in &mut self.participants {
+                if participant.position.take().is_some() {
+                    moved.push(participant.clone());
+                }
+            }
+            for participant in moved {
+                self.emit(Event::Moved { participant });
+            }
+        }
+        self.map = map;
+        self.regroup();
+    }
+
+    /// Work out who can hear whom and tell every session whose group changed
+    fn regroup(&mut self) {
+        let mut earshot = HashMap::new();
+        if let Some(map) = &self.map {
+            let positions: Vec<(Uuid, Point)> = self
+                .participants
+                .iter()
+                .filter_map(|p| p.position.map(|position| (p.session_id, position)))
+                .collect();
+            for group in spatial::clusters(map, &positions) {
+                for session_id in &group {
+                    let others = group.iter().copied().filter(|id| id != session_id);
+                    earshot.insert(*session_id, others.collect::<Vec<_>>());
+                }
+            }
+        }
+        let mut changes = Vec::new();
+        for participant in &self.participants {
+            let session_ids = earshot.remove(&participant.session_id).unwrap_or_default();
+            let previous = self
+                .earshot
+                .get(&participant.session_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            if session_ids == previous {
+                continue;
+            }
+            let zone = self.map.as_ref().and_then(|map| {
+                let zone = map.zone_at(participant.position?)?;
+                Some(zone.name.clone())
+            });
+            changes.push((participant.session_id, zone, session_ids));
+        }
+        for (to, zone, session_ids) in changes {
+            self.earshot.insert(to, session_ids.clone());
+            self.emit(Event::Earshot {
+                to,
+                zone,
+                session_ids,
+            });
+        }
+        let participants = &self.participants;
+        self.earshot
+            .retain(|id, _| participants.iter().any(|p| p.session_id == *id));
+    }
+}
+
+#[derive(Clone)]
+pub struct Hub {
+    rooms: Arc<Mutex<HashMap<Uuid, RoomChannel>>>,
+    /// Identifies this instance of the service to the others
+    instance: Uuid,
+    fanout: Arc<dyn Fanout>,
+    /// When each of the other instances was last heard from
+    instances: Arc<Mutex<HashMap<Uuid, Instant>>>,
+    /// Knocks as they change, for whoever is waiting on them in a lobby
+    lobby: broadcast::Sender<Knock>,
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Hub::new(Arc::new(fanout::Local))
+    }
+}
+
+impl Hub {
+    pub fn new(fanout: Arc<dyn Fanout>) -> Self {
+        Hub {
+            rooms: Arc::default(),
+            instance: Uuid::new_v4(),
+            fanout,
+            instances: Arc::default(),
+            lobby: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+
+    pub fn instance(&self) -> Uuid {
+        self.instance
+    }
+
+    fn publish(&self, update: Update) {
+        self.fanout.publish(Envelope {
+            origin: self.instance,
+            update,
+        });
+    }
+
+    /// Join a room, announcing the new participant to everyone already there.
+    /// `map` is the room's floor plan, if it is laid out as a 2D space. A
+    /// client that was connected before can pass where it got to, and if the
+    /// events it missed are still known they are replayed to the session.
+    pub fn join(
+        &self,
+        room_id: Uuid,
+        user_id: Uuid,
+        guest_name: Option<String>,
+        map: Option<RoomMap>,
+        since: Option<Cursor>,
+    ) -> Session {
+        let participant = Participant {
+            session_id: Uuid::new_v4(),
+            user_id,
+            guest_name,
+            joined_at: Utc::now().naive_utc(),
+            media: Vec::new(),
+            position: None,
+        };
+        let session = {
+            let mut rooms = self.rooms.lock().unwrap();
+            rooms.retain(|_, channel| channel.is_live());
+            let channel = rooms.entry(room_id).or_insert_with(RoomChannel::new);
+            let missed = since.and_then(|since| channel.since(since));
+            channel.add(participant.clone(), self.instance);
+            channel.set_map(map);
+            Session {
+                hub: self.clone(),
+                room_id,
+                participant: participant.clone(),
+                participants: channel.participants.clone(),
+                cursor: channel.cursor(),
+                missed,
+                receiver: channel.sender.subscribe(),
+                kicked: false,
+            }
+        };
+        self.publish(Update::Event {
+            room_id,
+            event: Event::Joined { participant },
+        });
+        session
+    }
+
+    fn leave(&self, room_id: Uuid, session_id: Uuid) {
+        let Some(participant) = self.remove(room_id, session_id) else {
+            return;
+        };
+        self.publish(Update::Event {
+            room_id,
+            event: Event::Left { participant },
+        });
+    }
+
+    fn remove(&self, room_id: Uuid, session_id: Uuid) -> Option<Participant> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let channel = rooms.get_mut(&room_id)?;
+        let participant = channel.remove(session_id)?;
+        rooms.retain(|_, channel| channel.is_live());
+        Some(participant)
+    }
+
+    /// The sessions currently connected to a room
+    pub fn participants(&self, room_id: Uuid) -> Vec<Participant> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms
+            .get(&room_id)
+            .map(|channel| channel.participants.clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether a user has at least one session connected to a room
+    pub fn is_present(&self, room_id: Uuid, user_id: Uuid) -> bool {
+        let rooms = self.rooms.lock().unwrap();
+        rooms
+            .get(&room_id)
+            .is_some_and(|channel| channel.participants.iter().any(|p| p.user_id == user_id))
+    }
+
+    /// How many users have a session connected to a room
+    pub fn user_count(&self, room_id: Uuid) -> usize {
+        let rooms = self.rooms.lock().unwrap();
+        rooms
+            .get(&room_id)
+            .map(|channel| {
+                let users: HashSet<Uuid> = channel.participants.iter().map(|p| p.user_id).collect();
+                users.len()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The user a session in a room belongs to, if it is still connected
+    pub fn session_user(&self, room_id: Uuid, session_id: Uuid) -> Option<Uuid> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms.get(&room_id).and_then(|channel| {
+            channel
+                .participants
+                .iter()
+                .find(|p| p.session_id == session_id)
+                .map(|p| p.user_id)
+        })
+    }
+
+    /// A user's sessions in a room
+    pub fn sessions_of(&self, room_id: Uuid, user_id: Uuid) -> Vec<Uuid> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms
+            .get(&room_id)
+            .map(|channel| {
+                channel
+                    .participants
+                    .iter()
+                    .filter(|p| p.user_id == user_id)
+                    .map(|p| p.session_id)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Disconnect all of a user's sessions in a room, telling each why
+    pub fn kick(&self, room_id: Uuid, user_id: Uuid, reason: Option<String>) {
+        let kicked = {
+            let mut rooms = self.rooms.lock().unwrap();
+            let Some(channel) = rooms.get_mut(&room_id) else {
+                return;
+            };
+            let sessions: Vec<Uuid> = channel
+                .participants
+                .iter()
+                .filter(|p| p.user_id == user_id)
+                .map(|p| p.session_id)
+                .collect();
+            let mut kicked = Vec::new();
+            for to in sessions {
+                channel.emit(Event::Kicked {
+                    to,
+                    reason: reason.clone(),
+                });
+                kicked.extend(channel.remove(to));
+            }
+            kicked
+        };
+        for participant in kicked {
+            self.publish(Update::Event {
+                room_id,
+                event: Event::Kicked {
+                    to: participant.session_id,
+                    reason: reason.clone(),
+                },
+            });
+            self.publish(Update::Event {
+                room_id,
+                event: Event::Left { participant },
+            });
+        }
+    }
+
+    /// Change a participant, telling everyone in the room with the event
+    /// `announce` makes from the changed participant
+    fn edit(
+        &self,
+        room_id: Uuid,
+        session_id: Uuid,
+        change: impl FnOnce(&mut Participant),
+        announce: fn(Participant) -> Event,
+    ) -> Option<Participant> {
+        let participant = {
+            let mut rooms = self.rooms.lock().unwrap();
+            let channel = rooms.get_mut(&room_id)?;
+            let mut participant = channel
+                .participants
+                .iter()
+                .find(|p| p.session_id == session_id)?
+                .clone();
+            change(&mut participant);
+            channel.replace(participant.clone(), announce(participant.clone()));
+            participant
+        };
+        self.publish(Update::Event {
+            room_id,
+            event: announce(participant.clone()),
+        });
+        Some(participant)
+    }
+
+    /// Record what a session is publishing and tell everyone in the room
+    pub fn set_media(
+        &self,
+        room_id: Uuid,
+        session_id: Uuid,
+        media: Vec<Media>,
+    ) -> Option<Participant> {
+        self.edit(
+            room_id,
+            session_id,
+            |participant| participant.media = media,
+            |participant| Event::Media { participant },
+        )
+    }
+
+    /// Move a session's avatar, telling everyone in the room and updating who
+    /// can hear whom
+    pub fn set_position(
+        &self,
+        room_id: Uuid,
+        session_id: Uuid,
+        position: Option<Point>,
+    ) -> Option<Participant> {
+        self.edit(
+            room_id,
+            session_id,
+            |participant| participant.position = position,
+            |participant| Event::Moved { participant },
+        )
+    }
+
+    fn apply_map(&self, room_id: Uuid, map: Option<RoomMap>) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(channel) = rooms.get_mut(&room_id) {
+            channel.set_map(map);
+        }
+    }
+
+    /// Use a new floor plan for a room's connected sessions. Without one the
+    /// room is no longer a 2D space, so everyone's avatar is taken off the map.
+    pub fn set_map(&self, room_id: Uuid, map: Option<RoomMap>) {
+        self.apply_map(room_id, map.clone());
+        self.publish(Update::Map { room_id, map });
+    }
+
+    fn apply_close(&self, room_id: Uuid, parent_id: Uuid) {
+        let event = Event::BreakoutClosed { room_id, parent_id };
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(mut channel) = rooms.remove(&room_id) {
+            channel.emit(event.clone());
+        }
+        if let Some(channel) = rooms.get_mut(&parent_id) {
+            channel.emit(event);
+        }
+    }
+
+    /// Send everyone in a breakout room back to its parent. Their sessions in
+    /// the breakout end once they have been told.
+    pub fn close_breakout(&self, room_id: Uuid, parent_id: Uuid) {
+        self.apply_close(room_id, parent_id);
+        self.publish(Update::Close { room_id, parent_id });
+    }
+
+    fn apply_knock(&self, knock: Knock) {
+        let _ = self.lobby.send(knock.clone());
+        self.send(knock.room_id, Event::Knock { knock });
+    }
+
+    /// Tell a room, and whoever knocked if they are waiting in its lobby,
+    /// about a knock that is new or has changed
+    pub fn knock(&self, knock: Knock) {
+        self.apply_knock(knock.clone());
+        self.publish(Update::Knock { knock });
+    }
+
+    /// Wait in a room's lobby to hear what becomes of a knock
+    pub fn wait(&self, knock_id: Uuid) -> Waiting {
+        Waiting {
+            knock_id,
+            receiver: self.lobby.subscribe(),
+        }
+    }
+
+    /// Send an event to a room's channel, if it has one. It is kept for
+    /// whoever reconnects even if nobody is connected now, in which case this
+    /// returns `false`.
+    fn send(&self, room_id: Uuid, event: Event) -> bool {
+        let mut rooms = self.rooms.lock().unwrap();
+        match rooms.get_mut(&room_id) {
+            Some(channel) => {
+                channel.emit(event);
+                !channel.participants.is_empty()
+            }
+            None => false,
+        }
+    }
+
+    /// Send an event to every session connected to a room. Returns `false` if
+    /// nobody is connected.
+    pub fn broadcast(&self, room_id: Uuid, event: Event) -> bool {
+        let present = self.send(room_id, event.clone());
+        self.publish(Update::Event { room_id, event });
+        present
+    }
+
+    /// Apply an update published by another instance
+    pub fn apply(&self, envelope: Envelope) {
+        if envelope.origin == self.instance {
+            return;
+        }
+        self.instances
+            .lock()
+            .unwrap()
+            .insert(envelope.origin, Instant::now());
+        match envelope.update {
+            Update::Event { room_id, event } => self.apply_event(envelope.origin, room_id, event),
+            Update::Map { room_id, map } => self.apply_map(room_id, map),
+            Update::Close { room_id, parent_id } => self.apply_close(room_id, parent_id),
+            Update::Knock { knock } => self.apply_knock(knock),
+            Update::Alive { rooms } => self.reconcile(envelope.origin, rooms),
+        }
+    }
+
+    fn apply_event(&self, origin: Uuid, room_id: Uuid, event: Event) {
+        match event {
+            Event::Joined { participant } => {
+                let mut rooms = self.rooms.lock().unwrap();
+                let channel = rooms.entry(room_id).or_insert_with(RoomChannel::new);
+                channel.add(participant, origin);
+            }
+            Event::Left { participant } => {
+                self.remove(room_id, participant.session_id);
+            }
+            Event::Media { ref participant } | Event::Moved { ref participant } => {
+                let mut rooms = self.rooms.lock().unwrap();
+                if let Some(channel) = rooms.get_mut(&room_id) {
+                    channel.replace(participant.clone(), event);
+                }
+            }
+            event => {
+                self.send(room_id, event);
+            }
+        }
+    }
+
+    /// Tell the other instances which sessions are connected here, so that
+    /// they can catch up on anything they missed and know this one is alive
+    pub fn announce(&self) {
+        let rooms = self.rooms.lock().unwrap();
+        let rooms = rooms
+            .iter()
+            .map(|(room_id, channel)| RoomSessions {
+                room_id: *room_id,
+                participants: channel
+                    .participants
+                    .iter()
+                    .filter(|p| channel.instances.get(&p.session_id) == Some(&self.instance))
+                    .cloned()
+                    .collect(),
+            })
+            .filter(|room| !room.participants.is_empty())
+            .collect();
+        self.publish(Update::Alive { rooms });
+    }
+
+    /// Bring the sessions known to be connected to another instance in line
+    /// with what it says it has
+    fn reconcile(&self, origin: Uuid, sessions: Vec<RoomSessions>) {
+        let mut rooms = self.rooms.lock().unwrap();
+        for channel in rooms.values_mut() {
+            let gone: Vec<Uuid> = channel
+                .participants
+                .iter()
+                .filter(|p| channel.instances.get(&p.session_id) == Some(&origin))
+                .filter(|p| {
+                    !sessions.iter().any(|room| {
+                        room.participants
+                            .iter()
+                            .any(|other| other.session_id == p.session_id)
+                    })
+                })
+                .map(|p| p.session_id)
+                .collect();
+            for session_id in gone {
+                channel.remove(session_id);
+            }
+        }
+        for room in sessions {
+            let channel = rooms.entry(room.room_id).or_insert_with(RoomChannel::new);
+            for participant in room.participants {
+                let known = channel
+                    .participants
+                    .iter()
+                    .find(|p| p.session_id == participant.session_id);
+                match known {
+                    None => channel.add(participant, origin),
+                    Some(known) if *known != participant => {
+                        channel.replace(participant.clone(), Event::Moved { participant });
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        rooms.retain(|_, channel| channel.is_live());
+    }
+
+    /// Forget other instances that haven't been heard from for `timeout`,
+    /// taking their sessions out of every room
+    pub fn expire(&self, timeout: Duration) {
+        let stale: HashSet<Uuid> = {
+            let mut instances = self.instances.lock().unwrap();
+            let stale: HashSet<Uuid> = instances
+                .iter()
+                .filter(|(_, seen)| seen.elapsed() > timeout)
+                .map(|(instance, _)| *instance)
+                .collect();
+            instances.retain(|instance, _| !stale.contains(instance));
+            stale
+        };
+        if stale.is_empty() {
+            return;
+        }
+        let mut rooms = self.rooms.lock().unwrap();
+        for channel in rooms.values_mut() {
+            let gone: Vec<Uuid> = channel
+                .instances
+                .iter()
+                .filter(|(_, instance)| stale.contains(instance))
+                .map(|(session_id, _)| *session_id)
+                .collect();
+            for session_id in gone {
+                channel.remove(session_id);
+            }
+        }
+        rooms.retain(|_, channel| channel.is_live());
+    }
+}
+
+/// Someone waiting in a room's lobby
+pub struct Waiting {
+    knock_id: Uuid,
+    receiver: broadcast::Receiver<Knock>,
+}
+
+impl Waiting {
+    /// Wait for the knock to change, or `None` if changes may have been
+    /// missed, in which case the client should reconnect and look again
+    pub async fn recv(&mut self) -> Option<Knock> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(knock) if knock.id == self.knock_id => return Some(knock),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// A client's connection to a room
+pub struct Session {
+    hub: Hub,
+    room_id: Uuid,
+    participant: Participant,
+    /// Who was in the room when the session joined
+    participants: Vec<Participant>,
+    /// Where the room's events had got to when the session joined
+    cursor: Cursor,
+    /// The events missed since the client was last connected, if it is
+    /// picking up where it left off
+    missed: Option<Vec<Sequenced>>,
+    receiver: broadcast::Receiver<Sequenced>,
+    /// Whether the session has been kicked out of the room
+    kicked: bool,
+}
+
+impl Session {
+    /// Where the room's events had got to when the session joined
+    pub fn cursor(&self) -> Cursor {
+        self.cursor
+    }
+
+    /// Where a numbered event puts the session in the room's events
+    pub fn cursor_at(&self, event: &Sequenced) -> Cursor {
+        Cursor {
+            epoch: self.cursor.epoch,
+            seq: event.seq,
+        }
+    }
+
+    /// Whether the client is picking up where it left off
+    pub fn resumed(&self) -> bool {
+        self.missed.is_some()
+    }
+
+    /// The first event sent to the client, telling it who is in the room. A
+    /// client that couldn't pick up where it left off is also sent the room's
+    /// latest `messages`.
+    pub fn presence(&self, messages: Vec<Message>) -> Event {
+        Event::Presence {
+            session_id: self.participant.session_id,
+            participants: self.participants.clone(),
+            cursor: self.cursor.to_string(),
+            resumed: self.resumed(),
+            messages,
+        }
+    }
+
+    /// The events the client missed while it was disconnected, to be sent
+    /// straight after `presence`
+    pub fn take_missed(&mut self) -> Vec<Sequenced> {
+        self.missed.take().unwrap_or_default()
+    }
+
+    /// Wait for the next event in the room, or `None` once the room is gone
+    /// or the session has been kicked out of it. Events addressed to other
+    /// sessions are skipped. A client that falls too far behind is
+    /// disconnected, so that it can reconnect and pick up where it left off.
+    pub async fn recv(&mut self) -> Option<Sequenced> {
+        if self.kicked {
+            return None;
+        }
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => match event.event.recipient() {
+                    Some(to) if to != self.participant.session_id => continue,
+                    _ => {
+                        self.kicked = matches!(event.event, Event::Kicked { .. });
+                        return Some(event);
+                    }
+                },
+                Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.hub.leave(self.room_id, self.participant.session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deleted() -> Event {
+        Event::MessageDeleted {
+            message_id: Uuid::new_v4(),
+        }
+    }
+
+    fn seqs(events: &[Sequenced]) -> Vec<u64> {
+        events.iter().map(|event| event.seq).collect()
+    }
+
+    /// A channel that has sent `count` events to the whole room
+    fn channel_with(count: usize) -> RoomChannel {
+        let mut channel = RoomChannel::new();
+        for _ in 0..count {
+            channel.emit(deleted());
+        }
+        channel
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor {
+            epoch: Uuid::new_v4(),
+            seq: 42,
+        };
+        assert_eq!(cursor.to_string().parse::<Cursor>(), Ok(cursor));
+        assert_eq!(
+            format!("{}:0", cursor.epoch).parse::<Cursor>(),
+            Ok(Cursor {
+                epoch: cursor.epoch,
+                seq: 0
+            })
+        );
+    }
+
+    #[test]
+    fn cursor_rejects_malformed_values() {
+        let epoch = Uuid::new_v4();
+        for value in [
+            String::new(),
+            String::from(":"),
+            String::from("42"),
+            epoch.to_string(),
+            format!("{}:", epoch),
+            format!("{}:-1", epoch),
+            format!("{}:one", epoch),
+            format!("{}:1:2", epoch),
+            format!("{}: 1", epoch),
+            String::from("not-an-epoch:1"),
+            String::from(":1"),
+        ] {
+            assert_eq!(value.parse::<Cursor>(), Err(()), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn since_replays_events_in_the_window() {
+        let channel = channel_with(5);
+        let at = |seq| Cursor {
+            epoch: channel.epoch,
+            seq,
+        };
+        assert_eq!(
+            channel.since(at(2)).map(|events| seqs(&events)),
+            Some(vec![3, 4, 5])
+        );
+        assert_eq!(
+            channel.since(at(0)).map(|events| seqs(&events)),
+            Some(vec![1, 2, 3, 4, 5])
+        );
+        assert_eq!(
+            channel.since(at(5)).map(|events| seqs(&events)),
+            Some(vec![])
+        );
+        // Further along than the room has got
+        assert!(channel.since(at(6)).is_none());
+    }
+
+    #[test]
+    fn since_skips_events_not_worth_replaying() {
+        let mut channel = channel_with(1);
+        let cursor = channel.cursor();
+        channel.emit(Event::Typing {
+            session_id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            thread_id: None,
+            typing: true,
+        });
+        channel.emit(Event::Kicked {
+            to: Uuid::new_v4(),
+            reason: None,
+        });
+        channel.emit(deleted());
+        assert_eq!(
+            channel.since(cursor).map(|events| seqs(&events)),
+            Some(vec![4])
+        );
+    }
+
+    #[test]
+    fn since_gives_up_on_evicted_events() {
+        let channel = channel_with(HISTORY_CAPACITY + 10);
+        let at = |seq| Cursor {
+            epoch: channel.epoch,
+            seq,
+        };
+        assert_eq!(channel.trimmed, 10);
+        assert!(channel.since(at(0)).is_none());
+        assert!(channel.since(at(9)).is_none());
+        let replayed = channel.since(at(10)).unwrap();
+        assert_eq!(replayed.len(), HISTORY_CAPACITY);
+        assert_eq!(replayed.first().map(|event| event.seq), Some(11));
+    }
+
+    #[test]
+    fn since_gives_up_on_a_stale_epoch() {
+        let channel = channel_with(5);
+        let stale = Cursor {
+            epoch: Uuid::new_v4(),
+            seq: 2,
+        };
+        assert!(channel.since(stale).is_none());
+        assert!(channel_with(5).since(channel.cursor()).is_none());
+    }
+
+    #[test]
+    fn lone_participant_resumes_after_reconnecting() {
+        let hub = Hub::default();
+        let (room_id, user_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let session = hub.join(room_id, user_id, None, None, None);
+        hub.broadcast(room_id, deleted());
+        let cursor = Cursor {
+            seq: session.cursor().seq + 1,
+            ..session.cursor()
+        };
+        drop(session);
+        assert!(hub.participants(room_id).is_empty());
+        // Nobody is there to hear it, but it is kept for when they're back
+        assert!(!hub.broadcast(room_id, deleted()));
+
+        let mut session = hub.join(room_id, user_id, None, None, Some(cursor));
+        assert!(session.resumed());
+        assert_eq!(session.cursor().epoch, cursor.epoch);
+        let missed = session.take_missed();
+        assert!(matches!(
+            missed.as_slice(),
+            [
+                Sequenced {
+                    event: Event::Left { .. },
+                    ..
+                },
+                Sequenced {
+                    event: Event::MessageDeleted { .. },
+                    ..
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn rooms_empty_for_too_long_start_afresh() {
+        let hub = Hub::default();
+        let (room_id, user_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let session = hub.join(room_id, user_id, None, None, None);
+        let cursor = session.cursor();
+        drop(session);
+        hub.rooms
+            .lock()
+            .unwrap()
+            .get_mut(&room_id)
+            .unwrap()
+            .emptied_at = Some(Instant::now() - RESUME_GRACE);
+
+        let session = hub.join(room_id, user_id, None, None, Some(cursor));
+        assert!(!session.resumed());
+        assert_ne!(session.cursor().epoch, cursor.epoch);
+    }
+
+    #[test]
+    fn empty_rooms_are_forgotten_after_the_grace_period() {
+        let hub = Hub::default();
+        let (room_id, other_id) = (Uuid::new_v4(), Uuid::new_v4());
+        drop(hub.join(room_id, Uuid::new_v4(), None, None, None));
+        assert!(hub.rooms.lock().unwrap().contains_key(&room_id));
+        hub.rooms
+            .lock()
+            .unwrap()
+            .get_mut(&room_id)
+            .unwrap()
+            .emptied_at = Some(Instant::now() - RESUME_GRACE);
+        // Anyone joining any room clears out the ones nobody came back to
+        drop(hub.join(other_id, Uuid::new_v4(), None, None, None));
+        let rooms = hub.rooms.lock().unwrap();
+        assert!(!rooms.contains_key(&room_id));
+        assert!(rooms.contains_key(&other_id));
+    }
+
+    /// The next event a session is sent, failing the test if none comes soon
+    async fn next(session: &mut Session) -> Event {
+        let event = rocket::tokio::time::timeout(Duration::from_secs(5), session.recv());
+        event.await.unwrap().unwrap().event
+    }
+
+    #[rocket::async_test]
+    async fn participants_hear_each_other_through_a_local_hub() {
+        let hub = Hub::new(Arc::new(fanout::Local));
+        let (room_id, a, b) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut a_session = hub.join(room_id, a, None, None, None);
+        let mut b_session = hub.join(room_id, b, None, None, None);
+        let Event::Presence { participants, .. } = b_session.presence(Vec::new()) else {
+            unreachable!();
+        };
+        let present: Vec<Uuid> = participants.iter().map(|p| p.user_id).collect();
+        assert_eq!(present, [a, b]);
+        assert!(matches!(
+            next(&mut a_session).await,
+            Event::Joined { participant } if participant.user_id == b
+        ));
+
+        assert!(hub.broadcast(room_id, deleted()));
+        for session in [&mut a_session, &mut b_session] {
+            assert!(matches!(next(session).await, Event::MessageDeleted { .. }));
+        }
+
+        drop(b_session);
+        assert!(matches!(
+            next(&mut a_session).await,
+            Event::Left { participant } if participant.user_id == b
+        ));
+        let present: Vec<Uuid> = hub
+            .participants(room_id)
+            .iter()
+            .map(|p| p.user_id)
+            .collect();
+        assert_eq!(present, [a]);
+        assert!(!hub.is_present(room_id, b));
+    }
+}
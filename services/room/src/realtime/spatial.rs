@@ -0,0 +1,243 @@
+//! Who can hear whom in a room laid out as a 2D space.
+//!
+//! Two people are within earshot when they are close enough, in the same zone
+//! (or both outside every zone) and there is no wall between them. Anyone
+//! within earshot of someone in a group is part of the conversation, so the
+//! groups are the connected clusters of that relation.
+
+use uuid::Uuid;
+
+use crate::db::responses::{Point, RoomMap};
+
+fn distance(a: Point, b: Point) -> f64 {
+    (a.x - b.x).hypot(a.y - b.y)
+}
+
+/// Which side of the line through `a` and `b` a point is on
+fn orientation(a: Point, b: Point, point: Point) -> f64 {
+    (b.x - a.x) * (point.y - a.y) - (b.y - a.y) * (point.x - a.x)
+}
+
+/// Whether the segments `a1`-`a2` and `b1`-`b2` cross. Segments that only
+/// touch at an end count as crossing, so a wall can't be slipped past at its
+/// corner.
+fn crosses(a1: Point, a2: Point, b1: Point, b2: Point) -> bool {
+    let d1 = orientation(b1, b2, a1);
+    let d2 = orientation(b1, b2, a2);
+    let d3 = orientation(a1, a2, b1);
+    let d4 = orientation(a1, a2, b2);
+    if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+        return true;
+    }
+    let on_segment = |p: Point, q: Point, r: Point| {
+        r.x >= p.x.min(q.x) && r.x <= p.x.max(q.x) && r.y >= p.y.min(q.y) && r.y <= p.y.max(q.y)
+    };
+    (d1 == 0.0 && on_segment(b1, b2, a1))
+        || (d2 == 0.0 && on_segment(b1, b2, a2))
+        || (d3 == 0.0 && on_segment(a1, a2, b1))
+        || (d4 == 0.0 && on_segment(a1, a2, b2))
+}
+
+pub fn within_earshot(map: &RoomMap, a: Point, b: Point) -> bool {
+    distance(a, b) <= map.earshot
+        && map.zone_at(a).map(|zone| &zone.name) == map.zone_at(b).map(|zone| &zone.name)
+        && !map
+            .walls
+            .iter()
+            .any(|wall| crosses(a, b, wall.from, wall.to))
+}
+
+fn root(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+/// Group positioned sessions into conversations. Each group lists sessions in
+/// the order they were given, and someone out of earshot of everybody is in a
+/// group of their own.
+pub fn clusters(map: &RoomMap, positions: &[(Uuid, Point)]) -> Vec<Vec<Uuid>> {
+    let mut parents: Vec<usize> = (0..positions.len()).collect();
+    for i in 0..positions.len() {
+        for j in i + 1..positions.len() {
+            if within_earshot(map, positions[i].1, positions[j].1) {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+    let mut groups: Vec<(usize, Vec<Uuid>)> = Vec::new();
+    for (index, (session_id, _)) in positions.iter().enumerate() {
+        let group = root(&mut parents, index);
+        match groups.iter_mut().find(|(root, _)| *root == group) {
+            Some((_, sessions)) => sessions.push(*session_id),
+            None => groups.push((group, vec![*session_id])),
+        }
+    }
+    groups.into_iter().map(|(_, sessions)| sessions).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::responses::{Wall, Zone};
+    use chrono::Utc;
+    use sqlx::types::Json;
+
+    fn point(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    fn map(walls: Vec<Wall>, zones: Vec<Zone>) -> RoomMap {
+        let now = Utc::now().naive_utc();
+        RoomMap {
+            room_id: Uuid::new_v4(),
+            width: 100.0,
+            height: 100.0,
+            earshot: 10.0,
+            walls: Json(walls),
+            zones: Json(zones),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn sessions(count: usize) -> Vec<Uuid> {
+        (0..count).map(|_| Uuid::new_v4()).collect()
+    }
+
+    #[test]
+    fn crosses_when_segments_intersect() {
+        assert!(crosses(
+            point(0.0, 0.0),
+            point(10.0, 10.0),
+            point(0.0, 10.0),
+            point(10.0, 0.0)
+        ));
+        assert!(!crosses(
+            point(0.0, 0.0),
+            point(10.0, 0.0),
+            point(0.0, 5.0),
+            point(10.0, 5.0)
+        ));
+        // Pointing at a wall but stopping short of it
+        assert!(!crosses(
+            point(0.0, 5.0),
+            point(4.0, 5.0),
+            point(5.0, 0.0),
+            point(5.0, 10.0)
+        ));
+    }
+
+    #[test]
+    fn crosses_when_touching_a_wall_end() {
+        assert!(crosses(
+            point(0.0, 5.0),
+            point(10.0, 5.0),
+            point(5.0, 5.0),
+            point(5.0, 10.0)
+        ));
+        assert!(crosses(
+            point(5.0, 5.0),
+            point(10.0, 5.0),
+            point(5.0, 0.0),
+            point(5.0, 10.0)
+        ));
+        // Along the wall itself
+        assert!(crosses(
+            point(0.0, 0.0),
+            point(10.0, 0.0),
+            point(5.0, 0.0),
+            point(20.0, 0.0)
+        ));
+        assert!(!crosses(
+            point(0.0, 0.0),
+            point(4.0, 0.0),
+            point(5.0, 0.0),
+            point(20.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn clusters_chain_through_someone_in_the_middle() {
+        let ids = sessions(3);
+        // A and C are 20 apart, but both can hear B
+        let positions = [
+            (ids[0], point(0.0, 0.0)),
+            (ids[1], point(10.0, 0.0)),
+            (ids[2], point(20.0, 0.0)),
+        ];
+        assert_eq!(
+            clusters(&map(vec![], vec![]), &positions),
+            vec![ids.clone()]
+        );
+        assert!(!within_earshot(
+            &map(vec![], vec![]),
+            positions[0].1,
+            positions[2].1
+        ));
+    }
+
+    #[test]
+    fn clusters_include_someone_exactly_at_the_radius() {
+        let ids = sessions(2);
+        let at = [(ids[0], point(0.0, 0.0)), (ids[1], point(6.0, 8.0))];
+        assert_eq!(clusters(&map(vec![], vec![]), &at), vec![ids.clone()]);
+        let beyond = [(ids[0], point(0.0, 0.0)), (ids[1], point(6.0, 8.001))];
+        assert_eq!(
+            clusters(&map(vec![], vec![]), &beyond),
+            [vec![ids[0]], vec![ids[1]]]
+        );
+    }
+
+    #[test]
+    fn clusters_split_when_someone_moves_away() {
+        let map = map(vec![], vec![]);
+        let ids = sessions(3);
+        let mut positions = vec![
+            (ids[0], point(0.0, 0.0)),
+            (ids[1], point(10.0, 0.0)),
+            (ids[2], point(20.0, 0.0)),
+        ];
+        assert_eq!(clusters(&map, &positions), vec![ids.clone()]);
+        // Without B in the middle, A and C are out of earshot too
+        positions[1].1 = point(50.0, 50.0);
+        assert_eq!(
+            clusters(&map, &positions),
+            [vec![ids[0]], vec![ids[1]], vec![ids[2]]]
+        );
+        positions[1].1 = point(20.0, 5.0);
+        assert_eq!(
+            clusters(&map, &positions),
+            [vec![ids[0]], vec![ids[1], ids[2]]]
+        );
+    }
+
+    #[test]
+    fn clusters_keep_walls_and_zones_apart() {
+        let wall = Wall {
+            from: point(5.0, -10.0),
+            to: point(5.0, 10.0),
+        };
+        let zone = Zone {
+            name: String::from("Booth"),
+            x: 20.0,
+            y: 0.0,
+            width: 10.0,
+            height: 10.0,
+        };
+        let ids = sessions(4);
+        let positions = [
+            (ids[0], point(0.0, 0.0)),
+            (ids[1], point(8.0, 0.0)),
+            (ids[2], point(18.0, 5.0)),
+            (ids[3], point(22.0, 5.0)),
+        ];
+        assert_eq!(
+            clusters(&map(vec![wall], vec![zone]), &positions),
+            [vec![ids[0]], vec![ids[1]], vec![ids[2]], vec![ids[3]]]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Where the files attached in rooms are kept.
+//!
+//! The database only has what is known about each attachment; its content is
+//! handed to a `Storage` backend under the attachment's id. The `Local`
+//! backend keeps files in a directory, which suits a single instance or
+//! instances sharing a volume.
+
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use envconfig::Envconfig;
+use rocket::fairing;
+use rocket::tokio::fs;
+use rocket::tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use rocket::{Build, Rocket};
+use uuid::Uuid;
+
+/// A stream of a file's content
+pub type Reader<'a> = Pin<Box<dyn AsyncRead + Send + 'a>>;
+
+#[derive(Envconfig)]
+struct Config {
+    /// Only `local` for now
+    #[envconfig(from = "YONDER_STORAGE", default = "local")]
+    storage: String,
+    /// Where the `local` backend keeps files
+    #[envconfig(from = "YONDER_STORAGE_PATH", default = "attachments")]
+    path: String,
+}
+
+#[rocket::async_trait]
+pub trait Storage: Send + Sync {
+    /// Store a file under `id`, reading no more than `limit` bytes of it, and
+    /// return how many bytes were stored
+    async fn put(&self, id: Uuid, content: Reader<'_>, limit: u64) -> io::Result<u64>;
+
+    async fn get(&self, id: Uuid) -> io::Result<Reader<'static>>;
+
+    /// Remove a file. Removing one that isn't there is not an error.
+    async fn delete(&self, id: Uuid) -> io::Result<()>;
+}
+
+/// Keeps files in a directory on the local filesystem
+pub struct Local {
+    root: PathBuf,
+}
+
+impl Local {
+    pub fn new(root: PathBuf) -> Self {
+        Local { root }
+    }
+
+    fn path(&self, id: Uuid) -> PathBuf {
+        self.root.join(id.to_string())
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for Local {
+    async fn put(&self, id: Uuid, content: Reader<'_>, limit: u64) -> io::Result<u64> {
+        // Write somewhere else first so a half-written file is never served
+        let partial = self.root.join(format!("{}.partial", id));
+        let mut file = fs::File::create(&partial).await?;
+        let written = rocket::tokio::io::copy(&mut content.take(limit), &mut file).await;
+        let written = match written {
+            Ok(written) => file.flush().await.map(|_| written),
+            Err(e) => Err(e),
+        };
+        match written {
+            Ok(written) => {
+                fs::rename(&partial, self.path(id)).await?;
+                Ok(written)
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&partial).await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn get(&self, id: Uuid) -> io::Result<Reader<'static>> {
+        let file = fs::File::open(self.path(id)).await?;
+        Ok(Box::pin(file))
+    }
+
+    async fn delete(&self, id: Uuid) -> io::Result<()> {
+        match fs::remove_file(self.path(id)).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Set up the backend named by `YONDER_STORAGE`
+pub async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match Config::init_from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to read the storage configuration: {}", e);
+            return Err(rocket);
+        }
+    };
+    let storage: Arc<dyn Storage> = match config.storage.as_str() {
+        "local" => {
+            let root = PathBuf::from(config.path);
+            if let Err(e) = fs::create_dir_all(&root).await {
+                error!("Failed to create {}: {}", root.display(), e);
+                return Err(rocket);
+            }
+            Arc::new(Local::new(root))
+        }
+        other => {
+            error!("Unknown storage backend: {}", other);
+            return Err(rocket);
+        }
+    };
+    Ok(rocket.manage(storage))
+}
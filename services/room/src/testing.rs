@@ -0,0 +1,259 @@
+//! Helpers for tests that drive the whole service through a local client.
+//!
+//! The tests use the database `DATABASE_URL` names, the same one the queries
+//! are checked against. Callers are looked up in a stand-in for the user
+//! service, which takes every user with a valid token to be who it says.
+
+use std::env;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Once;
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use rocket::config::LogLevel;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::serde::json::{json, Json, Value};
+use rocket::tokio::io::{AsyncBufReadExt, BufReader};
+use rocket::tokio::time::timeout;
+use rocket_db_pools::Database;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// How long to wait for an event before giving up on it
+const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The user service's `/users/me`, for whoever the token is for
+#[get("/users/me")]
+fn me(claims: auth::Claims) -> Json<Value> {
+    Json(json!({
+        "result": {"id": claims.sub, "is_superuser": false, "groups": []}
+    }))
+}
+
+/// Start the stand-in user service on a thread of its own, returning the port
+/// it listens on
+fn start_user_service() -> u16 {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|address| address.port())
+        .unwrap();
+    thread::spawn(move || {
+        let figment = rocket::Config::figment()
+            .merge(("address", "127.0.0.1"))
+            .merge(("port", port))
+            .merge(("log_level", LogLevel::Off));
+        let launched = rocket::execute(rocket::custom(figment).mount("/", routes![me]).launch());
+        launched.expect("the stand-in user service stopped");
+    });
+    for _ in 0..100 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return port;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("the stand-in user service didn't start");
+}
+
+fn setup() {
+    static SETUP: Once = Once::new();
+    SETUP.call_once(|| {
+        dotenv::dotenv().ok();
+        env::set_var("YONDER_JWT_SECRET", "test secret");
+        env::set_var("YONDER_FANOUT", "memory");
+        env::set_var("YONDER_STORAGE", "local");
+        env::set_var(
+            "YONDER_STORAGE_PATH",
+            env::temp_dir().join("yonder-test-attachments"),
+        );
+        let port = start_user_service();
+        env::set_var(
+            "USER_API_ENDPOINT",
+            format!("http://127.0.0.1:{}/users/me", port),
+        );
+    });
+}
+
+/// A client for the whole service, with a `Hub` of its own
+pub async fn client() -> Client {
+    setup();
+    let url = env::var("DATABASE_URL").expect("DATABASE_URL must be set to run the tests");
+    let figment = rocket::Config::figment()
+        .merge(("databases.roomdb.url", url))
+        .merge(("log_level", LogLevel::Off));
+    Client::tracked(crate::rocket().configure(figment))
+        .await
+        .unwrap()
+}
+
+/// The service's database, for setting up what the API can't
+pub fn database(client: &Client) -> &PgPool {
+    crate::db::RoomDb::fetch(client.rocket()).unwrap()
+}
+
+/// Someone with an account, as far as the stand-in user service is concerned,
+/// or a guest
+pub struct User {
+    pub id: Uuid,
+    token: String,
+}
+
+impl User {
+    pub fn new() -> Self {
+        let id = Uuid::new_v4();
+        let token = auth::encode_token(&json!({
+            "sub": id,
+            "email": format!("{}@example.com", id),
+            "exp": Utc::now().timestamp() + 60 * 60,
+        }))
+        .unwrap();
+        User { id, token }
+    }
+
+    /// A visitor without an account, with a token for one room like those the
+    /// web client gives out
+    pub fn guest(room_id: Uuid) -> Self {
+        let id = Uuid::new_v4();
+        let token = auth::encode_token(&json!({
+            "sub": id,
+            "email": "",
+            "exp": Utc::now().timestamp() + 60 * 60,
+            "given_name": "Visitor",
+            "room_id": room_id,
+        }))
+        .unwrap();
+        User { id, token }
+    }
+
+    pub fn authorization(&self) -> Header<'static> {
+        Header::new(
+            auth::AUTHORIZATION,
+            format!("{}{}", auth::BEARER, self.token),
+        )
+    }
+}
+
+/// Send `body` as `user`, returning the response's status and JSON
+pub async fn post(client: &Client, user: &User, uri: String, body: Value) -> (Status, Value) {
+    let response = client
+        .post(uri)
+        .header(ContentType::JSON)
+        .header(user.authorization())
+        .body(body.to_string())
+        .dispatch()
+        .await;
+    (response.status(), response.into_json().await.unwrap())
+}
+
+/// Replace what's at `uri` with `body` as `user`, returning the response's
+/// status and JSON
+pub async fn put(client: &Client, user: &User, uri: String, body: Value) -> (Status, Value) {
+    let response = client
+        .put(uri)
+        .header(ContentType::JSON)
+        .header(user.authorization())
+        .body(body.to_string())
+        .dispatch()
+        .await;
+    (response.status(), response.into_json().await.unwrap())
+}
+
+/// Fetch `uri` as `user`, returning the response's status and JSON
+pub async fn get(client: &Client, user: &User, uri: String) -> (Status, Value) {
+    let response = client
+        .get(uri)
+        .header(user.authorization())
+        .dispatch()
+        .await;
+    (response.status(), response.into_json().await.unwrap())
+}
+
+/// Create a `public` or `private` room owned by `owner`, returning its id
+pub async fn create_room(client: &Client, owner: &User, visibility: &str) -> Uuid {
+    let room = json!({"name": "Test room", "visibility": visibility});
+    let (status, room) = post(client, owner, String::from("/rooms"), room).await;
+    assert_eq!(status, Status::Created);
+    room["result"]["id"].as_str().unwrap().parse().unwrap()
+}
+
+/// Give `user` a Role in a room owned by `owner`
+pub async fn add_member(client: &Client, owner: &User, room_id: Uuid, user: &User, role: &str) {
+    let response = client
+        .put(format!("/rooms/{}/members/{}", room_id, user.id))
+        .header(ContentType::JSON)
+        .header(owner.authorization())
+        .body(json!({ "role": role }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+}
+
+/// The users with a session connected to a room, in the order they joined
+pub async fn participants(client: &Client, user: &User, room_id: Uuid) -> Vec<Value> {
+    let (status, participants) =
+        get(client, user, format!("/rooms/{}/participants", room_id)).await;
+    assert_eq!(status, Status::Ok);
+    participants["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|participant| participant["user_id"].clone())
+        .collect()
+}
+
+/// A session's event stream. Dropping it disconnects the session.
+pub struct Events<'c> {
+    reader: BufReader<LocalResponse<'c>>,
+}
+
+impl<'c> Events<'c> {
+    /// Connect to a room's event stream as `user`
+    pub async fn join(client: &'c Client, user: &User, room_id: Uuid) -> Events<'c> {
+        let response = client
+            .get(format!("/rooms/{}/events", room_id))
+            .header(user.authorization())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        Events {
+            reader: BufReader::new(response),
+        }
+    }
+
+    /// Connect to a room's event stream as `user`, returning the stream and
+    /// the session's id from the presence sent first
+    pub async fn join_session(
+        client: &'c Client,
+        user: &User,
+        room_id: Uuid,
+    ) -> (Events<'c>, Uuid) {
+        let mut events = Events::join(client, user, room_id).await;
+        let presence = events.next().await;
+        assert_eq!(presence["type"], "presence");
+        let session_id = presence["session_id"].as_str().unwrap().parse().unwrap();
+        (events, session_id)
+    }
+
+    /// The next event, failing the test if none comes soon
+    pub async fn next(&mut self) -> Value {
+        timeout(EVENT_TIMEOUT, self.read())
+            .await
+            .expect("no event came in time")
+    }
+
+    async fn read(&mut self) -> Value {
+        let mut data = String::new();
+        loop {
+            let mut line = String::new();
+            let read = self.reader.read_line(&mut line).await.unwrap();
+            assert!(read > 0, "the event stream ended");
+            let line = line.trim_end_matches(['\r', '\n']);
+            if let Some(value) = line.strip_prefix("data:") {
+                data.push_str(value.trim_start());
+            } else if line.is_empty() && !data.is_empty() {
+                return rocket::serde::json::from_str(&data).unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+// Callers are identified by their token, but rooms refer to users by their
+// id in the user service, so look the caller up there on each request.
+// Guests have no account there; their token says all there is to know.
+
+use envconfig::Envconfig;
+use reqwest::header::{ACCEPT, AUTHORIZATION};
+use rocket::http::Status;
+use rocket::request;
+use rocket::request::Outcome;
+use rocket::serde::json::json;
+use rocket::serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Envconfig)]
+struct Config {
+    #[envconfig(from = "USER_API_ENDPOINT")]
+    user_api_endpoint: String,
+    /// Only needed to resolve mentions and invitees
+    #[envconfig(from = "USER_MENTIONS_ENDPOINT")]
+    user_mentions_endpoint: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum CallerError {
+    Unauthenticated,
+    UnknownUser,
+    Unavailable,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+struct User {
+    id: Uuid,
+    is_superuser: bool,
+    groups: Option<Vec<Uuid>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+struct UserResponse {
+    result: User,
+}
+
+/// A user or group mentioned in a message, and the users it reaches
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Mention {
+    pub id: Uuid,
+    pub user_ids: Vec<Uuid>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+struct MentionsResponse {
+    result: Vec<Mention>,
+}
+
+/// A visitor without an account, let into a single room
+#[derive(Debug)]
+pub struct Guest {
+    pub room_id: Uuid,
+    pub name: String,
+}
+
+/// The authenticated user making a request
+#[derive(Debug)]
+pub struct Caller {
+    pub id: Uuid,
+    pub is_superuser: bool,
+    pub groups: Vec<Uuid>,
+    /// Set when the caller is a guest rather than a user
+    pub guest: Option<Guest>,
+}
+
+impl Caller {
+    /// A guest's token carries a made-up id, the room it is for and the name
+    /// the guest gave
+    fn guest(claims: auth::Claims) -> Result<Self, CallerError> {
+        let id = Uuid::parse_str(&claims.sub).map_err(|_| CallerError::Unauthenticated)?;
+        let room_id = claims
+            .room_id
+            .as_deref()
+            .and_then(|room_id| Uuid::parse_str(room_id).ok())
+            .ok_or(CallerError::Unauthenticated)?;
+        let name = claims
+            .given_name
+            .filter(|name| !name.trim().is_empty())
+            .ok_or(CallerError::Unauthenticated)?;
+        Ok(Caller {
+            id,
+            is_superuser: false,
+            groups: Vec::new(),
+            guest: Some(Guest { room_id, name }),
+        })
+    }
+}
+
+async fn fetch_me(authorization: &str) -> Result<User, CallerError> {
+    let config = Config::init_from_env().unwrap();
+    let response = reqwest::Client::new()
+        .get(config.user_api_endpoint)
+        .header(AUTHORIZATION, authorization)
+        .header(ACCEPT, "application/json")
+        .send()
+        .await
+        .map_err(|_| CallerError::Unavailable)?;
+    match response.status() {
+        reqwest::StatusCode::OK => response
+            .json::<UserResponse>()
+            .await
+            .map(|parsed| parsed.result)
+            .map_err(|_| CallerError::Unavailable),
+        reqwest::StatusCode::NOT_FOUND => Err(CallerError::UnknownUser),
+        _ => Err(CallerError::Unavailable),
+    }
+}
+
+/// Resolve the ids of mentioned users and groups to the users they reach.
+/// Ids the user service doesn't know are left out. The user service only
+/// tells services who is in a group, so this asks as the room service rather
+/// than as the caller.
+pub async fn resolve_mentions(ids: &[Uuid]) -> Result<Vec<Mention>, CallerError> {
+    let config = Config::init_from_env().unwrap();
+    let Some(endpoint) = config.user_mentions_endpoint else {
+        error!("USER_MENTIONS_ENDPOINT is not set, so mentions can't be resolved");
+        return Err(CallerError::Unavailable);
+    };
+    let authorization = auth::service_token("room").map_err(|_| CallerError::Unavailable)?;
+    let response = reqwest::Client::new()
+        .post(endpoint)
+        .header(AUTHORIZATION, authorization)
+        .header(ACCEPT, "application/json")
+        .json(&json!({ "ids": ids }))
+        .send()
+        .await
+        .map_err(|_| CallerError::Unavailable)?;
+    match response.status() {
+        reqwest::StatusCode::OK => response
+            .json::<MentionsResponse>()
+            .await
+            .map(|parsed| parsed.result)
+            .map_err(|_| CallerError::Unavailable),
+        _ => Err(CallerError::Unavailable),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> request::FromRequest<'r> for Caller {
+    type Error = CallerError;
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> Outcome<Self, Self::Error> {
+        // Not the Claims guard, which turns guests away
+        let Some(authorization) = request.headers().get_one(auth::AUTHORIZATION) else {
+            return Outcome::Error((Status::Forbidden, CallerError::Unauthenticated));
+        };
+        let claims = match auth::Claims::from_authorization(authorization) {
+            Ok(claims) => claims,
+            Err(_) => return Outcome::Error((Status::Forbidden, CallerError::Unauthenticated)),
+        };
+        if claims.is_guest() {
+            return match Caller::guest(claims) {
+                Ok(caller) => Outcome::Success(caller),
+                Err(e) => Outcome::Error((Status::Forbidden, e)),
+            };
+        }
+        match fetch_me(authorization).await {
+            Ok(user) => Outcome::Success(Caller {
+                id: user.id,
+                is_superuser: user.is_superuser,
+                groups: user.groups.unwrap_or_default(),
+                guest: None,
+            }),
+            Err(CallerError::UnknownUser) => {
+                Outcome::Error((Status::Forbidden, CallerError::UnknownUser))
+            }
+            Err(e) => Outcome::Error((Status::ServiceUnavailable, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(sub: &str, room_id: &str, given_name: Option<&str>) -> auth::Claims {
+        auth::Claims {
+            sub: sub.to_string(),
+            email: String::new(),
+            exp: 0,
+            given_name: given_name.map(str::to_string),
+            family_name: None,
+            room_id: Some(room_id.to_string()),
+        }
+    }
+
+    #[test]
+    fn guests_are_confined_to_their_room() {
+        let (id, room_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let claims = claims(&id.to_string(), &room_id.to_string(), Some("Visitor"));
+        let caller = Caller::guest(claims).unwrap();
+        assert_eq!(caller.id, id);
+        assert!(!caller.is_superuser);
+        assert!(caller.groups.is_empty());
+        let guest = caller.guest.unwrap();
+        assert_eq!(guest.room_id, room_id);
+        assert_eq!(guest.name, "Visitor");
+    }
+
+    #[test]
+    fn guests_need_an_id_a_room_and_a_name() {
+        let (id, room_id) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+        let cases = [
+            (
+                "an id that isn't one",
+                claims("guest", &room_id, Some("Visitor")),
+            ),
+            (
+                "a room that isn't one",
+                claims(&id, "lobby", Some("Visitor")),
+            ),
+            ("no name", claims(&id, &room_id, None)),
+            ("a blank name", claims(&id, &room_id, Some("  "))),
+        ];
+        for (name, claims) in cases {
+            assert!(Caller::guest(claims).is_err(), "{}", name);
+        }
+    }
+}
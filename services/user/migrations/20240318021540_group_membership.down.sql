@@ -0,0 +1,30 @@
+BEGIN;
+
+
+ALTER TABLE IF EXISTS public."UserGroup"
+    ADD COLUMN IF NOT EXISTS user_id uuid;
+
+-- A Group can only point at a single User again, so keep its earliest member
+UPDATE public."UserGroup" g
+    SET user_id = (
+        SELECT m.user_id FROM public."GroupMembership" m
+        WHERE m.group_id = g.id
+        ORDER BY m.created_at
+        LIMIT 1
+    );
+
+DELETE FROM public."UserGroup" WHERE user_id IS NULL;
+
+ALTER TABLE IF EXISTS public."UserGroup"
+    ALTER COLUMN user_id SET NOT NULL;
+
+ALTER TABLE IF EXISTS public."UserGroup"
+    ADD CONSTRAINT "UserGroup_User_fkey" FOREIGN KEY (user_id)
+    REFERENCES public."User" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE NO ACTION
+    NOT VALID;
+
+DROP TABLE IF EXISTS "GroupMembership";
+
+END;
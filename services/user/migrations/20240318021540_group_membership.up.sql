@@ -0,0 +1,40 @@
+BEGIN;
+
+
+CREATE TABLE IF NOT EXISTS public."GroupMembership"
+(
+    group_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    created_at timestamp without time zone NOT NULL DEFAULT now(),
+    CONSTRAINT "GroupMembership_pkey" PRIMARY KEY (group_id, user_id)
+);
+
+COMMENT ON TABLE public."GroupMembership"
+    IS 'Associates Users with the Groups they are in.';
+
+ALTER TABLE IF EXISTS public."GroupMembership"
+    ADD CONSTRAINT "GroupMembership_UserGroup_fkey" FOREIGN KEY (group_id)
+    REFERENCES public."UserGroup" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+ALTER TABLE IF EXISTS public."GroupMembership"
+    ADD CONSTRAINT "GroupMembership_User_fkey" FOREIGN KEY (user_id)
+    REFERENCES public."User" (id) MATCH SIMPLE
+    ON UPDATE NO ACTION
+    ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS "GroupMembership_user_id_idx"
+    ON public."GroupMembership" (user_id);
+
+INSERT INTO public."GroupMembership" (group_id, user_id, created_at)
+    SELECT id, user_id, created_at FROM public."UserGroup"
+    ON CONFLICT DO NOTHING;
+
+ALTER TABLE IF EXISTS public."UserGroup"
+    DROP CONSTRAINT IF EXISTS "UserGroup_User_fkey";
+
+ALTER TABLE IF EXISTS public."UserGroup"
+    DROP COLUMN IF EXISTS user_id;
+
+END;
@@ -1,16 +1,20 @@
-use super::auth;
 use super::db;
 use rocket::http::ContentType;
 use rocket::http::Status;
 use rocket::response;
+use rocket::response::Debug;
 use rocket::response::{Responder, Response};
 use rocket::serde::json::json;
+use rocket::serde::json::Json;
 use rocket::serde::json::Value;
+use rocket::serde::uuid::Uuid;
 use rocket::Request;
 use rocket_db_pools::Connection;
+use std::cmp;
 
 const DEFAULT_PAGE: i32 = 1;
 const DEFAULT_PER_PAGE: i32 = 10;
+const MAX_MENTIONS: usize = 50;
 
 #[derive(Debug)]
 pub struct ApiResponse {
@@ -28,6 +32,41 @@ impl<'r> Responder<'r, 'static> for ApiResponse {
     }
 }
 
+impl ApiResponse {
+    fn error(status: Status, short: &str, long: &str) -> Self {
+        ApiResponse {
+            json: json!({"error": {"short": short, "long": long}}),
+            status,
+        }
+    }
+
+    fn not_found() -> Self {
+        Self::error(Status::NotFound, "NotFound", "resource not found")
+    }
+
+    fn forbidden() -> Self {
+        Self::error(Status::Forbidden, "Forbidden", "operation not permitted")
+    }
+}
+
+/// Clamp the requested page and page size to sane bounds given the total
+/// number of items, returning `(page, per_page, total_pages)`
+fn paginate(page: Option<i32>, per_page: Option<i32>, count: i64) -> (i32, i32, i32) {
+    let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(10, 100);
+    let total_pages = cmp::max(1, (count + i64::from(per_page) - 1) / i64::from(per_page));
+    let total_pages = i32::try_from(total_pages).unwrap_or(i32::MAX);
+    let page = page.unwrap_or(DEFAULT_PAGE).clamp(1, total_pages);
+    (page, per_page, total_pages)
+}
+
+/// Fetch the caller's `User`, but only if they are a superuser
+async fn superuser(conn: Connection<db::UserDb>, email: &str) -> Option<db::responses::User> {
+    match db::fetch_user_by_email(conn, email).await {
+        Ok(user) if user.is_superuser => Some(user),
+        _ => None,
+    }
+}
+
 #[get("/groups?<page>&<per_page>")]
 pub async fn groups_list(
     claims: auth::Claims,
@@ -38,45 +77,19 @@ pub async fn groups_list(
     per_page: Option<i32>,
 ) -> ApiResponse {
     let email: &str = claims.email.as_str();
-    match db::get_user_by_email(user_conn, email).await {
-        Ok(j) => {
-            let user: db::responses::User = serde_json::from_value(j).unwrap();
-            if user.is_superuser {
-                let mut resolved_page = page.unwrap_or(DEFAULT_PAGE);
-                let mut resolved_per_page = per_page.unwrap_or(DEFAULT_PER_PAGE);
-                let mut total_pages: i32 = 1;
-                match db::get_group_count(count_conn).await {
-                    Ok(j) => {
-                        let pagination: db::responses::Pagination =
-                            serde_json::from_value(j).unwrap();
-                        total_pages = pagination.total_pages;
-                    }
-                    Err(_) => {}
-                }
-                if resolved_page < 1 {
-                    resolved_page = 1;
-                } else if resolved_page > total_pages {
-                    resolved_page = total_pages
-                }
-                if resolved_per_page < 10 {
-                    resolved_per_page = 10
-                } else if resolved_per_page > 100 {
-                    resolved_per_page = 100;
-                }
-                match db::get_groups(group_conn, resolved_page, resolved_per_page, total_pages)
-                    .await
-                {
-                    Ok(groups) => {
-                        return ApiResponse {
-                            json: groups,
-                            status: Status::Ok,
-                        };
-                    }
-                    Err(_) => {}
-                }
-            };
-        }
-        Err(_) => {}
+    if let Ok(user) = db::fetch_user_by_email(user_conn, email).await {
+        if user.is_superuser {
+            let count = db::get_group_count(count_conn).await.unwrap_or(0);
+            let (resolved_page, resolved_per_page, total_pages) = paginate(page, per_page, count);
+            if let Ok(groups) =
+                db::get_groups(group_conn, resolved_page, resolved_per_page, total_pages).await
+            {
+                return ApiResponse {
+                    json: groups,
+                    status: Status::Ok,
+                };
+            }
+        };
     }
     ApiResponse {
         json: json!({"error": {"short": "NotFound", "long": "resource not found"}}),
@@ -84,50 +97,262 @@ pub async fn groups_list(
     }
 }
 
-#[post("/groups")]
-fn groups_add(claims: auth::Claims) -> String {
-    format!("User '{}' found!", claims.email)
-    // fetch user information
+#[post("/groups", format = "json", data = "<group>")]
+pub async fn groups_add(
+    claims: auth::Claims,
+    user_conn: Connection<db::UserDb>,
+    group_conn: Connection<db::UserDb>,
+    group: Json<db::requests::GroupRequest>,
+) -> ApiResponse {
+    if superuser(user_conn, claims.email.as_str()).await.is_none() {
+        return ApiResponse::forbidden();
+    }
+    let name = group.name.trim();
+    if name.is_empty() {
+        return ApiResponse::error(
+            Status::BadRequest,
+            "BadRequest",
+            "group name must not be empty",
+        );
+    }
+    match db::create_group(group_conn, name).await {
+        Ok(group) => ApiResponse {
+            json: group,
+            status: Status::Created,
+        },
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to create group",
+        ),
+    }
 }
 
-#[delete("/groups")]
-fn groups_remove(claims: auth::Claims) -> String {
-    format!("User '{}' found!", claims.email)
-    // fetch user information
+#[delete("/groups/<id>")]
+pub async fn groups_remove(
+    claims: auth::Claims,
+    user_conn: Connection<db::UserDb>,
+    group_conn: Connection<db::UserDb>,
+    id: Uuid,
+) -> ApiResponse {
+    if superuser(user_conn, claims.email.as_str()).await.is_none() {
+        return ApiResponse::forbidden();
+    }
+    match db::delete_group(group_conn, id).await {
+        Ok(0) => ApiResponse::not_found(),
+        Ok(_) => ApiResponse {
+            json: json!({"result": {"id": id}}),
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to delete group",
+        ),
+    }
 }
 
 #[get("/groups/<id>")]
-fn group_show(claims: auth::Claims, id: &str) -> String {
-    format!("User '{},{}' found!", claims.email, id.to_string())
-    // fetch user information
+pub async fn group_show(
+    claims: auth::Claims,
+    user_conn: Connection<db::UserDb>,
+    group_conn: Connection<db::UserDb>,
+    id: Uuid,
+) -> ApiResponse {
+    if superuser(user_conn, claims.email.as_str()).await.is_none() {
+        return ApiResponse::forbidden();
+    }
+    match db::get_group(group_conn, id).await {
+        Ok(Some(group)) => ApiResponse {
+            json: group,
+            status: Status::Ok,
+        },
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to fetch group",
+        ),
+    }
 }
 
-#[get("/groups/<id>/members")]
-fn group_members_list(claims: auth::Claims, id: &str) -> String {
-    format!("User '{}' found!", claims.email)
-    // fetch user information
+#[get("/groups/<id>/members?<page>&<per_page>")]
+pub async fn group_members_list(
+    claims: auth::Claims,
+    user_conn: Connection<db::UserDb>,
+    member_conn: Connection<db::UserDb>,
+    count_conn: Connection<db::UserDb>,
+    id: Uuid,
+    page: Option<i32>,
+    per_page: Option<i32>,
+) -> ApiResponse {
+    if superuser(user_conn, claims.email.as_str()).await.is_none() {
+        return ApiResponse::forbidden();
+    }
+    let count = match db::get_group_member_count(count_conn, id).await {
+        Ok(Some(count)) => count,
+        Ok(None) => return ApiResponse::not_found(),
+        Err(_) => {
+            return ApiResponse::error(
+                Status::InternalServerError,
+                "InternalServerError",
+                "failed to count group members",
+            )
+        }
+    };
+    let (resolved_page, resolved_per_page, total_pages) = paginate(page, per_page, count);
+    match db::get_group_members(
+        member_conn,
+        id,
+        resolved_page,
+        resolved_per_page,
+        total_pages,
+    )
+    .await
+    {
+        Ok(members) => ApiResponse {
+            json: members,
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to list group members",
+        ),
+    }
 }
 
-#[post("/groups/<id>/members")]
-fn group_member_add(claims: auth::Claims, id: &str) -> String {
-    format!("User '{}' found!", claims.email)
-    // fetch user information
+#[post("/groups/<id>/members", format = "json", data = "<membership>")]
+pub async fn group_member_add(
+    claims: auth::Claims,
+    user_conn: Connection<db::UserDb>,
+    group_conn: Connection<db::UserDb>,
+    member_conn: Connection<db::UserDb>,
+    id: Uuid,
+    membership: Json<db::requests::MembershipRequest>,
+) -> ApiResponse {
+    if superuser(user_conn, claims.email.as_str()).await.is_none() {
+        return ApiResponse::forbidden();
+    }
+    match db::group_exists(group_conn, id).await {
+        Ok(true) => {}
+        Ok(false) => return ApiResponse::not_found(),
+        Err(_) => {
+            return ApiResponse::error(
+                Status::InternalServerError,
+                "InternalServerError",
+                "failed to look up group",
+            )
+        }
+    }
+    match db::add_group_member(member_conn, id, membership.user_id).await {
+        Ok(added) => ApiResponse {
+            json: json!({"result": {"group_id": id, "user_id": membership.user_id}}),
+            status: if added > 0 {
+                Status::Created
+            } else {
+                Status::Ok
+            },
+        },
+        // A missing User, or a Group deleted since it was looked up
+        Err(Debug(sqlx::Error::Database(e))) if e.is_foreign_key_violation() => {
+            ApiResponse::not_found()
+        }
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to add group member",
+        ),
+    }
 }
 
-#[delete("/groups/<id>/members")]
-fn group_member_remove(claims: auth::Claims, id: &str) -> String {
-    format!("User '{}' found!", claims.email)
-    // fetch user information
+#[delete("/groups/<id>/members", format = "json", data = "<membership>")]
+pub async fn group_member_remove(
+    claims: auth::Claims,
+    user_conn: Connection<db::UserDb>,
+    member_conn: Connection<db::UserDb>,
+    id: Uuid,
+    membership: Json<db::requests::MembershipRequest>,
+) -> ApiResponse {
+    if superuser(user_conn, claims.email.as_str()).await.is_none() {
+        return ApiResponse::forbidden();
+    }
+    match db::remove_group_member(member_conn, id, membership.user_id).await {
+        Ok(0) => ApiResponse::not_found(),
+        Ok(_) => ApiResponse {
+            json: json!({"result": {"group_id": id, "user_id": membership.user_id}}),
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to remove group member",
+        ),
+    }
 }
 
-#[get("/users")]
-fn users_list(claims: auth::Claims) -> String {
-    format!("User '{}' found!", claims.email)
+#[get("/users?<page>&<per_page>")]
+pub async fn users_list(
+    claims: auth::Claims,
+    user_conn: Connection<db::UserDb>,
+    users_conn: Connection<db::UserDb>,
+    count_conn: Connection<db::UserDb>,
+    page: Option<i32>,
+    per_page: Option<i32>,
+) -> ApiResponse {
+    if superuser(user_conn, claims.email.as_str()).await.is_none() {
+        return ApiResponse::forbidden();
+    }
+    let count = match db::get_user_count(count_conn).await {
+        Ok(count) => count,
+        Err(_) => {
+            return ApiResponse::error(
+                Status::InternalServerError,
+                "InternalServerError",
+                "failed to count users",
+            )
+        }
+    };
+    let (resolved_page, resolved_per_page, total_pages) = paginate(page, per_page, count);
+    match db::get_users(users_conn, resolved_page, resolved_per_page, total_pages).await {
+        Ok(users) => ApiResponse {
+            json: users,
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to list users",
+        ),
+    }
 }
 
-#[post("/users")]
-fn users_add(claims: auth::Claims) -> String {
-    format!("User '{}' found!", claims.email)
+#[post("/users", format = "json", data = "<user>")]
+pub async fn users_add(
+    claims: auth::Claims,
+    user_conn: Connection<db::UserDb>,
+    create_conn: Connection<db::UserDb>,
+    user: Json<db::requests::UserRequest>,
+) -> ApiResponse {
+    if superuser(user_conn, claims.email.as_str()).await.is_none() {
+        return ApiResponse::forbidden();
+    }
+    match db::create_user(create_conn, &user).await {
+        Ok(Some(created)) => ApiResponse {
+            json: created,
+            status: Status::Created,
+        },
+        Ok(None) => ApiResponse::error(
+            Status::Conflict,
+            "Conflict",
+            "an account with that email already exists",
+        ),
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to create user",
+        ),
+    }
 }
 
 #[get("/users/me")]
@@ -148,12 +373,346 @@ pub async fn me_show(claims: auth::Claims, conn: Connection<db::UserDb>) -> ApiR
     }
 }
 
+/// Provision the caller's User and Account from their token's claims the first
+/// time they sign in. Safe to call on every sign-in.
+#[post("/users/me")]
+pub async fn me_provision(
+    claims: auth::Claims,
+    provision_conn: Connection<db::UserDb>,
+    user_conn: Connection<db::UserDb>,
+) -> ApiResponse {
+    let email: &str = claims.email.as_str();
+    let created = match db::provision_user(
+        provision_conn,
+        email,
+        claims.given_name.as_deref().unwrap_or(""),
+        claims.family_name.as_deref().unwrap_or(""),
+    )
+    .await
+    {
+        Ok(created) => created,
+        Err(_) => {
+            return ApiResponse::error(
+                Status::InternalServerError,
+                "InternalServerError",
+                "failed to provision user",
+            )
+        }
+    };
+    match db::get_user_by_email(user_conn, email).await {
+        Ok(user) => ApiResponse {
+            json: user,
+            status: if created { Status::Created } else { Status::Ok },
+        },
+        // The User was just provisioned, so failing to find it is our fault
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to fetch provisioned user",
+        ),
+    }
+}
+
+/// Resolve mentioned Users and Groups to the Users they reach, so other
+/// services can tell who a message mentions. This gives away who is in every
+/// Group, so only services may ask, not Users.
+#[post("/mentions", format = "json", data = "<mention>")]
+pub async fn mentions_resolve(
+    _service: auth::ServiceClaims,
+    mention_conn: Connection<db::UserDb>,
+    mention: Json<db::requests::MentionRequest>,
+) -> ApiResponse {
+    if mention.ids.len() > MAX_MENTIONS {
+        return ApiResponse::error(
+            Status::BadRequest,
+            "BadRequest",
+            "too many mentions to resolve at once",
+        );
+    }
+    match db::resolve_mentions(mention_conn, &mention.ids).await {
+        Ok(mentions) => ApiResponse {
+            json: json!({ "result": mentions }),
+            status: Status::Ok,
+        },
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to resolve mentions",
+        ),
+    }
+}
+
 #[get("/users/<id>")]
-fn user_show(claims: auth::Claims, id: &str) -> String {
-    format!("User '{}' found!", claims.email)
+pub async fn user_show(
+    claims: auth::Claims,
+    caller_conn: Connection<db::UserDb>,
+    user_conn: Connection<db::UserDb>,
+    id: Uuid,
+) -> ApiResponse {
+    match db::fetch_user_by_email(caller_conn, claims.email.as_str()).await {
+        Ok(caller) if caller.is_superuser || caller.id == id => {}
+        _ => return ApiResponse::forbidden(),
+    }
+    match db::get_user(user_conn, id).await {
+        Ok(Some(user)) => ApiResponse {
+            json: user,
+            status: Status::Ok,
+        },
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to fetch user",
+        ),
+    }
+}
+
+#[put("/users/<id>", format = "json", data = "<user>")]
+pub async fn user_update(
+    claims: auth::Claims,
+    caller_conn: Connection<db::UserDb>,
+    user_conn: Connection<db::UserDb>,
+    id: Uuid,
+    user: Json<db::requests::UserUpdateRequest>,
+) -> ApiResponse {
+    match db::fetch_user_by_email(caller_conn, claims.email.as_str()).await {
+        // Only superusers may grant or revoke superuser status
+        Ok(caller) if caller.is_superuser => {}
+        Ok(caller) if caller.id == id && user.is_superuser.is_none() => {}
+        _ => return ApiResponse::forbidden(),
+    }
+    match db::update_user(user_conn, id, &user).await {
+        Ok(Some(updated)) => ApiResponse {
+            json: updated,
+            status: Status::Ok,
+        },
+        Ok(None) => ApiResponse::not_found(),
+        Err(_) => ApiResponse::error(
+            Status::InternalServerError,
+            "InternalServerError",
+            "failed to update user",
+        ),
+    }
 }
 
-#[put("/users/<id>")]
-fn user_update(claims: auth::Claims, id: &str) -> String {
-    format!("User '{}' found!", claims.email)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+    use rocket_db_pools::Database;
+    use sqlx::PgPool;
+    use std::env;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// A client for the whole service, on the database `DATABASE_URL` names
+    async fn client() -> Client {
+        dotenv::dotenv().ok();
+        env::set_var("YONDER_JWT_SECRET", "test secret");
+        let url = env::var("DATABASE_URL").expect("DATABASE_URL must be set to run the tests");
+        let figment = rocket::Config::figment().merge(("databases.userdb.url", url));
+        Client::tracked(crate::rocket().configure(figment))
+            .await
+            .unwrap()
+    }
+
+    /// Add a User with an Account, returning a header to act as them with
+    async fn add_user(pool: &PgPool, is_superuser: bool) -> (Uuid, Header<'static>) {
+        let email = format!("{}@example.com", Uuid::new_v4());
+        let user_id: Uuid = sqlx::query_scalar(
+            r#"INSERT INTO public."User" (given_name, family_name, country, is_superuser)
+            VALUES ('Ada', 'Lovelace', 'GB', $1) RETURNING id"#,
+        )
+        .bind(is_superuser)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        sqlx::query(r#"INSERT INTO public."Account" (email, user_id) VALUES ($1, $2)"#)
+            .bind(&email)
+            .bind(user_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        let token = auth::encode_token(&json!({
+            "sub": user_id,
+            "email": email,
+            "exp": SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 60,
+        }))
+        .unwrap();
+        let authorization = Header::new(auth::AUTHORIZATION, format!("{}{}", auth::BEARER, token));
+        (user_id, authorization)
+    }
+
+    #[rocket::async_test]
+    async fn users_are_only_for_themselves_and_superusers() {
+        let client = client().await;
+        let pool = &**db::UserDb::fetch(client.rocket()).unwrap();
+        let (alice, as_alice) = add_user(pool, false).await;
+        let (bob, _) = add_user(pool, false).await;
+        let (_, as_root) = add_user(pool, true).await;
+        let show = |id: Uuid, authorization: &Header<'static>| {
+            client
+                .get(format!("/users/{}", id))
+                .header(authorization.clone())
+                .dispatch()
+        };
+        let update = |id: Uuid, authorization: &Header<'static>, body: Value| {
+            client
+                .put(format!("/users/{}", id))
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(body.to_string())
+                .dispatch()
+        };
+
+        assert_eq!(show(alice, &as_alice).await.status(), Status::Ok);
+        assert_eq!(show(bob, &as_alice).await.status(), Status::Forbidden);
+        let response = client
+            .get("/users")
+            .header(as_alice.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let renamed = json!({ "given_name": "Mallory" });
+        let response = update(bob, &as_alice, renamed.clone()).await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let promoted = json!({ "is_superuser": true });
+        let response = update(alice, &as_alice, promoted.clone()).await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = update(alice, &as_alice, renamed.clone()).await;
+        assert_eq!(response.status(), Status::Ok);
+        let user: Value = response.into_json().await.unwrap();
+        assert_eq!(user["result"]["given_name"], "Mallory");
+        assert_eq!(user["result"]["is_superuser"], false);
+
+        assert_eq!(show(bob, &as_root).await.status(), Status::Ok);
+        let response = update(bob, &as_root, promoted).await;
+        assert_eq!(response.status(), Status::Ok);
+        let user: Value = response.into_json().await.unwrap();
+        assert_eq!(user["result"]["is_superuser"], true);
+
+        let nobody = Uuid::new_v4();
+        assert_eq!(show(nobody, &as_root).await.status(), Status::NotFound);
+        let response = update(nobody, &as_root, renamed).await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn groups_are_only_shown_to_superusers() {
+        let client = client().await;
+        let pool = &**db::UserDb::fetch(client.rocket()).unwrap();
+        let (_, as_user) = add_user(pool, false).await;
+        let (_, as_root) = add_user(pool, true).await;
+        let group_id: Uuid = sqlx::query_scalar(
+            r#"INSERT INTO public."UserGroup" (name) VALUES ('staff') RETURNING id"#,
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        let show = |id: Uuid, authorization: &Header<'static>| {
+            client
+                .get(format!("/groups/{}", id))
+                .header(authorization.clone())
+                .dispatch()
+        };
+
+        let response = show(group_id, &as_root).await;
+        assert_eq!(response.status(), Status::Ok);
+        let group: Value = response.into_json().await.unwrap();
+        assert_eq!(group["result"], json!({ "id": group_id, "name": "staff" }));
+        assert_eq!(show(group_id, &as_user).await.status(), Status::Forbidden);
+        let response = show(Uuid::new_v4(), &as_root).await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn group_members_are_only_added_if_both_exist() {
+        let client = client().await;
+        let pool = &**db::UserDb::fetch(client.rocket()).unwrap();
+        let (user_id, _) = add_user(pool, false).await;
+        let (_, as_root) = add_user(pool, true).await;
+        let group_id: Uuid = sqlx::query_scalar(
+            r#"INSERT INTO public."UserGroup" (name) VALUES ('staff') RETURNING id"#,
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        let add = |group_id: Uuid, user_id: Uuid| {
+            client
+                .post(format!("/groups/{}/members", group_id))
+                .header(ContentType::JSON)
+                .header(as_root.clone())
+                .body(json!({ "user_id": user_id }).to_string())
+                .dispatch()
+        };
+
+        assert_eq!(add(group_id, user_id).await.status(), Status::Created);
+        assert_eq!(add(group_id, user_id).await.status(), Status::Ok);
+        let nobody = Uuid::new_v4();
+        assert_eq!(add(group_id, nobody).await.status(), Status::NotFound);
+        assert_eq!(add(nobody, user_id).await.status(), Status::NotFound);
+
+        let response = client
+            .get(format!("/groups/{}/members", group_id))
+            .header(as_root.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let members: Value = response.into_json().await.unwrap();
+        assert_eq!(members["result"][0]["id"], json!(user_id));
+        let response = client
+            .get(format!("/groups/{}/members", nobody))
+            .header(as_root.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn mentions_resolve_is_only_for_services() {
+        let client = client().await;
+        let pool = &**db::UserDb::fetch(client.rocket()).unwrap();
+        let (user_id, as_user) = add_user(pool, false).await;
+        let group_id: Uuid = sqlx::query_scalar(
+            r#"INSERT INTO public."UserGroup" (name) VALUES ('staff') RETURNING id"#,
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        sqlx::query(r#"INSERT INTO public."GroupMembership" (group_id, user_id) VALUES ($1, $2)"#)
+            .bind(group_id)
+            .bind(user_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        let body = json!({ "ids": [group_id] }).to_string();
+
+        // A User, even one in the Group, can't list its members
+        let response = client
+            .post("/mentions")
+            .header(ContentType::JSON)
+            .header(as_user)
+            .body(&body)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client
+            .post("/mentions")
+            .header(ContentType::JSON)
+            .header(Header::new(
+                auth::AUTHORIZATION,
+                auth::service_token("room").unwrap(),
+            ))
+            .body(&body)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let resolved: Value = response.into_json().await.unwrap();
+        assert_eq!(
+            resolved["result"],
+            json!([{ "id": group_id, "user_ids": [user_id] }])
+        );
+    }
 }
@@ -13,7 +13,10 @@ use rocket_db_pools::Connection;
 use rocket_db_pools::Database;
 use serde::Deserialize;
 use serde::Serialize;
+use sqlx::Acquire;
+use sqlx::PgConnection;
 use sqlx::PgPool;
+use uuid::Uuid;
 
 pub type DBResult<T, E = rocket::response::Debug<sqlx::Error>> = std::result::Result<T, E>;
 
@@ -36,13 +39,24 @@ pub async fn run_migrations(rocket: Rocket<Build>) -> fairing::Result {
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(crate = "rocket::serde")]
-struct PaginatedGroupsQueryResult {
-    result: Vec<responses::Group>,
+struct PaginatedQueryResult<T> {
+    result: Vec<T>,
     prev_page: i32,
     next_page: i32,
     last_page: i32,
 }
 
+impl<T> PaginatedQueryResult<T> {
+    fn new(result: Vec<T>, page: i32, total_pages: i32) -> Self {
+        PaginatedQueryResult {
+            result,
+            prev_page: cmp::max(1, page - 1),
+            next_page: cmp::min(total_pages, page + 1),
+            last_page: total_pages,
+        }
+    }
+}
+
 pub async fn get_groups(
     mut conn: Connection<UserDb>,
     page: i32,
@@ -52,7 +66,7 @@ pub async fn get_groups(
     let query = sqlx::query_as!(
         responses::Group,
         r#"
-        SELECT id, name FROM public."UserGroup" LIMIT $1 OFFSET $2;
+        SELECT id, name FROM public."UserGroup" ORDER BY created_at, id LIMIT $1 OFFSET $2;
         "#,
         i64::from(per_page),
         i64::from((page - 1) * per_page)
@@ -60,23 +74,158 @@ pub async fn get_groups(
     .fetch_all(&mut **conn)
     .await?;
 
-    Ok(json!(PaginatedGroupsQueryResult {
-        result: query,
-        prev_page: cmp::max(1, page - 1),
-        next_page: cmp::min(total_pages, page),
-        last_page: total_pages,
-    }))
+    Ok(json!(PaginatedQueryResult::new(query, page, total_pages)))
+}
+
+pub async fn get_group_count(mut conn: Connection<UserDb>) -> DBResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM public."UserGroup";
+        "#,
+    )
+    .fetch_one(&mut **conn)
+    .await?;
+    Ok(count)
 }
 
-pub async fn get_group_count(mut conn: Connection<UserDb>) -> DBResult<Value> {
-    let groups_count = sqlx::query_as::<_, responses::Pagination>(
+pub async fn group_exists(mut conn: Connection<UserDb>, id: Uuid) -> DBResult<bool> {
+    let exists = sqlx::query_scalar!(
         r#"
-        SELECT COUNT(*) as total_pages FROM public."UserGroup";
+        SELECT EXISTS(SELECT 1 FROM public."UserGroup" WHERE id = $1) AS "exists!";
         "#,
+        id
     )
     .fetch_one(&mut **conn)
     .await?;
-    Ok(json!(groups_count))
+    Ok(exists)
+}
+
+pub async fn get_group_members(
+    mut conn: Connection<UserDb>,
+    group_id: Uuid,
+    page: i32,
+    per_page: i32,
+    total_pages: i32,
+) -> DBResult<Value> {
+    let query = sqlx::query_as!(
+        responses::Member,
+        r#"
+        SELECT u.id, u.given_name, u.family_name
+        FROM public."GroupMembership" m
+        JOIN public."User" u ON u.id = m.user_id
+        WHERE m.group_id = $1
+        ORDER BY m.created_at, u.id LIMIT $2 OFFSET $3;
+        "#,
+        group_id,
+        i64::from(per_page),
+        i64::from((page - 1) * per_page)
+    )
+    .fetch_all(&mut **conn)
+    .await?;
+
+    Ok(json!(PaginatedQueryResult::new(query, page, total_pages)))
+}
+
+/// Count the members of a Group, or `None` if the Group does not exist
+pub async fn get_group_member_count(
+    mut conn: Connection<UserDb>,
+    group_id: Uuid,
+) -> DBResult<Option<i64>> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT (SELECT COUNT(*) FROM public."GroupMembership" m WHERE m.group_id = g.id) AS "count!"
+        FROM public."UserGroup" g WHERE g.id = $1;
+        "#,
+        group_id
+    )
+    .fetch_optional(&mut **conn)
+    .await?;
+    Ok(count)
+}
+
+/// Add a User to a Group, returning the number of memberships created (0 if
+/// the User was already a member)
+pub async fn add_group_member(
+    mut conn: Connection<UserDb>,
+    group_id: Uuid,
+    user_id: Uuid,
+) -> DBResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO public."GroupMembership" (group_id, user_id) VALUES ($1, $2)
+        ON CONFLICT DO NOTHING;
+        "#,
+        group_id,
+        user_id
+    )
+    .execute(&mut **conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Remove a User from a Group, returning the number of memberships removed
+pub async fn remove_group_member(
+    mut conn: Connection<UserDb>,
+    group_id: Uuid,
+    user_id: Uuid,
+) -> DBResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM public."GroupMembership" WHERE group_id = $1 AND user_id = $2;
+        "#,
+        group_id,
+        user_id
+    )
+    .execute(&mut **conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+struct GroupQueryResult {
+    result: responses::Group,
+}
+
+pub async fn create_group(mut conn: Connection<UserDb>, name: &str) -> DBResult<Value> {
+    let query = sqlx::query_as!(
+        responses::Group,
+        r#"
+        INSERT INTO public."UserGroup" (name) VALUES ($1) RETURNING id, name;
+        "#,
+        name
+    )
+    .fetch_one(&mut **conn)
+    .await?;
+    Ok(json!(GroupQueryResult { result: query }))
+}
+
+/// Fetch a Group, or `None` if the Group does not exist
+pub async fn get_group(mut conn: Connection<UserDb>, id: Uuid) -> DBResult<Option<Value>> {
+    let group = sqlx::query_as!(
+        responses::Group,
+        r#"
+        SELECT id, name FROM public."UserGroup" WHERE id = $1;
+        "#,
+        id
+    )
+    .fetch_optional(&mut **conn)
+    .await?;
+    Ok(group.map(|group| json!(GroupQueryResult { result: group })))
+}
+
+/// Delete a group, returning the number of groups removed (0 or 1). Its
+/// memberships are removed along with it.
+pub async fn delete_group(mut conn: Connection<UserDb>, id: Uuid) -> DBResult<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM public."UserGroup" WHERE id = $1;
+        "#,
+        id
+    )
+    .execute(&mut **conn)
+    .await?;
+    Ok(result.rows_affected())
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -85,20 +234,256 @@ struct UserQueryResult {
     result: responses::User,
 }
 
-pub async fn get_user_by_email(mut conn: Connection<UserDb>, email: &str) -> DBResult<Value> {
+pub async fn fetch_user_by_email(
+    mut conn: Connection<UserDb>,
+    email: &str,
+) -> DBResult<responses::User> {
     let query = sqlx::query_as!(
         responses::User,
         r#"
         SELECT user_id AS id, u.given_name AS given_name, u.family_name AS family_name, u.is_superuser AS is_superuser, u.groups AS groups
         FROM public."Account" a
         LEFT JOIN (
-            SELECT u.id as user_id, u.given_name, u.family_name, u.is_superuser, array_remove(array_agg(g.id), NULL) AS groups
+            SELECT u.id as user_id, u.given_name, u.family_name, u.is_superuser, array_remove(array_agg(g.group_id), NULL) AS groups
             FROM public."User" u
-            LEFT OUTER JOIN public."UserGroup" g ON u.id = g.user_id
+            LEFT OUTER JOIN public."GroupMembership" g ON u.id = g.user_id
             GROUP BY u.id
         ) u USING (user_id) WHERE a.email = $1
         "#,
         email
     ).fetch_one(&mut **conn).await?;
-    Ok(json!(UserQueryResult { result: query }))
+    Ok(query)
+}
+
+pub async fn get_user_by_email(conn: Connection<UserDb>, email: &str) -> DBResult<Value> {
+    let user = fetch_user_by_email(conn, email).await?;
+    Ok(json!(UserQueryResult { result: user }))
+}
+
+async fn fetch_user_by_id(
+    conn: &mut PgConnection,
+    id: Uuid,
+) -> sqlx::Result<Option<responses::User>> {
+    sqlx::query_as!(
+        responses::User,
+        r#"
+        SELECT u.id, u.given_name, u.family_name, u.is_superuser, array_remove(array_agg(m.group_id), NULL) AS groups
+        FROM public."User" u
+        LEFT OUTER JOIN public."GroupMembership" m ON u.id = m.user_id
+        WHERE u.id = $1
+        GROUP BY u.id
+        "#,
+        id
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+/// Fetch a User, or `None` if the User does not exist
+pub async fn get_user(mut conn: Connection<UserDb>, id: Uuid) -> DBResult<Option<Value>> {
+    let user = fetch_user_by_id(&mut conn, id).await?;
+    Ok(user.map(|user| json!(UserQueryResult { result: user })))
+}
+
+pub async fn get_users(
+    mut conn: Connection<UserDb>,
+    page: i32,
+    per_page: i32,
+    total_pages: i32,
+) -> DBResult<Value> {
+    let query = sqlx::query_as!(
+        responses::User,
+        r#"
+        SELECT u.id, u.given_name, u.family_name, u.is_superuser, array_remove(array_agg(m.group_id), NULL) AS groups
+        FROM public."User" u
+        LEFT OUTER JOIN public."GroupMembership" m ON u.id = m.user_id
+        GROUP BY u.id
+        ORDER BY u.created_at, u.id LIMIT $1 OFFSET $2;
+        "#,
+        i64::from(per_page),
+        i64::from((page - 1) * per_page)
+    )
+    .fetch_all(&mut **conn)
+    .await?;
+
+    Ok(json!(PaginatedQueryResult::new(query, page, total_pages)))
+}
+
+/// Resolve the ids of mentioned Users and Groups to the Users they reach. A
+/// User reaches only themselves and a Group reaches its members. Ids that are
+/// neither are left out.
+pub async fn resolve_mentions(
+    mut conn: Connection<UserDb>,
+    ids: &[Uuid],
+) -> DBResult<Vec<responses::Mention>> {
+    let mentions = sqlx::query_as!(
+        responses::Mention,
+        r#"
+        SELECT u.id AS "id!", ARRAY[u.id] AS "user_ids!"
+        FROM public."User" u WHERE u.id = ANY($1)
+        UNION ALL
+        SELECT g.id, array_remove(array_agg(m.user_id), NULL)
+        FROM public."UserGroup" g
+        LEFT OUTER JOIN public."GroupMembership" m ON g.id = m.group_id
+        WHERE g.id = ANY($1)
+        GROUP BY g.id;
+        "#,
+        ids
+    )
+    .fetch_all(&mut **conn)
+    .await?;
+    Ok(mentions)
+}
+
+pub async fn get_user_count(mut conn: Connection<UserDb>) -> DBResult<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM public."User";
+        "#,
+    )
+    .fetch_one(&mut **conn)
+    .await?;
+    Ok(count)
+}
+
+/// Insert a User, its UserProfile and the Account it signs in with. Returns
+/// `None` if an Account already exists for `email`, in which case the caller
+/// should roll back so the new User isn't left orphaned.
+async fn insert_user(
+    conn: &mut PgConnection,
+    email: &str,
+    given_name: &str,
+    family_name: &str,
+    country: &str,
+    is_superuser: bool,
+) -> sqlx::Result<Option<Uuid>> {
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO public."User" (given_name, family_name, country, is_superuser)
+        VALUES ($1, $2, $3, $4) RETURNING id;
+        "#,
+        given_name,
+        family_name,
+        country,
+        is_superuser
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO public."UserProfile" (user_id) VALUES ($1);
+        "#,
+        id
+    )
+    .execute(&mut *conn)
+    .await?;
+    let account = sqlx::query!(
+        r#"
+        INSERT INTO public."Account" (email, user_id) VALUES ($1, $2)
+        ON CONFLICT (email) DO NOTHING;
+        "#,
+        email,
+        id
+    )
+    .execute(&mut *conn)
+    .await?;
+    if account.rows_affected() == 0 {
+        return Ok(None);
+    }
+    Ok(Some(id))
+}
+
+/// Create a User along with its UserProfile and the Account it signs in with.
+/// Returns `None` if an Account already exists for the email address.
+pub async fn create_user(
+    mut conn: Connection<UserDb>,
+    user: &requests::UserRequest,
+) -> DBResult<Option<Value>> {
+    let mut tx = (&mut **conn).begin().await?;
+    let id = insert_user(
+        &mut tx,
+        user.email.as_str(),
+        user.given_name.as_str(),
+        user.family_name.as_str(),
+        user.country.as_str(),
+        user.is_superuser.unwrap_or(false),
+    )
+    .await?;
+    let Some(id) = id else {
+        tx.rollback().await?;
+        return Ok(None);
+    };
+    // Just inserted in this transaction, so it can't be missing
+    let created = fetch_user_by_id(&mut tx, id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+    tx.commit().await?;
+    Ok(Some(json!(UserQueryResult { result: created })))
+}
+
+/// Make sure a User and Account exist for an email address, creating them if
+/// necessary. Returns whether anything was created; calling this again for the
+/// same email address is a no-op.
+pub async fn provision_user(
+    mut conn: Connection<UserDb>,
+    email: &str,
+    given_name: &str,
+    family_name: &str,
+) -> DBResult<bool> {
+    let mut tx = (&mut **conn).begin().await?;
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM public."Account" WHERE email = $1) AS "exists!";
+        "#,
+        email
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if exists {
+        tx.rollback().await?;
+        return Ok(false);
+    }
+    // Another sign-in may have raced us to create the Account
+    match insert_user(&mut tx, email, given_name, family_name, "", false).await? {
+        Some(_) => {
+            tx.commit().await?;
+            Ok(true)
+        }
+        None => {
+            tx.rollback().await?;
+            Ok(false)
+        }
+    }
+}
+
+/// Apply a partial update to a User, leaving any fields that weren't provided
+/// untouched. Returns `None` if the User does not exist.
+pub async fn update_user(
+    mut conn: Connection<UserDb>,
+    id: Uuid,
+    user: &requests::UserUpdateRequest,
+) -> DBResult<Option<Value>> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE public."User" SET
+            given_name = COALESCE($2, given_name),
+            family_name = COALESCE($3, family_name),
+            country = COALESCE($4, country),
+            is_superuser = COALESCE($5, is_superuser),
+            updated_at = now()
+        WHERE id = $1;
+        "#,
+        id,
+        user.given_name,
+        user.family_name,
+        user.country,
+        user.is_superuser
+    )
+    .execute(&mut **conn)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Ok(None);
+    }
+    let user = fetch_user_by_id(&mut conn, id).await?;
+    Ok(user.map(|user| json!(UserQueryResult { result: user })))
 }
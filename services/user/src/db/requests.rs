@@ -1,4 +1,5 @@
 use rocket::serde::Deserialize;
+use uuid::Uuid;
 
 #[derive(Deserialize, Debug)]
 #[serde(crate = "rocket::serde")]
@@ -6,3 +7,41 @@ pub struct TaskRequest {
     pub name: String,
     pub description: String,
 }
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct GroupRequest {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct MembershipRequest {
+    pub user_id: Uuid,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct UserRequest {
+    pub email: String,
+    pub given_name: String,
+    pub family_name: String,
+    pub country: String,
+    pub is_superuser: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct UserUpdateRequest {
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub country: Option<String>,
+    pub is_superuser: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct MentionRequest {
+    /// User and Group ids, in any mix
+    pub ids: Vec<Uuid>,
+}
@@ -6,14 +6,16 @@ use uuid::Uuid;
 #[derive(Serialize, Deserialize, FromRow, Debug)]
 #[serde(crate = "rocket::serde")]
 pub struct Group {
-    pub id: String,
+    pub id: Uuid,
     pub name: String,
 }
 
 #[derive(Serialize, Deserialize, FromRow, Debug)]
 #[serde(crate = "rocket::serde")]
-pub struct Pagination {
-    pub total_pages: i32,
+pub struct Member {
+    pub id: Uuid,
+    pub given_name: String,
+    pub family_name: String,
 }
 
 #[derive(Serialize, Deserialize, FromRow, Debug)]
@@ -25,3 +27,11 @@ pub struct User {
     pub is_superuser: bool,
     pub groups: Option<Vec<Uuid>>,
 }
+
+/// A User or Group named in a mention, and the Users it reaches
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Mention {
+    pub id: Uuid,
+    pub user_ids: Vec<Uuid>,
+}
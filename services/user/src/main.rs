@@ -2,31 +2,12 @@
 extern crate rocket;
 
 mod api;
-mod auth;
 mod db;
 
 use dotenv::dotenv;
 use rocket::fairing::AdHoc;
-use rocket::http::Status;
-use rocket::request;
-use rocket::request::Outcome;
 use rocket_db_pools::Database;
 
-#[rocket::async_trait]
-impl<'r> request::FromRequest<'r> for auth::Claims {
-    type Error = auth::AuthenticationError;
-
-    async fn from_request(request: &'r rocket::Request<'_>) -> Outcome<Self, Self::Error> {
-        match request.headers().get_one(auth::AUTHORIZATION) {
-            None => Outcome::Error((Status::Forbidden, auth::AuthenticationError::Missing)),
-            Some(value) => match auth::Claims::from_authorization(value) {
-                Err(e) => Outcome::Error((Status::Forbidden, e)),
-                Ok(claims) => Outcome::Success(claims),
-            },
-        }
-    }
-}
-
 #[launch]
 fn rocket() -> _ {
     dotenv().ok();
@@ -34,5 +15,23 @@ fn rocket() -> _ {
     rocket::build()
         .attach(db::UserDb::init())
         .attach(migrations)
-        .mount("/", routes![api::me_show, api::groups_list,])
+        .mount(
+            "/",
+            routes![
+                api::me_show,
+                api::me_provision,
+                api::users_list,
+                api::users_add,
+                api::user_show,
+                api::user_update,
+                api::mentions_resolve,
+                api::groups_list,
+                api::groups_add,
+                api::groups_remove,
+                api::group_show,
+                api::group_members_list,
+                api::group_member_add,
+                api::group_member_remove,
+            ],
+        )
 }